@@ -0,0 +1,124 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::components::{Memory, MemoryError};
+
+/// Something that answers physical loads and stores: RAM, ROM or a memory-mapped device.
+/// Addresses are offsets from where it's mapped and sizes are 1, 2, 4 or 8 bytes. Loads take
+/// `&mut self` because reading a device register can have side effects.
+pub trait Bus {
+    /// Reads `size` bytes at `offset`, zero extended.
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError>;
+    /// Writes the low `size` bytes of `value` at `offset`.
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError>;
+}
+
+impl Bus for Memory {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        let offset = offset as usize;
+        match size {
+            1 => self.read_byte(offset, false),
+            2 => self.read_half_word(offset, false),
+            4 => self.read_word(offset),
+            _ => self.read_double_word(offset),
+        }
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        let offset = offset as usize;
+        match size {
+            1 => self.write_byte(offset, value),
+            2 => self.write_half_word(offset, value),
+            4 => self.write_word(offset, value),
+            _ => self.write_double_word(offset, value),
+        }
+    }
+}
+
+/// Memory that can be read and executed, but not written.
+pub struct Rom {
+    pub memory: Memory,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { memory: Memory { data } }
+    }
+}
+
+impl Bus for Rom {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        self.memory.load(offset, size)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, _value: u64) -> Result<(), MemoryError> {
+        Err(MemoryError::ReadOnly { address: offset as usize })
+    }
+}
+
+/// A device that's shared, so whoever mapped it can still look at it.
+impl<T: Bus> Bus for Rc<RefCell<T>> {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        self.borrow_mut().load(offset, size)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        self.borrow_mut().store(offset, size, value)
+    }
+}
+
+/// A device mapped at `base..base + size`.
+pub struct Region {
+    pub base: u64,
+    pub size: u64,
+    pub device: Box<dyn Bus>,
+}
+
+impl Region {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+}
+
+/// The physical address space besides the CPU's main memory, routing every access to the
+/// device mapped at its address.
+#[derive(Default)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    /// Maps `device` at `base..base + size`.
+    ///
+    /// Panics when the range overlaps a device that's already mapped.
+    pub fn map(&mut self, base: u64, size: u64, device: impl Bus + 'static) {
+        let end = base.checked_add(size).expect("device range overflows the address space");
+        if let Some(region) = self.regions.iter().find(|region| base < region.base + region.size && region.base < end) {
+            panic!("device at {:#x}..{:#x} overlaps the one at {:#x}..{:#x}", base, end, region.base, region.base + region.size);
+        }
+
+        self.regions.push(Region { base, size, device: Box::new(device) });
+    }
+
+    /// The device at `address` and the offset into it.
+    pub fn find(&mut self, address: u64) -> Option<(&mut (dyn Bus + 'static), u64)> {
+        self.regions.iter_mut()
+            .find(|region| region.contains(address))
+            .map(|region| (region.device.as_mut(), address - region.base))
+    }
+}
+
+impl Bus for MemoryMap {
+    fn load(&mut self, address: u64, size: u64) -> Result<u64, MemoryError> {
+        match self.find(address) {
+            Some((device, offset)) => device.load(offset, size),
+            None => Err(MemoryError::Unmapped { address: address as usize }),
+        }
+    }
+
+    fn store(&mut self, address: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        match self.find(address) {
+            Some((device, offset)) => device.store(offset, size, value),
+            None => Err(MemoryError::Unmapped { address: address as usize }),
+        }
+    }
+}
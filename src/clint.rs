@@ -0,0 +1,114 @@
+use std::time::Instant;
+
+use crate::{bus::Bus, components::MemoryError, csr::{MIP_MSIP, MIP_MTIP}};
+
+/// Where the CLINT usually lives, QEMU virt and SiFive cores included.
+pub const CLINT_BASE: u64 = 0x0200_0000;
+pub const CLINT_SIZE: u64 = 0x10000;
+
+// Register offsets for hart 0, the only one
+const MSIP: u64 = 0x0000;
+const MTIMECMP: u64 = 0x4000;
+const MTIME: u64 = 0xBFF8;
+
+/// What drives `mtime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timebase {
+    /// One tick per cycle, so runs are reproducible.
+    Instructions,
+    /// The host's clock, with `frequency` ticks per second.
+    WallClock { frequency: u64 },
+}
+
+/// The core-local interruptor: the machine timer (`mtime` and `mtimecmp`) and the machine
+/// software interrupt (`msip`) of a single hart.
+#[derive(Debug)]
+pub struct Clint {
+    pub msip: bool,
+    /// The timer interrupt is pending while `mtime >= mtimecmp`.
+    pub mtimecmp: u64,
+    pub timebase: Timebase,
+    /// `mtime` for the instruction timebase, the offset from the host clock otherwise.
+    ticks: u64,
+    start: Instant,
+}
+
+impl Clint {
+    pub fn new(timebase: Timebase) -> Self {
+        Self {
+            msip: false,
+            mtimecmp: u64::MAX,
+            timebase,
+            ticks: 0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn mtime(&self) -> u64 {
+        match self.timebase {
+            Timebase::Instructions => self.ticks,
+            Timebase::WallClock { frequency } => self.ticks.wrapping_add(self.elapsed(frequency)),
+        }
+    }
+
+    pub fn set_mtime(&mut self, value: u64) {
+        self.ticks = match self.timebase {
+            Timebase::Instructions => value,
+            Timebase::WallClock { frequency } => value.wrapping_sub(self.elapsed(frequency)),
+        };
+    }
+
+    /// Advances the instruction timebase, called once per cycle.
+    pub fn tick(&mut self) {
+        if self.timebase == Timebase::Instructions {
+            self.ticks = self.ticks.wrapping_add(1);
+        }
+    }
+
+    /// The MSIP and MTIP bits of `mip`.
+    pub fn interrupts(&self) -> u64 {
+        let software = if self.msip { MIP_MSIP } else { 0 };
+        let timer = if self.mtime() >= self.mtimecmp { MIP_MTIP } else { 0 };
+        software | timer
+    }
+
+    fn elapsed(&self, frequency: u64) -> u64 {
+        (self.start.elapsed().as_nanos() * frequency as u128 / 1_000_000_000) as u64
+    }
+}
+
+impl Bus for Clint {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        // RV32 reads the 64 bit registers in two halves
+        let register = offset & !0b111;
+        let value = match register {
+            MSIP => self.msip as u64,
+            MTIMECMP => self.mtimecmp,
+            MTIME => self.mtime(),
+            _ => 0,
+        };
+
+        let shifted = value >> (8 * (offset - register));
+        Ok(shifted & (u64::MAX >> (64 - 8 * size)))
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        let register = offset & !0b111;
+        let shift = 8 * (offset - register);
+        let mask = (u64::MAX >> (64 - 8 * size)) << shift;
+        let merge = |old: u64| (old & !mask) | ((value << shift) & mask);
+
+        match register {
+            // Only bit 0 of the 32 bit msip register exists
+            MSIP if offset == MSIP => self.msip = value & 1 == 1,
+            MTIMECMP => self.mtimecmp = merge(self.mtimecmp),
+            MTIME => {
+                let mtime = merge(self.mtime());
+                self.set_mtime(mtime);
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
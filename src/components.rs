@@ -1,4 +1,20 @@
-use crate::stages::{decode_instruction, execute, fetch_instruction, DecodeError, DecodedInstr, ExecuteError, MemSize};
+use crate::csr::{CsrFile, Privilege, MIP_MSIP, MIP_MTIP, MSTATUS_FS, MSTATUS_FS_DIRTY, MSTATUS_MIE, MSTATUS_MPIE, MSTATUS_MPP, MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SIE, MSTATUS_SPIE, MSTATUS_SPP, MSTATUS_SUM, MSTATUS_TSR, MSTATUS_TVM, MSTATUS_TW, MSTATUS_VS, MSTATUS_VS_DIRTY, SATP};
+use crate::mmu::{AccessContext, AccessType, Mmu, PAGE_SIZE};
+use crate::compressed::is_compressed;
+use crate::stages::{decode_compressed, decode_instruction, execute, execute_fp, execute_rv32, is_floating_point, AtomicAccess, AtomicOp, CsrOp, DecodeError, DecodedInstr, ExecuteError, FpOperands, MemSize, SystemOp, Xlen};
+use crate::trap::{Exception, Interrupt, Trap};
+use crate::fpu;
+use crate::isa::{required_extension, Extension, Isa};
+use crate::vector::{execute_vector, VectorEffect, VectorRegisters, VectorState};
+use crate::halt::{HaltConfig, SYS_EXIT};
+use crate::htif::Htif;
+use crate::bus::{Bus, MemoryMap};
+use crate::clint::{Clint, CLINT_SIZE};
+use crate::plic::{Plic, PLIC_SIZE};
+use crate::virtio::{VirtioBlock, VIRTIO_SIZE};
+use std::{cell::RefCell, rc::Rc};
+use crate::semihosting::{Semihosting, EBREAK, ENTRY, EXIT};
+use crate::syscall::{SyscallArgs, SyscallResult, Syscalls};
 
 #[derive(Default)]
 pub struct ProgramCounter {
@@ -7,7 +23,12 @@ pub struct ProgramCounter {
 
 impl ProgramCounter {
     pub fn increment(&mut self) {
-        self.address += 4;
+        self.advance(4);
+    }
+
+    /// Steps over an instruction of `length` bytes, 2 for compressed instructions.
+    pub fn advance(&mut self, length: u64) {
+        self.address += length;
     }
 
     pub fn set(&mut self, value: u64) {
@@ -19,6 +40,10 @@ impl ProgramCounter {
 pub enum MemoryError {
     #[error("Memory address out of bounds. Requested address: {address}, max: {max}")]
     OutOfBounds{address: usize, max: usize},
+    #[error("Write to read-only memory. Requested address: {address}")]
+    ReadOnly{address: usize},
+    #[error("Nothing is mapped at address {address}")]
+    Unmapped{address: usize},
 }
 
 #[derive(Default)]
@@ -118,55 +143,299 @@ pub enum CPUError {
     #[error("Too little memory to load ELF file")]
     ElfTooLittleMemoryError,
 
-    #[error("Fetch error at PC={pc}: {source}")]
-    FetchError {
-        source: MemoryError,
+    #[error("Unhandled trap at PC={pc}: {trap:?}")]
+    Trap {
+        trap: Trap,
         pc: u64
     },
 
-    #[error("Memory error a PC={pc}: {source}")]
-    MemoryError {
-        source: MemoryError,
-        pc: u64
+    /// The guest asked to stop through one of the protocols in `HaltConfig`.
+    #[error("Program exited with code {code}")]
+    Exit {
+        code: i64,
     },
+}
 
-    #[error("Decode error at PC={pc}: {source}")]
-    DecodeError {
-        source: DecodeError,
-        pc: u64,
-    },
+/// Main memory for Linux and newlib programs, which need room for their heap and stack.
+pub const HOSTED_MEMORY: usize = 64 * 1024 * 1024;
 
-    #[error("Execute error at PC={pc}: {source}")]
-    ExecuteError {
-        source: ExecuteError,
-        pc: u64
-    },
+/// `size` cut short so memory, which starts at 0, ends before the lowest of `devices`.
+pub fn memory_below(size: usize, devices: &[u64]) -> usize {
+    devices.iter().fold(size, |size, base| size.min(*base as usize))
 }
 
 pub struct CPU {
     pub pc: ProgramCounter,
+    /// Main memory, mapped from physical address 0.
     pub mem: Memory,
+    /// ROM and memory-mapped devices. They take precedence over main memory, and unlike it
+    /// aren't used for page table walks or by the host syscalls.
+    pub bus: MemoryMap,
+    /// The core-local interruptor, it's mapped on `bus` as well. It drives the machine timer
+    /// and software interrupts, and the `time` CSR reads its `mtime`.
+    pub clint: Option<Rc<RefCell<Clint>>>,
+    /// The platform-level interrupt controller, also mapped on `bus`. It drives the machine
+    /// and supervisor external interrupts, a guest can't set SEIP itself while it's there.
+    pub plic: Option<Rc<RefCell<Plic>>>,
+    /// A virtio block device, also mapped on `bus`. Its virtqueues live in main memory.
+    pub virtio: Option<Rc<RefCell<VirtioBlock>>>,
+    /// In RV32 mode the registers hold their 32 bit value sign extended to 64 bits.
     pub regs: [u64; 32],
+    /// Single precision values are NaN-boxed in the upper 32 bits.
+    pub fregs: [u64; 32],
+    pub vregs: VectorRegisters,
+    pub xlen: Xlen,
+    /// Optional extensions that are switched on, the others decode as illegal instructions.
+    pub isa: Isa,
+    pub csr: CsrFile,
+    pub privilege: Privilege,
+    pub mmu: Mmu,
+    /// The address reserved by the last LR, SC only succeeds on a matching address.
+    pub reservation: Option<u64>,
 
     pub last_store: Option<(u64, u64)>,
+
+    pub halt: HaltConfig,
+    /// The HTIF device, `load_elf` sets it up when the program has a `tohost` symbol.
+    pub htif: Option<Htif>,
+    /// Services ECALLs on the host instead of trapping, set it before `load_elf`.
+    pub syscalls: Syscalls,
+    /// Services the semihosting EBREAK sequence on the host when set.
+    pub semihosting: Option<Semihosting>,
 }
 
 impl CPU {
     pub fn new(mem_size: usize) -> Self {
+        Self::with_xlen(mem_size, Xlen::Rv64)
+    }
+
+    pub fn with_xlen(mem_size: usize, xlen: Xlen) -> Self {
+        Self::with_isa(mem_size, Isa::new(xlen))
+    }
+
+    /// Creates a CPU for a parsed ISA string, e.g. `"rv64imac_zba_zbb".parse()`.
+    pub fn with_isa(mem_size: usize, isa: Isa) -> Self {
+        let mut csr = CsrFile::new();
+        csr.extensions = isa.misa_extensions();
+
         CPU {
             pc: ProgramCounter::default(),
             mem: Memory::new(mem_size),
+            bus: MemoryMap::default(),
+            clint: None,
+            plic: None,
+            virtio: None,
             regs: [0; 32],
+            fregs: [0; 32],
+            vregs: VectorRegisters::default(),
+            xlen: isa.xlen,
+            isa,
+            csr,
+            privilege: Privilege::Machine,
+            mmu: Mmu::new(),
+            reservation: None,
             last_store: None,
+            halt: HaltConfig::default(),
+            htif: None,
+            syscalls: Syscalls::None,
+            semihosting: None,
         }
     }
 
+    /// Changes the width of the vector registers, which clears them.
+    pub fn set_vlen(&mut self, vlen: usize) {
+        self.vregs = VectorRegisters::new(vlen);
+        self.csr.vlenb = self.vregs.vlenb() as u64;
+    }
+
+    /// Whether `base..base + size` overlaps main memory.
+    pub fn overlaps_memory(&self, base: u64, size: u64) -> bool {
+        size > 0 && base < self.mem.data.len() as u64
+    }
+
+    /// Maps `device` on `bus` at `base..base + size`.
+    ///
+    /// Panics when the range overlaps main memory or a device that's already mapped, it would
+    /// hide what's there.
+    pub fn map(&mut self, base: u64, size: u64, device: impl Bus + 'static) {
+        if self.overlaps_memory(base, size) {
+            panic!("device at {:#x}..{:#x} overlaps main memory at 0x0..{:#x}", base, base + size, self.mem.data.len());
+        }
+
+        self.bus.map(base, size, device);
+    }
+
+    /// Maps a CLINT at `base` and connects its interrupts and timer.
+    pub fn add_clint(&mut self, base: u64, clint: Clint) {
+        let clint = Rc::new(RefCell::new(clint));
+        self.map(base, CLINT_SIZE, clint.clone());
+        self.clint = Some(clint);
+    }
+
+    /// Maps a PLIC at `base` and connects it to the external interrupts.
+    pub fn add_plic(&mut self, base: u64, plic: Plic) {
+        let plic = Rc::new(RefCell::new(plic));
+        self.map(base, PLIC_SIZE, plic.clone());
+        self.plic = Some(plic);
+    }
+
+    /// Maps a virtio block device at `base`.
+    pub fn add_virtio_block(&mut self, base: u64, block: VirtioBlock) {
+        let block = Rc::new(RefCell::new(block));
+        self.map(base, VIRTIO_SIZE, block.clone());
+        self.virtio = Some(block);
+    }
+
+    /// Runs a single instruction, or enters the handler of a pending interrupt instead.
+    /// Exceptions are delivered to the guest trap handler, only when no handler is installed
+    /// (`mtvec` is zero) they are returned as `CPUError::Trap`.
     pub fn cycle(&mut self) -> Result<(), CPUError> {
         self.last_store = None;
-        let instruction = fetch_instruction(&self.pc, &self.mem)
-            .map_err(|e| CPUError::FetchError { source: e, pc: self.pc.address })?;
-        let decoded_instruction = decode_instruction(instruction as u32)
-            .map_err(|e| CPUError::DecodeError { source: e, pc: self.pc.address })?;
+        self.csr.tick();
+
+        if let Some(clint) = &self.clint {
+            let mut clint = clint.borrow_mut();
+            clint.tick();
+            self.csr.time = clint.mtime();
+            self.csr.mip = (self.csr.mip & !(MIP_MSIP | MIP_MTIP)) | clint.interrupts();
+        }
+
+        if let Some(plic) = &self.plic {
+            let mut plic = plic.borrow_mut();
+            plic.update();
+            self.csr.external = plic.interrupts();
+        }
+
+        let result = match self.pending_interrupt() {
+            Some(interrupt) => Err(CPUError::Trap { trap: Trap::Interrupt(interrupt), pc: self.pc.address }),
+            None => self.step().map(|_| self.csr.retire()),
+        };
+
+        match result {
+            Err(CPUError::Trap { trap, .. }) => match self.take_trap(trap) {
+                Err(CPUError::Trap { trap, pc }) => Err(self.unhandled(trap, pc)),
+                result => result,
+            },
+            result => result,
+        }
+    }
+
+    /// The highest priority interrupt that's pending, enabled in `mie` and not masked by the
+    /// privilege level. Interrupts for a higher level than the current one are always taken,
+    /// ones for the current level only with its xIE bit in `mstatus`, and delegated ones never
+    /// in M-mode.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.csr.pending() & self.csr.mie;
+        if pending == 0 {
+            return None;
+        }
+
+        let mstatus = self.csr.mstatus;
+        let machine = self.privilege < Privilege::Machine || mstatus & MSTATUS_MIE != 0;
+        let supervisor = self.privilege < Privilege::Supervisor
+            || (self.privilege == Privilege::Supervisor && mstatus & MSTATUS_SIE != 0);
+
+        let enabled = match (machine, supervisor) {
+            (true, true) => pending,
+            (true, false) => pending & !self.csr.mideleg,
+            (false, true) => pending & self.csr.mideleg,
+            (false, false) => 0,
+        };
+
+        [
+            Interrupt::MachineExternal,
+            Interrupt::MachineSoftware,
+            Interrupt::MachineTimer,
+            Interrupt::SupervisorExternal,
+            Interrupt::SupervisorSoftware,
+            Interrupt::SupervisorTimer,
+        ].into_iter().find(|interrupt| (enabled >> interrupt.code()) & 1 == 1)
+    }
+
+    /// Services an ECALL on the host, `false` when it should trap instead.
+    fn syscall(&mut self) -> Result<bool, CPUError> {
+        let call = SyscallArgs::new(&self.regs, self.xlen);
+        let result = match &mut self.syscalls {
+            Syscalls::None => return Ok(false),
+            Syscalls::Linux(linux) => linux.syscall(&call, &mut self.mem, self.xlen),
+            Syscalls::Newlib(newlib) => newlib.syscall(&call, &mut self.mem, self.xlen),
+        };
+
+        self.host_call_result(result)
+    }
+
+    /// Services a semihosting call if the EBREAK at `pc` is one, `false` when it should trap
+    /// instead. The three instructions of the sequence have to be on the same page.
+    fn semihost(&mut self, pc: u64) -> Result<bool, CPUError> {
+        if self.semihosting.is_none() || pc % PAGE_SIZE < 4 || pc % PAGE_SIZE + 8 > PAGE_SIZE {
+            return Ok(false);
+        }
+
+        // The EBREAK was just fetched, so its page translates
+        let physical = self.translate(pc, AccessType::Fetch)?;
+        let sequence = [physical - 4, physical, physical + 4].map(|address| self.read_physical(address, &MemSize::Word).ok());
+        if sequence != [Some(ENTRY as u64), Some(EBREAK as u64), Some(EXIT as u64)] {
+            return Ok(false);
+        }
+
+        let Some(semihosting) = &mut self.semihosting else {
+            return Ok(false);
+        };
+        let call = SyscallArgs::new(&self.regs, self.xlen);
+        let result = semihosting.call(call.args[0], call.args[1], &mut self.mem, self.xlen);
+        self.host_call_result(result)
+    }
+
+    /// Puts the result of a call serviced on the host in a0, or stops with its exit code.
+    fn host_call_result(&mut self, result: SyscallResult) -> Result<bool, CPUError> {
+        match result {
+            SyscallResult::Return(value) => self.regs[10] = match self.xlen {
+                Xlen::Rv32 => value as i32 as u64,
+                Xlen::Rv64 => value as u64,
+            },
+            SyscallResult::Exit(code) => return Err(CPUError::Exit { code }),
+        }
+
+        Ok(true)
+    }
+
+    /// Without a handler an ECALL or EBREAK can be the guest asking to stop.
+    fn unhandled(&self, trap: Trap, pc: u64) -> CPUError {
+        let code = self.regs[10] as i64;
+
+        match trap {
+            Trap::Exception { cause: Exception::EnvironmentCallFromUMode | Exception::EnvironmentCallFromSMode | Exception::EnvironmentCallFromMMode, .. }
+                if self.halt.ecall_exit && self.regs[17] == SYS_EXIT => CPUError::Exit { code },
+            Trap::Exception { cause: Exception::Breakpoint, .. } if self.halt.ebreak => CPUError::Exit { code },
+            _ => CPUError::Trap { trap, pc },
+        }
+    }
+
+    fn exception(&self, cause: Exception, tval: u64) -> CPUError {
+        CPUError::Trap { trap: Trap::exception(cause, tval), pc: self.pc.address }
+    }
+
+    fn step(&mut self) -> Result<(), CPUError> {
+        let pc = self.pc.address;
+
+        let instruction = self.fetch()?;
+        let length = if is_compressed(instruction) { 2 } else { 4 };
+        if length == 2 && !self.isa.has(Extension::C) {
+            return Err(self.exception(Exception::IllegalInstruction, instruction as u64));
+        }
+
+        let decoded_instruction = match length {
+            2 => decode_compressed(instruction as u16, self.xlen),
+            _ => decode_instruction(instruction),
+        };
+        let decoded_instruction = match decoded_instruction {
+            Ok(decoded) => decoded,
+            Err(DecodeError::UnknownOpcode(_) | DecodeError::IllegalCompressed(_)) => return Err(self.exception(Exception::IllegalInstruction, instruction as u64)),
+        };
+
+        if required_extension(&decoded_instruction).is_some_and(|extension| !self.isa.has(extension)) {
+            return Err(self.exception(Exception::IllegalInstruction, instruction as u64));
+        }
 
         let rs1_val = match &decoded_instruction {
             DecodedInstr::R(r) => self.regs[r.rs1 as usize],
@@ -175,6 +444,8 @@ impl CPU {
             DecodedInstr::B(b) => self.regs[b.rs1 as usize],
             DecodedInstr::U(_) => 0,
             DecodedInstr::J(_) => 0,
+            DecodedInstr::R4(r) => self.regs[r.rs1 as usize],
+            DecodedInstr::V(v) => self.regs[v.rs1 as usize],
         } as i64;
 
         let rs2_val = match &decoded_instruction {
@@ -184,49 +455,531 @@ impl CPU {
             DecodedInstr::B(b) => self.regs[b.rs2 as usize],
             DecodedInstr::U(_) => 0,
             DecodedInstr::J(_) => 0,
+            DecodedInstr::R4(_) => 0,
+            DecodedInstr::V(v) => self.regs[v.rs2 as usize],
         } as i64;
 
-        let execute_result = execute(&decoded_instruction, rs1_val, rs2_val, self.pc.address)
-            .map_err(|e| CPUError::ExecuteError { source: e, pc: self.pc.address })?;
+        let floating_point = is_floating_point(&decoded_instruction);
+        if floating_point && self.csr.mstatus & MSTATUS_FS == 0 {
+            return Err(self.exception(Exception::IllegalInstruction, instruction as u64));
+        }
+
+        if matches!(decoded_instruction, DecodedInstr::V(_)) && self.csr.mstatus & MSTATUS_VS == 0 {
+            return Err(self.exception(Exception::IllegalInstruction, instruction as u64));
+        }
+
+        let mut execute_result = match (self.xlen, &decoded_instruction) {
+            (_, DecodedInstr::V(v)) => {
+                let state = VectorState { registers: &self.vregs, vl: self.csr.vl, vtype: self.csr.vtype, vstart: self.csr.vstart, xlen: self.xlen };
+                execute_vector(v, rs1_val as u64, rs2_val as u64, &state)
+                    .ok_or(ExecuteError::UnimplementedInstruction { instr_type: "V".into(), instruction: decoded_instruction.clone() })
+            },
+            _ if floating_point => execute_fp(&decoded_instruction, &self.fp_operands(&decoded_instruction), self.csr.frm as u8, self.xlen),
+            (Xlen::Rv32, _) => execute_rv32(&decoded_instruction, rs1_val, rs2_val, pc),
+            (Xlen::Rv64, _) => execute(&decoded_instruction, rs1_val, rs2_val, pc),
+        }.map_err(|_| self.exception(Exception::IllegalInstruction, instruction as u64))?;
+
+        // C.JAL and C.JALR link to the instruction 2 bytes further instead of 4
+        if length == 2 && execute_result.branch_addr.is_some() {
+            if let Some(write_back) = &mut execute_result.write_back {
+                write_back.value = match self.xlen {
+                    Xlen::Rv32 => pc.wrapping_add(2) as i32 as u64,
+                    Xlen::Rv64 => pc.wrapping_add(2),
+                };
+            }
+        }
+
+        let mut next_pc = execute_result.branch_addr;
+
+        if let Some(system) = execute_result.system {
+            let illegal = self.exception(Exception::IllegalInstruction, instruction as u64);
+            let mstatus = self.csr.mstatus;
+
+            match system {
+                SystemOp::Ecall => if !self.syscall()? {
+                    return Err(self.exception(match self.privilege {
+                        Privilege::User => Exception::EnvironmentCallFromUMode,
+                        Privilege::Supervisor => Exception::EnvironmentCallFromSMode,
+                        Privilege::Machine => Exception::EnvironmentCallFromMMode,
+                    }, 0));
+                },
+                SystemOp::Ebreak => if !self.semihost(pc)? {
+                    return Err(self.exception(Exception::Breakpoint, pc));
+                },
+                SystemOp::Sret => match self.privilege {
+                    Privilege::User => return Err(illegal),
+                    Privilege::Supervisor if mstatus & MSTATUS_TSR != 0 => return Err(illegal),
+                    _ => next_pc = Some(self.sret()),
+                },
+                SystemOp::Mret => match self.privilege {
+                    Privilege::Machine => next_pc = Some(self.mret()),
+                    _ => return Err(illegal),
+                },
+                SystemOp::Wfi => match self.privilege {
+                    Privilege::User => return Err(illegal),
+                    Privilege::Supervisor if mstatus & MSTATUS_TW != 0 => return Err(illegal),
+                    // WFI may complete right away, a pending interrupt is taken before the
+                    // next instruction anyway
+                    _ => {},
+                },
+                SystemOp::SfenceVma { address } => match self.privilege {
+                    Privilege::User => return Err(illegal),
+                    Privilege::Supervisor if mstatus & MSTATUS_TVM != 0 => return Err(illegal),
+                    _ => self.mmu.flush(address),
+                },
+                // Instructions are fetched and decoded from memory every cycle, so there's no
+                // stale copy to throw away
+                SystemOp::FenceI => {},
+            }
+        }
+
+        // With the C extension instructions only have to be aligned to 2 bytes (IALIGN=16)
+        if let Some(target) = next_pc {
+            let alignment = if self.isa.has(Extension::C) { 2 } else { 4 };
+            if !target.is_multiple_of(alignment) {
+                return Err(self.exception(Exception::InstructionAddressMisaligned, target));
+            }
+        }
 
         if let Some(read_mem) = execute_result.read_mem {
-            let data = match read_mem.size {
-                MemSize::Byte => self.mem.read_byte(read_mem.address as usize, read_mem.signed),
-                MemSize::Half => self.mem.read_half_word(read_mem.address as usize, read_mem.signed),
-                MemSize::Word => self.mem.read_word(read_mem.address as usize),
-                MemSize::Double => self.mem.read_double_word(read_mem.address as usize),
-            }.map_err(|e| CPUError::MemoryError { source: e, pc: self.pc.address })?;
-
-            if read_mem.rd != 0 {
+            let data = self.load(read_mem.address, &read_mem.size)?;
+            let data = match (&read_mem.size, read_mem.signed) {
+                (MemSize::Byte, true) => data as i8 as u64,
+                (MemSize::Half, true) => data as i16 as u64,
+                (MemSize::Word, true) => data as i32 as u64,
+                _ => data,
+            };
+
+            if read_mem.fp {
+                self.fregs[read_mem.rd as usize] = match read_mem.size {
+                    MemSize::Word => fpu::nan_box(data),
+                    _ => data,
+                };
+                self.csr.mstatus |= MSTATUS_FS_DIRTY;
+            } else if read_mem.rd != 0 {
                 self.regs[read_mem.rd as usize] = data;
             }
         }
 
         if let Some(write_mem) = execute_result.write_mem {
-            match write_mem.size {
-                MemSize::Byte => self.mem.write_byte(write_mem.address as usize, write_mem.data),
-                MemSize::Half => self.mem.write_half_word(write_mem.address as usize, write_mem.data),
-                MemSize::Word => self.mem.write_word(write_mem.address as usize, write_mem.data),
-                MemSize::Double => self.mem.write_double_word(write_mem.address as usize, write_mem.data),
-            }.map_err(|e| CPUError::MemoryError { source: e, pc: self.pc.address })?;
-
+            self.store(write_mem.address, &write_mem.size, write_mem.data)?;
             self.last_store = Some((write_mem.address, write_mem.data));
         }
 
+        if let Some(atomic) = execute_result.atomic {
+            self.atomic(atomic)?;
+        }
+
+        if let Some(vector) = execute_result.vector {
+            self.vector(vector)?;
+        }
+
+        if let Some(csr) = execute_result.csr {
+            self.csr.check_access(csr.csr, self.privilege)
+                .map_err(|_| self.exception(Exception::IllegalInstruction, instruction as u64))?;
+
+            let old = self.csr.read(csr.csr, self.xlen)
+                .map_err(|_| self.exception(Exception::IllegalInstruction, instruction as u64))?;
+
+            if csr.write {
+                let current = || self.csr.read_for_update(csr.csr, self.xlen)
+                    .map_err(|_| self.exception(Exception::IllegalInstruction, instruction as u64));
+                let new = match csr.op {
+                    CsrOp::Write => csr.value,
+                    CsrOp::Set => current()? | csr.value,
+                    CsrOp::Clear => current()? & !csr.value,
+                };
+
+                self.csr.write(csr.csr, new, self.xlen)
+                    .map_err(|_| self.exception(Exception::IllegalInstruction, instruction as u64))?;
+
+                if csr.csr == SATP {
+                    self.mmu.flush(None);
+                }
+            }
+
+            if csr.rd != 0 {
+                self.regs[csr.rd as usize] = match self.xlen {
+                    Xlen::Rv32 => old as i32 as u64,
+                    Xlen::Rv64 => old,
+                };
+            }
+        }
+
         if let Some(write_back) = execute_result.write_back {
             if write_back.rd != 0 {
                 self.regs[write_back.rd as usize] = write_back.value;
             }
         }
 
-        if let Some(branch_addr) = execute_result.branch_addr {
-            self.pc.set(branch_addr);
+        if let Some(write_back) = execute_result.fp_write_back {
+            self.fregs[write_back.rd as usize] = write_back.value;
+            self.csr.mstatus |= MSTATUS_FS_DIRTY;
+        }
+
+        if execute_result.fflags != 0 {
+            self.csr.fflags |= execute_result.fflags as u64;
+            self.csr.mstatus |= MSTATUS_FS_DIRTY;
+        }
+
+        if let Some(next_pc) = next_pc {
+            self.pc.set(next_pc);
+        } else {
+            self.pc.advance(length);
+
+            if self.xlen == Xlen::Rv32 {
+                self.pc.address &= 0xFFFF_FFFF;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the effect of a vector instruction. A faulting element access leaves its index in
+    /// `vstart`, so the instruction resumes there once the trap handler returns.
+    fn vector(&mut self, effect: VectorEffect) -> Result<(), CPUError> {
+        match effect {
+            VectorEffect::Configure { vl, vtype } => {
+                self.csr.vl = vl;
+                self.csr.vtype = vtype;
+            },
+            VectorEffect::Write { offset, bytes } => {
+                self.vregs.data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            },
+            VectorEffect::Load(elements) => for element in elements {
+                let data = self.load(element.address, &element.size)
+                    .inspect_err(|_| self.csr.vstart = element.index)?;
+                let bytes = element.size.bytes() as usize;
+                self.vregs.data[element.offset..element.offset + bytes].copy_from_slice(&data.to_le_bytes()[..bytes]);
+            },
+            VectorEffect::Store(elements) => for element in elements {
+                self.store(element.address, &element.size, element.data)
+                    .inspect_err(|_| self.csr.vstart = element.index)?;
+            },
+        }
+
+        self.csr.vstart = 0;
+        self.csr.mstatus |= MSTATUS_VS_DIRTY;
+        Ok(())
+    }
+
+    /// Collects the registers a floating point instruction reads, from both register files.
+    fn fp_operands(&self, instruction: &DecodedInstr) -> FpOperands {
+        match instruction {
+            DecodedInstr::I(i) => FpOperands { x_rs1: self.regs[i.rs1 as usize], ..Default::default() },
+            DecodedInstr::S(s) => FpOperands {
+                x_rs1: self.regs[s.rs1 as usize],
+                f_rs2: self.fregs[s.rs2 as usize],
+                ..Default::default()
+            },
+            DecodedInstr::R(r) => FpOperands {
+                x_rs1: self.regs[r.rs1 as usize],
+                f_rs1: self.fregs[r.rs1 as usize],
+                f_rs2: self.fregs[r.rs2 as usize],
+                ..Default::default()
+            },
+            DecodedInstr::R4(r) => FpOperands {
+                x_rs1: 0,
+                f_rs1: self.fregs[r.rs1 as usize],
+                f_rs2: self.fregs[r.rs2 as usize],
+                f_rs3: self.fregs[r.rs3 as usize],
+            },
+            _ => FpOperands::default(),
+        }
+    }
+
+    /// Fetches the instruction at the PC. A 32 bit instruction in the last halfword of a page
+    /// is translated in two parts, since its upper half lives on the next page.
+    fn fetch(&mut self) -> Result<u32, CPUError> {
+        let pc = self.pc.address;
+        let physical = self.translate(pc, AccessType::Fetch)?;
+
+        let low = self.read_physical(physical, &MemSize::Half)
+            .map_err(|_| self.exception(Exception::InstructionAccessFault, pc))? as u32;
+        if is_compressed(low) {
+            return Ok(low);
+        }
+
+        let physical = match pc % PAGE_SIZE {
+            offset if offset == PAGE_SIZE - 2 => self.translate(pc + 2, AccessType::Fetch)?,
+            _ => physical + 2,
+        };
+        let high = self.read_physical(physical, &MemSize::Half)
+            .map_err(|_| self.exception(Exception::InstructionAccessFault, pc + 2))? as u32;
+
+        Ok(low | (high << 16))
+    }
+
+    /// Translates a virtual address with the paging mode in `satp`. Loads and stores use the
+    /// privilege in MPP while MPRV is set.
+    fn translate(&mut self, address: u64, access: AccessType) -> Result<u64, CPUError> {
+        let mstatus = self.csr.mstatus;
+        let privilege = match access {
+            AccessType::Load | AccessType::Store if mstatus & MSTATUS_MPRV != 0 =>
+                Privilege::from_bits((mstatus & MSTATUS_MPP) >> 11).unwrap_or(Privilege::User),
+            _ => self.privilege,
+        };
+
+        let context = AccessContext {
+            satp: self.csr.satp,
+            xlen: self.xlen,
+            privilege,
+            sum: mstatus & MSTATUS_SUM != 0,
+            mxr: mstatus & MSTATUS_MXR != 0,
+        };
+
+        self.mmu.translate(&mut self.mem, address, access, &context)
+            .map_err(|cause| self.exception(cause, address))
+    }
+
+    /// Reads from a virtual address, zero extended. Accesses that cross a page boundary are
+    /// split into bytes that are translated one by one.
+    fn load(&mut self, address: u64, size: &MemSize) -> Result<u64, CPUError> {
+        let fault = self.exception(Exception::LoadAccessFault, address);
+
+        if address % PAGE_SIZE + size.bytes() <= PAGE_SIZE {
+            let physical = self.translate(address, AccessType::Load)?;
+            if let Some(htif) = self.htif.as_mut().filter(|htif| htif.fromhost == Some(physical)) {
+                htif.poll(&mut self.mem);
+            }
+            return self.read_physical(physical, size).map_err(|_| fault);
+        }
+
+        let mut data = 0;
+        for i in 0..size.bytes() {
+            let physical = self.translate(address.wrapping_add(i), AccessType::Load)?;
+            data |= self.read_physical(physical, &MemSize::Byte).map_err(|_| self.exception(Exception::LoadAccessFault, address))? << (8 * i);
+        }
+
+        Ok(data)
+    }
+
+    /// Writes to a virtual address. A store that crosses a page boundary translates every byte
+    /// before writing any, so a page fault leaves memory untouched.
+    fn store(&mut self, address: u64, size: &MemSize, data: u64) -> Result<(), CPUError> {
+        if address % PAGE_SIZE + size.bytes() <= PAGE_SIZE {
+            let physical = self.translate(address, AccessType::Store)?;
+            return self.store_physical(address, physical, size, data);
+        }
+
+        let physical = (0..size.bytes())
+            .map(|i| self.translate(address.wrapping_add(i), AccessType::Store))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (i, physical) in physical.into_iter().enumerate() {
+            self.write_physical(physical, &MemSize::Byte, data >> (8 * i))
+                .map_err(|_| self.exception(Exception::StoreAccessFault, address))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes to a physical address for a store, SC or AMO to the virtual `address`. The test
+    /// finisher and HTIF see it, either can end the run, and a notified virtio queue is served.
+    fn store_physical(&mut self, address: u64, physical: u64, size: &MemSize, data: u64) -> Result<(), CPUError> {
+        let mask = u64::MAX >> (64 - 8 * size.bytes());
+        if let Some(code) = self.halt.store(physical, data & mask) {
+            return Err(CPUError::Exit { code });
+        }
+
+        self.write_physical(physical, size, data)
+            .map_err(|_| self.exception(Exception::StoreAccessFault, address))?;
+
+        // On RV32 a command is complete once its upper word is written, the low one goes first
+        let xlen = self.xlen;
+        let last = |tohost: u64| match xlen {
+            Xlen::Rv32 => physical <= tohost + 4 && physical + size.bytes() > tohost + 4,
+            Xlen::Rv64 => physical == tohost,
+        };
+        if let Some(htif) = self.htif.as_mut().filter(|htif| last(htif.tohost)) {
+            if let Some(code) = htif.command(&mut self.mem) {
+                return Err(CPUError::Exit { code });
+            }
+        }
+
+        // Requests are done before the notifying store retires
+        if let Some(virtio) = &self.virtio {
+            virtio.borrow_mut().process(&mut self.mem);
+        }
+
+        Ok(())
+    }
+
+    /// Reads from a physical address, a mapped device or otherwise main memory.
+    fn read_physical(&mut self, address: u64, size: &MemSize) -> Result<u64, MemoryError> {
+        match self.bus.find(address) {
+            Some((device, offset)) => device.load(offset, size.bytes()),
+            None => self.mem.load(address, size.bytes()),
+        }
+    }
+
+    fn write_physical(&mut self, address: u64, size: &MemSize, data: u64) -> Result<(), MemoryError> {
+        match self.bus.find(address) {
+            Some((device, offset)) => device.store(offset, size.bytes(), data),
+            None => self.mem.store(address, size.bytes(), data),
+        }
+    }
+
+    /// Runs LR, SC or an AMO. These have to be naturally aligned, so unlike plain loads and
+    /// stores they never cross a page. AMOs need write permission and report their faults as
+    /// store faults.
+    fn atomic(&mut self, atomic: AtomicAccess) -> Result<(), CPUError> {
+        let address = atomic.address;
+        let sign_extend = |value: u64| match atomic.size {
+            MemSize::Word => value as i32 as u64,
+            _ => value,
+        };
+
+        if !address.is_multiple_of(atomic.size.bytes()) {
+            let cause = match atomic.op {
+                AtomicOp::LoadReserved => Exception::LoadAddressMisaligned,
+                _ => Exception::StoreAddressMisaligned,
+            };
+            return Err(self.exception(cause, address));
+        }
+
+        let result = match atomic.op {
+            AtomicOp::LoadReserved => {
+                let physical = self.translate(address, AccessType::Load)?;
+                let value = self.read_physical(physical, &atomic.size)
+                    .map_err(|_| self.exception(Exception::LoadAccessFault, address))?;
+
+                self.reservation = Some(address);
+                sign_extend(value)
+            },
+            AtomicOp::StoreConditional => {
+                // Any SC gives up the reservation, whether it succeeds or not
+                if self.reservation.take() == Some(address) {
+                    let physical = self.translate(address, AccessType::Store)?;
+                    self.store_physical(address, physical, &atomic.size, atomic.value)?;
+
+                    self.last_store = Some((address, atomic.value));
+                    0
+                } else {
+                    1
+                }
+            },
+            AtomicOp::Amo(op) => {
+                let physical = self.translate(address, AccessType::Store)?;
+                // The test finisher only takes stores, it reads as zero
+                let loaded = match self.read_physical(physical, &atomic.size) {
+                    Err(_) if self.halt.test_finisher == Some(physical) => 0,
+                    loaded => loaded.map_err(|_| self.exception(Exception::StoreAccessFault, address))?,
+                };
+
+                let value = op.apply(loaded, atomic.value, &atomic.size);
+                self.store_physical(address, physical, &atomic.size, value)?;
+
+                self.last_store = Some((address, value));
+                sign_extend(loaded)
+            },
+        };
+
+        if atomic.rd != 0 {
+            self.regs[atomic.rd as usize] = result;
+        }
+
+        Ok(())
+    }
+
+    /// Enters the trap handler. Traps from S or U-mode that are delegated in `medeleg`/`mideleg`
+    /// go to the supervisor handler in `stvec`, everything else to `mtvec`. The interrupted PC
+    /// and interrupt enable are stacked in the xEPC and xPIE/xPP fields. In vectored mode
+    /// interrupts jump to `BASE + 4 * cause`, exceptions always use `BASE`.
+    pub fn take_trap(&mut self, trap: Trap) -> Result<(), CPUError> {
+        // A trap may be a context switch, so the reservation can't survive it
+        self.reservation = None;
+
+        let delegation = if trap.is_interrupt() { self.csr.mideleg } else { self.csr.medeleg };
+        let to_supervisor = self.privilege <= Privilege::Supervisor && (delegation >> trap.code()) & 1 == 1;
+
+        let tvec = if to_supervisor { self.csr.stvec } else { self.csr.mtvec };
+        let base = tvec & !0b11;
+        let vectored = tvec & 0b11 == 1;
+
+        if base == 0 {
+            return Err(CPUError::Trap { trap, pc: self.pc.address });
+        }
+
+        let interrupt_bit = match self.xlen {
+            Xlen::Rv32 => 1 << 31,
+            Xlen::Rv64 => 1 << 63,
+        };
+        let cause = if trap.is_interrupt() { interrupt_bit | trap.code() } else { trap.code() };
+
+        if to_supervisor {
+            self.csr.sepc = self.pc.address;
+            self.csr.scause = cause;
+            self.csr.stval = trap.tval();
+
+            let sie = self.csr.mstatus & MSTATUS_SIE != 0;
+            self.csr.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+            if sie {
+                self.csr.mstatus |= MSTATUS_SPIE;
+            }
+            if self.privilege == Privilege::Supervisor {
+                self.csr.mstatus |= MSTATUS_SPP;
+            }
+
+            self.privilege = Privilege::Supervisor;
+        } else {
+            self.csr.mepc = self.pc.address;
+            self.csr.mcause = cause;
+            self.csr.mtval = trap.tval();
+
+            let mie = self.csr.mstatus & MSTATUS_MIE != 0;
+            self.csr.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+            if mie {
+                self.csr.mstatus |= MSTATUS_MPIE;
+            }
+            self.csr.mstatus |= (self.privilege as u64) << 11;
+
+            self.privilege = Privilege::Machine;
+        }
+
+        if vectored && trap.is_interrupt() {
+            self.pc.set(base + 4 * trap.code());
         } else {
-            self.pc.increment();
+            self.pc.set(base);
         }
 
         Ok(())
     }
+
+    /// Returns from the machine mode trap handler to the mode in MPP, giving back the address
+    /// to continue at.
+    fn mret(&mut self) -> u64 {
+        let mpie = self.csr.mstatus & MSTATUS_MPIE != 0;
+        let mpp = Privilege::from_bits((self.csr.mstatus & MSTATUS_MPP) >> 11).unwrap_or(Privilege::User);
+
+        self.csr.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+        if mpie {
+            self.csr.mstatus |= MSTATUS_MIE;
+        }
+        self.csr.mstatus |= MSTATUS_MPIE;
+        if mpp != Privilege::Machine {
+            self.csr.mstatus &= !MSTATUS_MPRV;
+        }
+
+        self.privilege = mpp;
+        self.csr.epc(self.csr.mepc)
+    }
+
+    /// Returns from the supervisor trap handler to the mode in SPP, giving back the address to
+    /// continue at.
+    fn sret(&mut self) -> u64 {
+        let spie = self.csr.mstatus & MSTATUS_SPIE != 0;
+        let spp = if self.csr.mstatus & MSTATUS_SPP != 0 { Privilege::Supervisor } else { Privilege::User };
+
+        self.csr.mstatus &= !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV);
+        if spie {
+            self.csr.mstatus |= MSTATUS_SIE;
+        }
+        self.csr.mstatus |= MSTATUS_SPIE;
+
+        self.privilege = spp;
+        self.csr.epc(self.csr.sepc)
+    }
 }
 
 // Beautiful code written by Chat, because i couldn't be bothered to write this shit myself.
@@ -237,6 +990,10 @@ impl CPU {
     pub fn load_elf(&mut self, elf_bytes: &[u8]) -> Result<(), CPUError> {
         let elf = Elf::parse(elf_bytes).map_err(|_| CPUError::ElfParseError)?;
 
+        // ELFCLASS32 binaries come from rv32 toolchains
+        self.xlen = if elf.is_64 { Xlen::Rv64 } else { Xlen::Rv32 };
+        self.isa.xlen = self.xlen;
+
         // Laad elk PT_LOAD segment
         for ph in &elf.program_headers {
             if ph.p_type != PT_LOAD {
@@ -265,7 +1022,29 @@ impl CPU {
             }
         }
 
-        self.pc.set(elf.entry as u64);
+        self.pc.set(elf.entry);
+
+        if self.htif.is_none() {
+            self.htif = Htif::from_elf(&elf);
+        }
+
+        match &mut self.syscalls {
+            // A Linux program starts in U-mode with its arguments on the stack and the counters
+            // readable
+            Syscalls::Linux(linux) => {
+                let sp = linux.setup(&elf, &mut self.mem, self.xlen, self.isa.misa_extensions())
+                    .ok_or(CPUError::ElfTooLittleMemoryError)?;
+                self.regs[2] = sp;
+                self.privilege = Privilege::User;
+                self.csr.mcounteren = 0b111;
+                self.csr.scounteren = 0b111;
+            },
+            // A newlib program stays in M-mode, it only needs its arguments on the stack
+            Syscalls::Newlib(newlib) => {
+                self.regs[2] = newlib.setup(&elf, &mut self.mem, self.xlen).ok_or(CPUError::ElfTooLittleMemoryError)?;
+            },
+            Syscalls::None => {},
+        }
 
         Ok(())
     }
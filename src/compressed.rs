@@ -0,0 +1,159 @@
+use crate::{stages::Xlen, util::extract_bits};
+
+/// Instructions whose two lowest bits aren't `0b11` are 16 bits long.
+pub fn is_compressed(instruction: u32) -> bool {
+    instruction & 0b11 != 0b11
+}
+
+/// Expands a 16 bit RVC instruction into the 32 bit instruction it is shorthand for, or `None`
+/// when the encoding is reserved. Quadrant 0 to 2 differ between RV32 and RV64 in a few places
+/// (C.JAL/C.ADDIW, C.FLW/C.LD, C.FSW/C.SD and the upper shift amounts).
+pub fn expand(instruction: u16, xlen: Xlen) -> Option<u32> {
+    let c = instruction as u32;
+    let bits = |high: u8, low: u8| extract_bits(c, high, low);
+    let rv64 = xlen == Xlen::Rv64;
+
+    // Registers x8-x15 in the 3 bit fields of the CIW, CL, CS, CA and CB formats
+    let rd_prime = bits(4, 2) + 8;
+    let rs1_prime = bits(9, 7) + 8;
+    let rd = bits(11, 7);
+    let rs2 = bits(6, 2);
+
+    // The 6 bit signed immediate of C.ADDI, C.LI and friends
+    let imm6 = sign_extend((bits(12, 12) << 5) | bits(6, 2), 6);
+    let shamt = (bits(12, 12) << 5) | bits(6, 2);
+
+    // Offsets of the word and doubleword loads and stores
+    let lw_offset = (bits(12, 10) << 3) | (bits(6, 6) << 2) | (bits(5, 5) << 6);
+    let ld_offset = (bits(12, 10) << 3) | (bits(6, 5) << 6);
+    let lwsp_offset = (bits(12, 12) << 5) | (bits(6, 4) << 2) | (bits(3, 2) << 6);
+    let ldsp_offset = (bits(12, 12) << 5) | (bits(6, 5) << 3) | (bits(4, 2) << 6);
+    let swsp_offset = (bits(12, 9) << 2) | (bits(8, 7) << 6);
+    let sdsp_offset = (bits(12, 10) << 3) | (bits(9, 7) << 6);
+
+    let jump_offset = sign_extend(
+        (bits(12, 12) << 11) | (bits(11, 11) << 4) | (bits(10, 9) << 8) | (bits(8, 8) << 10)
+            | (bits(7, 7) << 6) | (bits(6, 6) << 7) | (bits(5, 3) << 1) | (bits(2, 2) << 5),
+        12,
+    );
+    let branch_offset = sign_extend(
+        (bits(12, 12) << 8) | (bits(11, 10) << 3) | (bits(6, 5) << 6) | (bits(4, 3) << 1) | (bits(2, 2) << 5),
+        9,
+    );
+
+    let expanded = match (bits(1, 0), bits(15, 13)) {
+        (0b00, 0b000) => { // C.ADDI4SPN
+            let imm = (bits(12, 11) << 4) | (bits(10, 7) << 6) | (bits(6, 6) << 2) | (bits(5, 5) << 3);
+            if imm == 0 { return None; }
+            i_type(imm as i32, 2, 0b000, rd_prime, 0b0010011)
+        },
+        (0b00, 0b001) => i_type(ld_offset as i32, rs1_prime, 0b011, rd_prime, 0b0000111), // C.FLD
+        (0b00, 0b010) => i_type(lw_offset as i32, rs1_prime, 0b010, rd_prime, 0b0000011), // C.LW
+        (0b00, 0b011) if rv64 => i_type(ld_offset as i32, rs1_prime, 0b011, rd_prime, 0b0000011), // C.LD
+        (0b00, 0b011) => i_type(lw_offset as i32, rs1_prime, 0b010, rd_prime, 0b0000111), // C.FLW
+        (0b00, 0b101) => s_type(ld_offset as i32, rd_prime, rs1_prime, 0b011, 0b0100111), // C.FSD
+        (0b00, 0b110) => s_type(lw_offset as i32, rd_prime, rs1_prime, 0b010, 0b0100011), // C.SW
+        (0b00, 0b111) if rv64 => s_type(ld_offset as i32, rd_prime, rs1_prime, 0b011, 0b0100011), // C.SD
+        (0b00, 0b111) => s_type(lw_offset as i32, rd_prime, rs1_prime, 0b010, 0b0100111), // C.FSW
+
+        (0b01, 0b000) => i_type(imm6, rd, 0b000, rd, 0b0010011), // C.ADDI (C.NOP for rd = x0)
+        (0b01, 0b001) if rv64 => { // C.ADDIW
+            if rd == 0 { return None; }
+            i_type(imm6, rd, 0b000, rd, 0b0011011)
+        },
+        (0b01, 0b001) => j_type(jump_offset, 1), // C.JAL
+        (0b01, 0b010) => i_type(imm6, 0, 0b000, rd, 0b0010011), // C.LI
+        (0b01, 0b011) if rd == 2 => { // C.ADDI16SP
+            let imm = sign_extend(
+                (bits(12, 12) << 9) | (bits(6, 6) << 4) | (bits(5, 5) << 6) | (bits(4, 3) << 7) | (bits(2, 2) << 5),
+                10,
+            );
+            if imm == 0 { return None; }
+            i_type(imm, 2, 0b000, 2, 0b0010011)
+        },
+        (0b01, 0b011) => { // C.LUI
+            if imm6 == 0 { return None; }
+            u_type(imm6 << 12, rd, 0b0110111)
+        },
+        (0b01, 0b100) => match (bits(11, 10), bits(12, 12), bits(6, 5)) {
+            (0b00 | 0b01, _, _) if !rv64 && shamt >= 32 => return None,
+            (0b00, _, _) => i_type(shamt as i32, rs1_prime, 0b101, rs1_prime, 0b0010011), // C.SRLI
+            (0b01, _, _) => i_type((0x400 | shamt) as i32, rs1_prime, 0b101, rs1_prime, 0b0010011), // C.SRAI
+            (0b10, _, _) => i_type(imm6, rs1_prime, 0b111, rs1_prime, 0b0010011), // C.ANDI
+            (_, 0, 0b00) => r_type(0x20, rd_prime, rs1_prime, 0b000, rs1_prime, 0b0110011), // C.SUB
+            (_, 0, 0b01) => r_type(0x00, rd_prime, rs1_prime, 0b100, rs1_prime, 0b0110011), // C.XOR
+            (_, 0, 0b10) => r_type(0x00, rd_prime, rs1_prime, 0b110, rs1_prime, 0b0110011), // C.OR
+            (_, 0, _) => r_type(0x00, rd_prime, rs1_prime, 0b111, rs1_prime, 0b0110011), // C.AND
+            (_, _, 0b00) if rv64 => r_type(0x20, rd_prime, rs1_prime, 0b000, rs1_prime, 0b0111011), // C.SUBW
+            (_, _, 0b01) if rv64 => r_type(0x00, rd_prime, rs1_prime, 0b000, rs1_prime, 0b0111011), // C.ADDW
+            _ => return None,
+        },
+        (0b01, 0b101) => j_type(jump_offset, 0), // C.J
+        (0b01, 0b110) => b_type(branch_offset, 0, rs1_prime, 0b000), // C.BEQZ
+        (0b01, 0b111) => b_type(branch_offset, 0, rs1_prime, 0b001), // C.BNEZ
+
+        (0b10, 0b000) => { // C.SLLI
+            if !rv64 && shamt >= 32 { return None; }
+            i_type(shamt as i32, rd, 0b001, rd, 0b0010011)
+        },
+        (0b10, 0b001) => i_type(ldsp_offset as i32, 2, 0b011, rd, 0b0000111), // C.FLDSP
+        (0b10, 0b010) => { // C.LWSP
+            if rd == 0 { return None; }
+            i_type(lwsp_offset as i32, 2, 0b010, rd, 0b0000011)
+        },
+        (0b10, 0b011) if rv64 => { // C.LDSP
+            if rd == 0 { return None; }
+            i_type(ldsp_offset as i32, 2, 0b011, rd, 0b0000011)
+        },
+        (0b10, 0b011) => i_type(lwsp_offset as i32, 2, 0b010, rd, 0b0000111), // C.FLWSP
+        (0b10, 0b100) => match (bits(12, 12), rd, rs2) {
+            (0, 0, 0) => return None,
+            (0, _, 0) => i_type(0, rd, 0b000, 0, 0b1100111), // C.JR
+            (0, _, _) => r_type(0x00, rs2, 0, 0b000, rd, 0b0110011), // C.MV
+            (_, 0, 0) => 0x00100073, // C.EBREAK
+            (_, _, 0) => i_type(0, rd, 0b000, 1, 0b1100111), // C.JALR
+            _ => r_type(0x00, rs2, rd, 0b000, rd, 0b0110011), // C.ADD
+        },
+        (0b10, 0b101) => s_type(sdsp_offset as i32, rs2, 2, 0b011, 0b0100111), // C.FSDSP
+        (0b10, 0b110) => s_type(swsp_offset as i32, rs2, 2, 0b010, 0b0100011), // C.SWSP
+        (0b10, 0b111) if rv64 => s_type(sdsp_offset as i32, rs2, 2, 0b011, 0b0100011), // C.SDSP
+        (0b10, 0b111) => s_type(swsp_offset as i32, rs2, 2, 0b010, 0b0100111), // C.FSWSP
+
+        _ => return None,
+    };
+
+    Some(expanded)
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+fn r_type(func7: u32, rs2: u32, rs1: u32, func3: u32, rd: u32, opcode: u32) -> u32 {
+    (func7 << 25) | (rs2 << 20) | (rs1 << 15) | (func3 << 12) | (rd << 7) | opcode
+}
+
+fn i_type(imm: i32, rs1: u32, func3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm as u32 & 0xFFF) << 20) | (rs1 << 15) | (func3 << 12) | (rd << 7) | opcode
+}
+
+fn s_type(imm: i32, rs2: u32, rs1: u32, func3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (extract_bits(imm, 11, 5) << 25) | (rs2 << 20) | (rs1 << 15) | (func3 << 12) | (extract_bits(imm, 4, 0) << 7) | opcode
+}
+
+fn b_type(imm: i32, rs2: u32, rs1: u32, func3: u32) -> u32 {
+    let imm = imm as u32;
+    (extract_bits(imm, 12, 12) << 31) | (extract_bits(imm, 10, 5) << 25) | (rs2 << 20) | (rs1 << 15)
+        | (func3 << 12) | (extract_bits(imm, 4, 1) << 8) | (extract_bits(imm, 11, 11) << 7) | 0b1100011
+}
+
+fn u_type(imm: i32, rd: u32, opcode: u32) -> u32 {
+    (imm as u32 & 0xFFFF_F000) | (rd << 7) | opcode
+}
+
+fn j_type(imm: i32, rd: u32) -> u32 {
+    let imm = imm as u32;
+    (extract_bits(imm, 20, 20) << 31) | (extract_bits(imm, 10, 1) << 21) | (extract_bits(imm, 11, 11) << 20)
+        | (extract_bits(imm, 19, 12) << 12) | (rd << 7) | 0b1101111
+}
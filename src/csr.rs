@@ -0,0 +1,474 @@
+use crate::{isa::Isa, mmu::PagingMode, stages::Xlen, vector::VTYPE_VILL};
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+pub const VSTART: u16 = 0x008;
+pub const VXSAT: u16 = 0x009;
+pub const VXRM: u16 = 0x00A;
+pub const VCSR: u16 = 0x00F;
+pub const VL: u16 = 0xC20;
+pub const VTYPE: u16 = 0xC21;
+pub const VLENB: u16 = 0xC22;
+
+pub const CYCLE: u16 = 0xC00;
+pub const TIME: u16 = 0xC01;
+pub const INSTRET: u16 = 0xC02;
+pub const CYCLEH: u16 = 0xC80;
+pub const TIMEH: u16 = 0xC81;
+pub const INSTRETH: u16 = 0xC82;
+
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const SATP: u16 = 0x180;
+
+pub const MVENDORID: u16 = 0xF11;
+pub const MARCHID: u16 = 0xF12;
+pub const MIMPID: u16 = 0xF13;
+pub const MHARTID: u16 = 0xF14;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MSTATUSH: u16 = 0x310;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const PMPCFG0: u16 = 0x3A0;
+pub const PMPADDR0: u16 = 0x3B0;
+pub const MCYCLE: u16 = 0xB00;
+pub const MINSTRET: u16 = 0xB02;
+pub const MCYCLEH: u16 = 0xB80;
+pub const MINSTRETH: u16 = 0xB82;
+
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+/// Vector state, with the same encoding as FS.
+pub const MSTATUS_VS: u64 = 0b11 << 9;
+pub const MSTATUS_VS_INITIAL: u64 = 0b01 << 9;
+pub const MSTATUS_VS_DIRTY: u64 = 0b11 << 9;
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+/// Floating point state: Off, Initial, Clean or Dirty.
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+pub const MSTATUS_FS_INITIAL: u64 = 0b01 << 13;
+pub const MSTATUS_FS_DIRTY: u64 = 0b11 << 13;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TW: u64 = 1 << 21;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+/// UXL and SXL, both hardwired to 64 bit on RV64.
+const MSTATUS_XL_64: u64 = (2 << 32) | (2 << 34);
+
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE | MSTATUS_SPP | MSTATUS_VS | MSTATUS_MPP
+    | MSTATUS_FS | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+const SSTATUS_WRITABLE: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_VS | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR;
+/// The fields of mstatus that are visible through sstatus, including SD in the top bit.
+const SSTATUS_MASK: u64 = SSTATUS_WRITABLE | (2 << 32) | (1 << 63) | (1 << 31);
+
+pub const MIP_SSIP: u64 = 1 << 1;
+pub const MIP_MSIP: u64 = 1 << 3;
+pub const MIP_STIP: u64 = 1 << 5;
+pub const MIP_MTIP: u64 = 1 << 7;
+pub const MIP_SEIP: u64 = 1 << 9;
+pub const MIP_MEIP: u64 = 1 << 11;
+
+const SUPERVISOR_INTERRUPTS: u64 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+const MIE_WRITABLE: u64 = SUPERVISOR_INTERRUPTS | MIP_MSIP | MIP_MTIP | MIP_MEIP;
+/// Machine mode can raise any of the supervisor interrupts, the machine ones belong to devices.
+const MIP_WRITABLE: u64 = SUPERVISOR_INTERRUPTS;
+
+/// Every exception except an ECALL from M-mode can be delegated.
+const MEDELEG_WRITABLE: u64 = 0xB3FF;
+
+const fn extension(letter: u8) -> u64 {
+    1 << (letter - b'A')
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    #[default]
+    Machine = 3,
+}
+
+impl Privilege {
+    /// Decodes the 2 bit privilege encoding used by the xPP fields, 0b10 is reserved.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Privilege::User),
+            1 => Some(Privilege::Supervisor),
+            3 => Some(Privilege::Machine),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CsrError {
+    #[error("Unknown CSR: 0x{0:03x}")]
+    Unknown(u16),
+    #[error("CSR 0x{0:03x} is read-only")]
+    ReadOnly(u16),
+    #[error("CSR 0x{0:03x} is not accessible from the current privilege level")]
+    Privileged(u16),
+    #[error("CSR 0x{0:03x} belongs to a disabled unit")]
+    Disabled(u16),
+}
+
+/// Whether the CSR address is in one of the read-only ranges (csr[11:10] == 0b11).
+pub fn is_read_only(csr: u16) -> bool {
+    (csr >> 10) & 0b11 == 0b11
+}
+
+/// The lowest privilege level that can access the CSR (csr[9:8]).
+pub fn min_privilege(csr: u16) -> Privilege {
+    match (csr >> 8) & 0b11 {
+        0 => Privilege::User,
+        1 => Privilege::Supervisor,
+        _ => Privilege::Machine,
+    }
+}
+
+pub struct CsrFile {
+    pub cycle: u64,
+    /// Without a timer device time simply advances by one tick per cycle.
+    pub time: u64,
+    pub instret: u64,
+
+    pub mstatus: u64,
+    pub medeleg: u64,
+    pub mideleg: u64,
+    pub mie: u64,
+    /// What software wrote and the CLINT's lines, `external` is added on top when it's read.
+    pub mip: u64,
+    /// The external interrupt lines of an interrupt controller. SEIP reads as the bit software
+    /// wrote or the line.
+    pub external: u64,
+    pub mtvec: u64,
+    pub mcounteren: u64,
+    pub mscratch: u64,
+    pub mepc: u64,
+    pub mcause: u64,
+    pub mtval: u64,
+
+    pub stvec: u64,
+    pub scounteren: u64,
+    pub sscratch: u64,
+    pub sepc: u64,
+    pub scause: u64,
+    pub stval: u64,
+    pub satp: u64,
+
+    /// Accrued floating point exceptions (NV, DZ, OF, UF, NX).
+    pub fflags: u64,
+    /// Dynamic rounding mode.
+    pub frm: u64,
+
+    /// The element a vector instruction restarts at after a trap.
+    pub vstart: u64,
+    pub vxsat: u64,
+    pub vxrm: u64,
+    /// Only vsetvl and friends change vl and vtype, `vtype` keeps vill in bit 63 on RV32 too.
+    pub vl: u64,
+    pub vtype: u64,
+    pub vlenb: u64,
+
+    /// Physical memory protection isn't enforced, the registers only hold what firmware writes.
+    pub pmpcfg: [u64; 16],
+    pub pmpaddr: [u64; 64],
+
+    /// The extension bits of `misa`, one per letter starting at 'A'.
+    pub extensions: u64,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self {
+            cycle: 0,
+            time: 0,
+            instret: 0,
+            mstatus: MSTATUS_FS_INITIAL | MSTATUS_VS_INITIAL,
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            external: 0,
+            mtvec: 0,
+            mcounteren: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            stvec: 0,
+            scounteren: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            satp: 0,
+            fflags: 0,
+            frm: 0,
+            vstart: 0,
+            vxsat: 0,
+            vxrm: 0,
+            vl: 0,
+            vtype: VTYPE_VILL,
+            vlenb: 16,
+            pmpcfg: [0; 16],
+            pmpaddr: [0; 64],
+            extensions: Isa::default().misa_extensions(),
+        }
+    }
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances the free running counters by one cycle.
+    pub fn tick(&mut self) {
+        self.cycle = self.cycle.wrapping_add(1);
+        self.time = self.time.wrapping_add(1);
+    }
+
+    pub fn retire(&mut self) {
+        self.instret = self.instret.wrapping_add(1);
+    }
+
+    pub fn misa(&self, xlen: Xlen) -> u64 {
+        match xlen {
+            Xlen::Rv32 => (1 << 30) | self.extensions,
+            Xlen::Rv64 => (2 << 62) | self.extensions,
+        }
+    }
+
+    /// Without C instructions are 4 byte aligned, which hides bit 1 of the exception PCs.
+    pub fn epc(&self, epc: u64) -> u64 {
+        if self.extensions & extension(b'C') == 0 { epc & !0b11 } else { epc }
+    }
+
+    /// Checks the privilege level encoded in the CSR address and the counter enables.
+    pub fn check_access(&self, csr: u16, privilege: Privilege) -> Result<(), CsrError> {
+        if privilege < min_privilege(csr) {
+            return Err(CsrError::Privileged(csr));
+        }
+
+        // The floating point CSRs are off limits while mstatus.FS is Off
+        if matches!(csr, FFLAGS | FRM | FCSR) && (self.mstatus & MSTATUS_FS == 0 || self.extensions & extension(b'F') == 0) {
+            return Err(CsrError::Disabled(csr));
+        }
+        if matches!(csr, VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB) && (self.mstatus & MSTATUS_VS == 0 || self.extensions & extension(b'V') == 0) {
+            return Err(CsrError::Disabled(csr));
+        }
+
+        // With TVM set supervisor mode may not touch satp
+        if csr == SATP && privilege == Privilege::Supervisor && self.mstatus & MSTATUS_TVM != 0 {
+            return Err(CsrError::Privileged(csr));
+        }
+
+        if matches!(csr, 0xC00..=0xC1F | 0xC80..=0xC9F) {
+            let bit = 1 << (csr & 0x1F);
+
+            if privilege < Privilege::Machine && self.mcounteren & bit == 0 {
+                return Err(CsrError::Privileged(csr));
+            }
+            if privilege < Privilege::Supervisor && self.scounteren & bit == 0 {
+                return Err(CsrError::Privileged(csr));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The interrupts that are pending, as `mip` reads.
+    pub fn pending(&self) -> u64 {
+        self.mip | self.external
+    }
+
+    /// The value a CSRRS or CSRRC starts from. It's what `read` gives, except that `mip` leaves
+    /// out the external lines so they don't get latched into SEIP.
+    pub fn read_for_update(&self, csr: u16, xlen: Xlen) -> Result<u64, CsrError> {
+        match csr {
+            MIP => Ok(self.mip),
+            _ => self.read(csr, xlen),
+        }
+    }
+
+    pub fn read(&self, csr: u16, xlen: Xlen) -> Result<u64, CsrError> {
+        // SD summarizes a dirty FS or VS in the most significant bit
+        let dirty = self.mstatus & MSTATUS_FS == MSTATUS_FS_DIRTY || self.mstatus & MSTATUS_VS == MSTATUS_VS_DIRTY;
+        let mstatus = match xlen {
+            Xlen::Rv32 => (self.mstatus & 0xFFFF_FFFF) | ((dirty as u64) << 31),
+            Xlen::Rv64 => self.mstatus | MSTATUS_XL_64 | ((dirty as u64) << 63),
+        };
+
+        match (csr, xlen) {
+            (FFLAGS, _) => Ok(self.fflags),
+            (FRM, _) => Ok(self.frm),
+            (FCSR, _) => Ok((self.frm << 5) | self.fflags),
+            (VSTART, _) => Ok(self.vstart),
+            (VXSAT, _) => Ok(self.vxsat),
+            (VXRM, _) => Ok(self.vxrm),
+            (VCSR, _) => Ok((self.vxrm << 1) | self.vxsat),
+            (VL, _) => Ok(self.vl),
+            (VTYPE, Xlen::Rv32) if self.vtype & VTYPE_VILL != 0 => Ok(1 << 31),
+            (VTYPE, _) => Ok(self.vtype),
+            (VLENB, _) => Ok(self.vlenb),
+
+            (CYCLE | MCYCLE, _) => Ok(self.cycle),
+            (TIME, _) => Ok(self.time),
+            (INSTRET | MINSTRET, _) => Ok(self.instret),
+            (CYCLEH | MCYCLEH, Xlen::Rv32) => Ok(self.cycle >> 32),
+            (TIMEH, Xlen::Rv32) => Ok(self.time >> 32),
+            (INSTRETH | MINSTRETH, Xlen::Rv32) => Ok(self.instret >> 32),
+
+            (SSTATUS, _) => Ok(mstatus & SSTATUS_MASK),
+            (SIE, _) => Ok(self.mie & self.mideleg),
+            (STVEC, _) => Ok(self.stvec),
+            (SCOUNTEREN, _) => Ok(self.scounteren),
+            (SSCRATCH, _) => Ok(self.sscratch),
+            (SEPC, _) => Ok(self.epc(self.sepc)),
+            (SCAUSE, _) => Ok(self.scause),
+            (STVAL, _) => Ok(self.stval),
+            (SIP, _) => Ok(self.pending() & self.mideleg),
+            (SATP, _) => Ok(self.satp),
+
+            (MVENDORID | MARCHID | MIMPID | MHARTID, _) => Ok(0),
+            (MSTATUS, _) => Ok(mstatus),
+            (MSTATUSH, Xlen::Rv32) => Ok(self.mstatus >> 32),
+            (MISA, _) => Ok(self.misa(xlen)),
+            (MEDELEG, _) => Ok(self.medeleg),
+            (MIDELEG, _) => Ok(self.mideleg),
+            (MIE, _) => Ok(self.mie),
+            (MIP, _) => Ok(self.pending()),
+            (MTVEC, _) => Ok(self.mtvec),
+            (MCOUNTEREN, _) => Ok(self.mcounteren),
+            (MSCRATCH, _) => Ok(self.mscratch),
+            (MEPC, _) => Ok(self.epc(self.mepc)),
+            (MCAUSE, _) => Ok(self.mcause),
+            (MTVAL, _) => Ok(self.mtval),
+            // RV64 packs eight entries per pmpcfg register, so only the even ones exist
+            (0x3A0..=0x3AF, Xlen::Rv64) if csr & 1 == 1 => Err(CsrError::Unknown(csr)),
+            (0x3A0..=0x3AF, _) => Ok(self.pmpcfg[(csr - PMPCFG0) as usize]),
+            (0x3B0..=0x3EF, _) => Ok(self.pmpaddr[(csr - PMPADDR0) as usize]),
+            _ => Err(CsrError::Unknown(csr)),
+        }
+    }
+
+    pub fn write(&mut self, csr: u16, value: u64, xlen: Xlen) -> Result<(), CsrError> {
+        // Make sure the CSR exists before complaining about its permissions
+        self.read(csr, xlen)?;
+
+        if is_read_only(csr) {
+            return Err(CsrError::ReadOnly(csr));
+        }
+
+        let value = match xlen {
+            Xlen::Rv32 => value & 0xFFFF_FFFF,
+            Xlen::Rv64 => value,
+        };
+
+        match csr {
+            FFLAGS => self.fflags = value & 0x1F,
+            FRM => self.frm = value & 0b111,
+            FCSR => {
+                self.fflags = value & 0x1F;
+                self.frm = (value >> 5) & 0b111;
+            },
+            VSTART => self.vstart = value,
+            VXSAT => self.vxsat = value & 1,
+            VXRM => self.vxrm = value & 0b11,
+            VCSR => {
+                self.vxsat = value & 1;
+                self.vxrm = (value >> 1) & 0b11;
+            },
+            SSTATUS => self.mstatus = (self.mstatus & !SSTATUS_WRITABLE) | (value & SSTATUS_WRITABLE),
+            SIE => self.mie = (self.mie & !self.mideleg) | (value & self.mideleg & SUPERVISOR_INTERRUPTS),
+            STVEC => self.stvec = trap_vector(self.stvec, value),
+            SCOUNTEREN => self.scounteren = value & 0xFFFF_FFFF,
+            SSCRATCH => self.sscratch = value,
+            SEPC => self.sepc = value & !0b1,
+            SCAUSE => self.scause = value,
+            STVAL => self.stval = value,
+            // Only the software interrupt can be raised from supervisor mode
+            SIP => self.mip = (self.mip & !(MIP_SSIP & self.mideleg)) | (value & MIP_SSIP & self.mideleg),
+            // Writing an unsupported paging mode has no effect at all
+            SATP => if PagingMode::from_satp(value, xlen).is_some() {
+                self.satp = value;
+            },
+
+            MSTATUS => {
+                let mut value = value;
+                // MPP is WARL, the reserved encoding keeps the previous mode
+                if Privilege::from_bits((value & MSTATUS_MPP) >> 11).is_none() {
+                    value = (value & !MSTATUS_MPP) | (self.mstatus & MSTATUS_MPP);
+                }
+                self.mstatus = (self.mstatus & !MSTATUS_WRITABLE) | (value & MSTATUS_WRITABLE);
+            },
+            MSTATUSH => {}, // No writable fields in the upper half
+            MISA => {}, // WARL, the extensions can't be switched at run time
+            MEDELEG => self.medeleg = value & MEDELEG_WRITABLE,
+            MIDELEG => self.mideleg = value & SUPERVISOR_INTERRUPTS,
+            MIE => self.mie = value & MIE_WRITABLE,
+            MIP => self.mip = (self.mip & !MIP_WRITABLE) | (value & MIP_WRITABLE),
+            MTVEC => self.mtvec = trap_vector(self.mtvec, value),
+            MCOUNTEREN => self.mcounteren = value & 0xFFFF_FFFF,
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b1,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            0x3A0..=0x3AF => self.pmpcfg[(csr - PMPCFG0) as usize] = value,
+            0x3B0..=0x3EF => self.pmpaddr[(csr - PMPADDR0) as usize] = value,
+            MCYCLE => self.cycle = write_low(self.cycle, value, xlen),
+            MCYCLEH => self.cycle = write_high(self.cycle, value),
+            MINSTRET => self.instret = write_low(self.instret, value, xlen),
+            MINSTRETH => self.instret = write_high(self.instret, value),
+            _ => return Err(CsrError::Unknown(csr)),
+        }
+
+        if matches!(csr, FFLAGS | FRM | FCSR) {
+            self.mstatus |= MSTATUS_FS_DIRTY;
+        }
+        if matches!(csr, VSTART | VXSAT | VXRM | VCSR) {
+            self.mstatus |= MSTATUS_VS_DIRTY;
+        }
+
+        Ok(())
+    }
+}
+
+/// Modes 2 and 3 of xtvec are reserved, writing them keeps the previous value.
+fn trap_vector(current: u64, value: u64) -> u64 {
+    if value & 0b11 < 2 { value } else { current }
+}
+
+/// On RV32 a 64 bit counter is written in two halves.
+fn write_low(current: u64, value: u64, xlen: Xlen) -> u64 {
+    match xlen {
+        Xlen::Rv32 => (current & !0xFFFF_FFFF) | value,
+        Xlen::Rv64 => value,
+    }
+}
+
+fn write_high(current: u64, value: u64) -> u64 {
+    (current & 0xFFFF_FFFF) | (value << 32)
+}
@@ -0,0 +1,555 @@
+//! IEEE-754 single and double precision arithmetic on raw bit patterns. Everything is computed
+//! with integers, so the five RISC-V rounding modes and the exception flags don't depend on the
+//! host FPU.
+
+/// Accrued exception flags, in their `fflags` bit positions.
+pub const NV: u8 = 1 << 4;
+pub const DZ: u8 = 1 << 3;
+pub const OF: u8 = 1 << 2;
+pub const UF: u8 = 1 << 1;
+pub const NX: u8 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// Decodes the `rm` field and `frm`, 5 and 6 are reserved and 7 selects `frm`.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(RoundingMode::NearestEven),
+            1 => Some(RoundingMode::TowardZero),
+            2 => Some(RoundingMode::Down),
+            3 => Some(RoundingMode::Up),
+            4 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Single,
+    Double,
+}
+
+impl Format {
+    fn frac_bits(self) -> i32 {
+        match self {
+            Format::Single => 23,
+            Format::Double => 52,
+        }
+    }
+
+    fn exp_bits(self) -> i32 {
+        match self {
+            Format::Single => 8,
+            Format::Double => 11,
+        }
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits()) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits() + self.frac_bits())
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits()) - 1
+    }
+
+    /// The only NaN RISC-V arithmetic ever produces.
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            Format::Single => 0x7FC0_0000,
+            Format::Double => 0x7FF8_0000_0000_0000,
+        }
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.signed(sign, self.max_exp() << self.frac_bits())
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.signed(sign, ((self.max_exp() - 1) << self.frac_bits()) | self.frac_mask())
+    }
+
+    fn signed(self, sign: bool, magnitude: u64) -> u64 {
+        if sign { self.sign_bit() | magnitude } else { magnitude }
+    }
+}
+
+/// Single precision values live in the low half of a 64 bit register with the upper half all
+/// ones. Anything else reads as the canonical NaN.
+pub fn nan_box(bits: u64) -> u64 {
+    0xFFFF_FFFF_0000_0000 | (bits & 0xFFFF_FFFF)
+}
+
+pub fn unbox(format: Format, register: u64) -> u64 {
+    match format {
+        Format::Single if register >> 32 != 0xFFFF_FFFF => format.canonical_nan(),
+        Format::Single => register & 0xFFFF_FFFF,
+        Format::Double => register,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Zero,
+    Finite,
+    Infinity,
+    QuietNaN,
+    SignalingNaN,
+}
+
+/// A decoded value, finite ones are `sig * 2^exp`.
+#[derive(Debug, Clone, Copy)]
+struct Unpacked {
+    class: Class,
+    sign: bool,
+    exp: i32,
+    sig: u128,
+}
+
+impl Unpacked {
+    fn is_nan(&self) -> bool {
+        matches!(self.class, Class::QuietNaN | Class::SignalingNaN)
+    }
+}
+
+fn unpack(format: Format, bits: u64) -> Unpacked {
+    let frac_bits = format.frac_bits();
+    let sign = bits & format.sign_bit() != 0;
+    let biased = (bits >> frac_bits) & format.max_exp();
+    let frac = bits & format.frac_mask();
+    let quiet_bit = 1 << (frac_bits - 1);
+
+    let (class, exp, sig) = match (biased, frac) {
+        (0, 0) => (Class::Zero, 0, 0),
+        (0, _) => (Class::Finite, 1 - format.bias() - frac_bits, frac as u128),
+        (e, 0) if e == format.max_exp() => (Class::Infinity, 0, 0),
+        (e, _) if e == format.max_exp() && frac & quiet_bit != 0 => (Class::QuietNaN, 0, 0),
+        (e, _) if e == format.max_exp() => (Class::SignalingNaN, 0, 0),
+        (e, _) => (Class::Finite, e as i32 - format.bias() - frac_bits, (frac | (1 << frac_bits)) as u128),
+    };
+
+    Unpacked { class, sign, exp, sig }
+}
+
+/// Shifts `sig` right by `shift` bits and rounds the result to an integer, also reporting
+/// whether any bits were lost.
+fn round_shift(sig: u128, shift: i32, sign: bool, rm: RoundingMode) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+
+    let (quotient, remainder, half) = match shift {
+        128 => (0, sig, Some(1 << 127)),
+        129.. => (0, sig, None),
+        _ => (sig >> shift, sig & ((1 << shift) - 1), Some(1u128 << (shift - 1))),
+    };
+
+    if remainder == 0 {
+        return (quotient, false);
+    }
+
+    let round_up = match rm {
+        RoundingMode::NearestEven => half.is_some_and(|half| remainder > half || (remainder == half && quotient & 1 == 1)),
+        RoundingMode::NearestMaxMagnitude => half.is_some_and(|half| remainder >= half),
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => sign,
+        RoundingMode::Up => !sign,
+    };
+
+    (quotient + round_up as u128, true)
+}
+
+/// Rounds the exact value `(-1)^sign * sig * 2^exp` to the format. `sig` may carry a sticky
+/// bit below the precision that matters. Tininess is detected after rounding.
+fn round_pack(format: Format, sign: bool, exp: i32, sig: u128, rm: RoundingMode) -> (u64, u8) {
+    if sig == 0 {
+        return (format.signed(sign, 0), 0);
+    }
+
+    let frac_bits = format.frac_bits();
+    let emin = 1 - format.bias();
+    let leading = 127 - sig.leading_zeros() as i32 + exp;
+
+    // Subnormals share the quantum of the smallest normal exponent
+    let mut quantum = leading.max(emin) - frac_bits;
+    let (mut rounded, inexact) = round_shift(sig, quantum - exp, sign, rm);
+    let mut flags = if inexact { NX } else { 0 };
+
+    if leading < emin && inexact {
+        // Only a value just below 2^emin can round up to it with an unbounded exponent
+        let (unbounded, _) = round_shift(sig, leading - frac_bits - exp, sign, rm);
+        if leading < emin - 1 || unbounded >> (frac_bits + 1) == 0 {
+            flags |= UF;
+        }
+    }
+
+    if rounded >> (frac_bits + 1) != 0 {
+        rounded >>= 1;
+        quantum += 1;
+    }
+
+    let biased = if rounded >> frac_bits == 0 { 0 } else { (quantum + frac_bits + format.bias()) as u64 };
+
+    if biased >= format.max_exp() {
+        let to_infinity = match rm {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign,
+            RoundingMode::Up => !sign,
+        };
+
+        let result = if to_infinity { format.infinity(sign) } else { format.max_finite(sign) };
+        return (result, OF | NX);
+    }
+
+    (format.signed(sign, (biased << frac_bits) | (rounded as u64 & format.frac_mask())), flags)
+}
+
+/// Exactly adds two finite values, except for a sticky bit when their exponents are far apart.
+/// Both are first normalized to bit 125, leaving room for the carry.
+fn add_exact(a: (bool, i32, u128), b: (bool, i32, u128)) -> (bool, i32, u128) {
+    let normalize = |(sign, exp, sig): (bool, i32, u128)| {
+        let shift = sig.leading_zeros() as i32 - 2;
+        (sign, exp - shift, sig << shift)
+    };
+
+    if a.2 == 0 {
+        return b;
+    }
+    if b.2 == 0 {
+        return a;
+    }
+
+    let (a, b) = (normalize(a), normalize(b));
+    let (high, low) = if a.1 >= b.1 { (a, b) } else { (b, a) };
+
+    let shift = (high.1 - low.1) as u32;
+    let low_sig = match shift {
+        0..=127 => (low.2 >> shift) | (low.2 & ((1 << shift) - 1) != 0) as u128,
+        _ => 1,
+    };
+
+    if high.0 == low.0 {
+        (high.0, high.1, high.2 + low_sig)
+    } else if high.2 >= low_sig {
+        (high.0, high.1, high.2 - low_sig)
+    } else {
+        (low.0, high.1, low_sig - high.2)
+    }
+}
+
+/// The sign of an exact zero sum, which is only negative when rounding down.
+fn zero_sum_sign(a: bool, b: bool, rm: RoundingMode) -> bool {
+    if a == b { a } else { rm == RoundingMode::Down }
+}
+
+fn nan_result(format: Format, operands: &[Unpacked]) -> (u64, u8) {
+    let signaling = operands.iter().any(|operand| operand.class == Class::SignalingNaN);
+    (format.canonical_nan(), if signaling { NV } else { 0 })
+}
+
+pub fn add(format: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+    let (a, b) = (unpack(format, a), unpack(format, b));
+
+    if a.is_nan() || b.is_nan() {
+        return nan_result(format, &[a, b]);
+    }
+
+    match (a.class, b.class) {
+        (Class::Infinity, Class::Infinity) if a.sign != b.sign => (format.canonical_nan(), NV),
+        (Class::Infinity, _) => (format.infinity(a.sign), 0),
+        (_, Class::Infinity) => (format.infinity(b.sign), 0),
+        (Class::Zero, Class::Zero) => (format.signed(zero_sum_sign(a.sign, b.sign, rm), 0), 0),
+        _ => {
+            let (sign, exp, sig) = add_exact((a.sign, a.exp, a.sig), (b.sign, b.exp, b.sig));
+            let sign = if sig == 0 { rm == RoundingMode::Down } else { sign };
+            round_pack(format, sign, exp, sig, rm)
+        },
+    }
+}
+
+pub fn sub(format: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+    // Flipping the sign of a NaN is harmless, the result is canonical anyway
+    add(format, a, b ^ format.sign_bit(), rm)
+}
+
+pub fn mul(format: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+    let (a, b) = (unpack(format, a), unpack(format, b));
+    let sign = a.sign != b.sign;
+
+    if a.is_nan() || b.is_nan() {
+        return nan_result(format, &[a, b]);
+    }
+
+    match (a.class, b.class) {
+        (Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity) => (format.canonical_nan(), NV),
+        (Class::Infinity, _) | (_, Class::Infinity) => (format.infinity(sign), 0),
+        (Class::Zero, _) | (_, Class::Zero) => (format.signed(sign, 0), 0),
+        _ => round_pack(format, sign, a.exp + b.exp, a.sig * b.sig, rm),
+    }
+}
+
+/// Computes `(a * b) + c` with a single rounding, negating the product and/or the addend first
+/// for FMSUB, FNMSUB and FNMADD.
+pub fn fused_mul_add(format: Format, a: u64, b: u64, c: u64, negate_product: bool, negate_addend: bool, rm: RoundingMode) -> (u64, u8) {
+    let (a, b, c) = (unpack(format, a), unpack(format, b), unpack(format, c));
+    let product_sign = (a.sign != b.sign) != negate_product;
+    let addend_sign = c.sign != negate_addend;
+    let invalid_product = matches!((a.class, b.class), (Class::Infinity, Class::Zero) | (Class::Zero, Class::Infinity));
+
+    // inf * 0 is invalid even when the addend is a quiet NaN
+    if a.is_nan() || b.is_nan() || c.is_nan() {
+        let (nan, flags) = nan_result(format, &[a, b, c]);
+        return (nan, if invalid_product { NV } else { flags });
+    }
+    if invalid_product {
+        return (format.canonical_nan(), NV);
+    }
+
+    let product_infinite = a.class == Class::Infinity || b.class == Class::Infinity;
+    let product_zero = a.class == Class::Zero || b.class == Class::Zero;
+
+    match (product_infinite, c.class) {
+        (true, Class::Infinity) if product_sign != addend_sign => (format.canonical_nan(), NV),
+        (true, _) => (format.infinity(product_sign), 0),
+        (_, Class::Infinity) => (format.infinity(addend_sign), 0),
+        _ if product_zero && c.class == Class::Zero => (format.signed(zero_sum_sign(product_sign, addend_sign, rm), 0), 0),
+        _ if product_zero => round_pack(format, addend_sign, c.exp, c.sig, rm),
+        _ => {
+            let (sign, exp, sig) = add_exact((product_sign, a.exp + b.exp, a.sig * b.sig), (addend_sign, c.exp, c.sig));
+            let sign = if sig == 0 { rm == RoundingMode::Down } else { sign };
+            round_pack(format, sign, exp, sig, rm)
+        },
+    }
+}
+
+/// Shifts the significand of a finite nonzero value up to the implicit bit position.
+fn normalize(format: Format, value: Unpacked) -> (i32, u128) {
+    let shift = format.frac_bits() - (127 - value.sig.leading_zeros() as i32);
+    (value.exp - shift, value.sig << shift)
+}
+
+pub fn div(format: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u8) {
+    let (a, b) = (unpack(format, a), unpack(format, b));
+    let sign = a.sign != b.sign;
+
+    if a.is_nan() || b.is_nan() {
+        return nan_result(format, &[a, b]);
+    }
+
+    match (a.class, b.class) {
+        (Class::Infinity, Class::Infinity) | (Class::Zero, Class::Zero) => (format.canonical_nan(), NV),
+        (Class::Infinity, _) => (format.infinity(sign), 0),
+        (_, Class::Infinity) | (Class::Zero, _) => (format.signed(sign, 0), 0),
+        (_, Class::Zero) => (format.infinity(sign), DZ),
+        _ => {
+            let ((a_exp, a_sig), (b_exp, b_sig)) = (normalize(format, a), normalize(format, b));
+
+            // The quotient of the normalized significands keeps at least 73 bits
+            let dividend = a_sig << 74;
+            let quotient = dividend / b_sig;
+            let sticky = (dividend % b_sig != 0) as u128;
+
+            round_pack(format, sign, a_exp - b_exp - 74, quotient | sticky, rm)
+        },
+    }
+}
+
+pub fn sqrt(format: Format, a: u64, rm: RoundingMode) -> (u64, u8) {
+    let a = unpack(format, a);
+
+    if a.is_nan() {
+        return nan_result(format, &[a]);
+    }
+
+    match a.class {
+        Class::Zero => (format.signed(a.sign, 0), 0),
+        _ if a.sign => (format.canonical_nan(), NV),
+        Class::Infinity => (format.infinity(false), 0),
+        _ => {
+            let (mut exp, mut sig) = normalize(format, a);
+
+            // An even exponent can be halved exactly
+            if exp.rem_euclid(2) != 0 {
+                sig <<= 1;
+                exp -= 1;
+            }
+
+            let radicand = sig << 72;
+            let root = radicand.isqrt();
+            let sticky = (root * root != radicand) as u128;
+
+            round_pack(format, false, (exp - 72) / 2, root | sticky, rm)
+        },
+    }
+}
+
+/// Maps a non NaN value to an integer with the same ordering, both zeros map to 0.
+fn ordered(format: Format, bits: u64) -> i128 {
+    let magnitude = (bits & !format.sign_bit()) as i128;
+    if bits & format.sign_bit() != 0 { -magnitude } else { magnitude }
+}
+
+/// FEQ is a quiet comparison, only signaling NaNs raise the invalid flag.
+pub fn eq(format: Format, a: u64, b: u64) -> (bool, u8) {
+    let (ua, ub) = (unpack(format, a), unpack(format, b));
+
+    if ua.is_nan() || ub.is_nan() {
+        return (false, nan_result(format, &[ua, ub]).1);
+    }
+
+    (ordered(format, a) == ordered(format, b), 0)
+}
+
+/// FLT and FLE are signaling comparisons, any NaN raises the invalid flag.
+pub fn lt(format: Format, a: u64, b: u64) -> (bool, u8) {
+    if unpack(format, a).is_nan() || unpack(format, b).is_nan() {
+        return (false, NV);
+    }
+
+    (ordered(format, a) < ordered(format, b), 0)
+}
+
+pub fn le(format: Format, a: u64, b: u64) -> (bool, u8) {
+    if unpack(format, a).is_nan() || unpack(format, b).is_nan() {
+        return (false, NV);
+    }
+
+    (ordered(format, a) <= ordered(format, b), 0)
+}
+
+/// FMIN and FMAX return the other operand when only one is a NaN, and treat -0 as smaller
+/// than +0.
+pub fn min_max(format: Format, a: u64, b: u64, max: bool) -> (u64, u8) {
+    let (ua, ub) = (unpack(format, a), unpack(format, b));
+    let flags = nan_result(format, &[ua, ub]).1;
+
+    match (ua.is_nan(), ub.is_nan()) {
+        (true, true) => return (format.canonical_nan(), flags),
+        (true, false) => return (b, flags),
+        (false, true) => return (a, flags),
+        _ => {},
+    }
+
+    let (oa, ob) = (ordered(format, a), ordered(format, b));
+    let a_first = if oa == ob {
+        // Only zeros compare equal with different bits
+        (a & format.sign_bit() != 0) != max
+    } else {
+        (oa < ob) != max
+    };
+
+    (if a_first { a } else { b }, 0)
+}
+
+/// The FCLASS mask: -inf, -normal, -subnormal, -0, +0, +subnormal, +normal, +inf, sNaN, qNaN.
+pub fn classify(format: Format, bits: u64) -> u64 {
+    let value = unpack(format, bits);
+    let subnormal = (bits >> format.frac_bits()) & format.max_exp() == 0;
+
+    let bit = match (value.class, value.sign) {
+        (Class::Infinity, true) => 0,
+        (Class::Finite, true) if !subnormal => 1,
+        (Class::Finite, true) => 2,
+        (Class::Zero, true) => 3,
+        (Class::Zero, false) => 4,
+        (Class::Finite, false) if subnormal => 5,
+        (Class::Finite, false) => 6,
+        (Class::Infinity, false) => 7,
+        (Class::SignalingNaN, _) => 8,
+        (Class::QuietNaN, _) => 9,
+    };
+
+    1 << bit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignInjection {
+    Copy,
+    Negate,
+    Xor,
+}
+
+pub fn sign_inject(format: Format, a: u64, b: u64, op: SignInjection) -> u64 {
+    let sign_bit = format.sign_bit();
+    let sign = match op {
+        SignInjection::Copy => b & sign_bit,
+        SignInjection::Negate => !b & sign_bit,
+        SignInjection::Xor => (a ^ b) & sign_bit,
+    };
+
+    (a & !sign_bit) | sign
+}
+
+/// Converts to a `width` bit integer. Out of range values and NaNs saturate and raise the
+/// invalid flag instead of the inexact flag. The result is truncated to `width` bits.
+pub fn to_int(format: Format, bits: u64, signed: bool, width: u32, rm: RoundingMode) -> (u64, u8) {
+    let value = unpack(format, bits);
+    let max = if signed { (1u128 << (width - 1)) - 1 } else { (1u128 << width) - 1 };
+    let min_magnitude = if signed { 1u128 << (width - 1) } else { 0 };
+    let mask = (1u128 << width) - 1;
+
+    let saturate = |negative: bool| {
+        let result = if negative { (min_magnitude.wrapping_neg() & mask) as u64 } else { max as u64 };
+        (result, NV)
+    };
+
+    let (magnitude, inexact) = match value.class {
+        Class::QuietNaN | Class::SignalingNaN => return saturate(false),
+        Class::Infinity => return saturate(value.sign),
+        Class::Zero => (0, false),
+        // Anything from 2^64 up is out of range for every width
+        Class::Finite if value.exp + (127 - value.sig.leading_zeros() as i32) >= 64 => return saturate(value.sign),
+        Class::Finite => round_shift(value.sig, -value.exp, value.sign, rm),
+    };
+
+    let in_range = if value.sign { magnitude <= min_magnitude } else { magnitude <= max };
+    if !in_range {
+        return saturate(value.sign);
+    }
+
+    let result = if value.sign { magnitude.wrapping_neg() & mask } else { magnitude };
+    (result as u64, if inexact { NX } else { 0 })
+}
+
+/// Converts the low `width` bits of `value` to a float.
+pub fn from_int(format: Format, value: u64, signed: bool, width: u32, rm: RoundingMode) -> (u64, u8) {
+    let value = if width == 32 { value & 0xFFFF_FFFF } else { value };
+    let negative = signed && value >> (width - 1) & 1 == 1;
+
+    let magnitude = match (negative, width) {
+        (false, _) => value,
+        (true, 32) => (value as u32).wrapping_neg() as u64,
+        (true, _) => value.wrapping_neg(),
+    };
+
+    round_pack(format, negative, 0, magnitude as u128, rm)
+}
+
+/// Converts between single and double precision, NaNs become the canonical NaN.
+pub fn convert(from: Format, to: Format, bits: u64, rm: RoundingMode) -> (u64, u8) {
+    let value = unpack(from, bits);
+
+    match value.class {
+        Class::QuietNaN | Class::SignalingNaN => nan_result(to, &[value]),
+        Class::Infinity => (to.infinity(value.sign), 0),
+        Class::Zero => (to.signed(value.sign, 0), 0),
+        Class::Finite => round_pack(to, value.sign, value.exp, value.sig, rm),
+    }
+}
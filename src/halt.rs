@@ -0,0 +1,43 @@
+/// The Linux/newlib `exit` syscall number in a7.
+pub const SYS_EXIT: u64 = 93;
+
+/// Values written to the SiFive test finisher, the upper 16 bits of a failure hold the code.
+pub const FINISHER_FAIL: u64 = 0x3333;
+pub const FINISHER_PASS: u64 = 0x5555;
+
+/// The ways a guest can stop the simulation with an exit code, besides running into an error
+/// and the HTIF device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HaltConfig {
+    /// An ECALL with a7 = 93 that no trap handler picks up exits with the code in a0.
+    pub ecall_exit: bool,
+    /// An EBREAK that no trap handler picks up exits with the code in a0.
+    pub ebreak: bool,
+    /// Physical address of a SiFive test finisher. Reset requests aren't supported.
+    pub test_finisher: Option<u64>,
+}
+
+impl Default for HaltConfig {
+    fn default() -> Self {
+        Self {
+            ecall_exit: true,
+            ebreak: true,
+            test_finisher: None,
+        }
+    }
+}
+
+impl HaltConfig {
+    /// The exit code a store of `data` to the physical `address` asks for, if any.
+    pub fn store(&self, address: u64, data: u64) -> Option<i64> {
+        if self.test_finisher == Some(address) {
+            return match data & 0xFFFF {
+                FINISHER_PASS => Some(0),
+                FINISHER_FAIL => Some(((data >> 16) & 0xFFFF) as i64),
+                _ => None,
+            };
+        }
+
+        None
+    }
+}
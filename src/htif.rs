@@ -0,0 +1,110 @@
+use std::{collections::VecDeque, io::{self, Write}, sync::mpsc::Receiver};
+
+use goblin::elf::Elf;
+
+use crate::{components::Memory, syscall::{read_guest, stdin, write_guest, ENOSYS}};
+
+// Devices and their commands, in the top two bytes of a tohost value
+const DEVICE_SYSCALL: u64 = 0;
+const DEVICE_CONSOLE: u64 = 1;
+const CONSOLE_GETC: u64 = 0;
+const CONSOLE_PUTC: u64 = 1;
+
+/// Spike's host-target interface: the guest writes commands to `tohost` and gets responses in
+/// `fromhost`. A command is `device << 56 | command << 48 | payload`.
+///
+/// Supported are exits, device 0 command 0 with the low payload bit set and the exit code
+/// above it, and the console, device 1, for reading and writing single characters. Proxied
+/// syscalls, the same command with the address of the guest's syscall buffer, aren't run: like
+/// Spike does for unknown ones, the first word of the buffer gets -ENOSYS and `fromhost` 1.
+#[derive(Debug)]
+pub struct Htif {
+    /// Physical address of the `tohost` word.
+    pub tohost: u64,
+    /// Physical address of the `fromhost` word, without it there are no responses.
+    pub fromhost: Option<u64>,
+    /// Responses waiting for the guest to clear `fromhost`.
+    responses: VecDeque<u64>,
+    /// A console read that's waiting for input.
+    reading: bool,
+    /// Where console input comes from, the shared stdin when it's `None`.
+    input: Option<Receiver<u8>>,
+}
+
+impl Htif {
+    pub fn new(tohost: u64, fromhost: Option<u64>) -> Self {
+        Self {
+            tohost,
+            fromhost,
+            responses: VecDeque::new(),
+            reading: false,
+            input: None,
+        }
+    }
+
+    /// The device at the `tohost` and `fromhost` symbols of the ELF, if it has them.
+    pub fn from_elf(elf: &Elf) -> Option<Self> {
+        let symbol = |name| elf.syms.iter()
+            .find(|symbol| elf.strtab.get_at(symbol.st_name) == Some(name))
+            .map(|symbol| symbol.st_value);
+
+        Some(Self::new(symbol("tohost")?, symbol("fromhost")))
+    }
+
+    /// Runs the command in `tohost` after the guest wrote to it, giving back the exit code when
+    /// the guest asks to stop. `tohost` reads as zero once the command is taken.
+    pub fn command(&mut self, mem: &mut Memory) -> Option<i64> {
+        let value = read_guest(mem, self.tohost, 8).ok()?;
+        if value == 0 {
+            return None;
+        }
+
+        let device = value >> 56;
+        let command = (value >> 48) & 0xFF;
+        let payload = value & 0xFFFF_FFFF_FFFF;
+
+        match (device, command) {
+            (DEVICE_SYSCALL, 0) if payload & 1 == 1 => return Some((payload >> 1) as i64),
+            (DEVICE_SYSCALL, 0) => {
+                let _ = write_guest(mem, payload, 8, -ENOSYS as u64);
+                self.responses.push_back(DEVICE_SYSCALL << 56 | 1);
+            },
+            (DEVICE_CONSOLE, CONSOLE_PUTC) => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(&[payload as u8]).and_then(|_| stdout.flush());
+                self.responses.push_back(DEVICE_CONSOLE << 56 | CONSOLE_PUTC << 48);
+            },
+            (DEVICE_CONSOLE, CONSOLE_GETC) => self.reading = true,
+            _ => {},
+        }
+
+        let _ = write_guest(mem, self.tohost, 8, 0);
+        self.poll(mem);
+        None
+    }
+
+    /// Hands the next response to the guest once it took the previous one. Call it when the
+    /// guest looks at `fromhost`.
+    pub fn poll(&mut self, mem: &mut Memory) {
+        let Some(fromhost) = self.fromhost else {
+            return;
+        };
+
+        if self.reading {
+            let byte = match &self.input {
+                Some(input) => input.try_recv(),
+                None => stdin().try_recv(),
+            };
+            if let Ok(byte) = byte {
+                self.responses.push_back(DEVICE_CONSOLE << 56 | CONSOLE_GETC << 48 | byte as u64);
+                self.reading = false;
+            }
+        }
+
+        if read_guest(mem, fromhost, 8) == Ok(0) {
+            if let Some(response) = self.responses.pop_front() {
+                let _ = write_guest(mem, fromhost, 8, response);
+            }
+        }
+    }
+}
@@ -53,7 +53,7 @@ impl From<u32> for IType {
         let rs1 = extract_bits(value, 19, 15)  as u8;
         let imm_raw = extract_bits(value, 31, 20);
 
-        let shamt = (imm_raw & 0x1F) as u8;
+        let shamt = (imm_raw & 0x3F) as u8;
         let func7 = (imm_raw >> 5) as u8;
 
         let imm = ((imm_raw as i32) << 20) >> 20;
@@ -192,3 +192,77 @@ impl From<u32> for JType {
     }
 }
 
+
+/// The fused multiply-add format, with a third source register where R-type has funct7.
+#[derive(Debug, PartialEq, Clone)]
+pub struct R4Type {
+    pub opcode: u8,
+    pub rd: u8,
+    pub func3: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub func2: u8,
+    pub rs3: u8,
+}
+
+impl From<u32> for R4Type {
+    fn from(value: u32) -> Self {
+        let opcode = extract_bits(value, 6, 0)    as u8;
+        let rd = extract_bits(value, 11, 7)       as u8;
+        let func3 = extract_bits(value, 14, 12)   as u8;
+        let rs1 = extract_bits(value, 19, 15)     as u8;
+        let rs2 = extract_bits(value, 24, 20)     as u8;
+        let func2 = extract_bits(value, 26, 25)   as u8;
+        let rs3 = extract_bits(value, 31, 27)     as u8;
+
+        Self {
+            opcode,
+            rd,
+            func3,
+            rs1,
+            rs2,
+            func2,
+            rs3
+        }
+    }
+}
+
+/// The vector arithmetic, configuration and load/store layout. For loads and stores func6
+/// holds nf, mew and mop, rs2 doubles as lumop/sumop and vd as the store data register vs3.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VType {
+    pub opcode: u8,
+    pub vd: u8,
+    pub func3: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    /// Bit 25, set for unmasked instructions.
+    pub vm: bool,
+    pub func6: u8,
+    /// Bits 30:20, the vtype immediate of vsetvli.
+    pub zimm: u16,
+}
+
+impl From<u32> for VType {
+    fn from(value: u32) -> Self {
+        let opcode = extract_bits(value, 6, 0)    as u8;
+        let vd = extract_bits(value, 11, 7)       as u8;
+        let func3 = extract_bits(value, 14, 12)   as u8;
+        let rs1 = extract_bits(value, 19, 15)     as u8;
+        let rs2 = extract_bits(value, 24, 20)     as u8;
+        let vm = extract_bits(value, 25, 25) == 1;
+        let func6 = extract_bits(value, 31, 26)   as u8;
+        let zimm = extract_bits(value, 30, 20)    as u16;
+
+        Self {
+            opcode,
+            vd,
+            func3,
+            rs1,
+            rs2,
+            vm,
+            func6,
+            zimm
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use std::{fmt, str::FromStr};
+
+use goblin::elf::Elf;
+
+use crate::stages::{DecodedInstr, Xlen};
+
+/// Extensions on top of the RV32I/RV64I base that can be switched off through the ISA string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    /// Integer multiplication and division
+    M,
+    /// Atomics
+    A,
+    /// Single precision floating point
+    F,
+    /// Double precision floating point
+    D,
+    /// Compressed instructions, also lowers the instruction alignment to 2 bytes
+    C,
+    /// Vector operations
+    V,
+    /// The CSR instructions
+    Zicsr,
+    /// FENCE.I
+    Zifencei,
+    /// Address generation (sh1add, add.uw, ...)
+    Zba,
+    /// Basic bit manipulation (andn, clz, cpop, rev8, min/max, rotates, ...)
+    Zbb,
+    /// Carry-less multiplication
+    Zbc,
+    /// Single bit instructions (bset, bclr, binv, bext)
+    Zbs,
+}
+
+impl Extension {
+    const ALL: [Extension; 12] = [
+        Extension::M, Extension::A, Extension::F, Extension::D, Extension::C, Extension::V,
+        Extension::Zicsr, Extension::Zifencei, Extension::Zba, Extension::Zbb, Extension::Zbc, Extension::Zbs,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::M => "m",
+            Extension::A => "a",
+            Extension::F => "f",
+            Extension::D => "d",
+            Extension::C => "c",
+            Extension::V => "v",
+            Extension::Zicsr => "zicsr",
+            Extension::Zifencei => "zifencei",
+            Extension::Zba => "zba",
+            Extension::Zbb => "zbb",
+            Extension::Zbc => "zbc",
+            Extension::Zbs => "zbs",
+        }
+    }
+
+    fn is_single_letter(self) -> bool {
+        self.name().len() == 1
+    }
+}
+
+/// The extensions enabled by a name in an ISA string. Extensions pull in the ones they depend
+/// on, and subsets like Zmmul or Zca enable the whole extension.
+fn implied(name: &str) -> Option<&'static [Extension]> {
+    use Extension::*;
+
+    Some(match name {
+        "i" | "zihintpause" => &[],
+        "g" => &[M, A, F, D, Zicsr, Zifencei],
+        "m" | "zmmul" => &[M],
+        "a" | "zaamo" | "zalrsc" => &[A],
+        "f" => &[F, Zicsr],
+        "d" => &[F, D, Zicsr],
+        "c" | "zca" => &[C],
+        "zcf" => &[C, F, Zicsr],
+        "zcd" => &[C, F, D, Zicsr],
+        "v" => &[F, D, V, Zicsr],
+        "zve32x" | "zve64x" | "zvl32b" | "zvl64b" | "zvl128b" => &[V, Zicsr],
+        "b" => &[Zba, Zbb, Zbs],
+        "zicsr" | "zicntr" | "zihpm" => &[Zicsr],
+        "zifencei" => &[Zifencei],
+        "zba" => &[Zba],
+        "zbb" => &[Zbb],
+        "zbc" => &[Zbc],
+        "zbs" => &[Zbs],
+        _ => return None,
+    })
+}
+
+/// Strips a version like `2p0` or `1` off the end of a multi-letter extension name.
+fn strip_version(name: &str) -> &str {
+    let major = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if major.len() == name.len() {
+        return name;
+    }
+
+    match major.strip_suffix('p') {
+        Some(rest) if rest.ends_with(|c: char| c.is_ascii_digit()) => rest.trim_end_matches(|c: char| c.is_ascii_digit()),
+        _ => major,
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum IsaError {
+    #[error("ISA string has to start with rv32i, rv64i or the g variants: {0}")]
+    InvalidBase(String),
+    #[error("Unsupported extension in ISA string: {0}")]
+    UnknownExtension(String),
+}
+
+/// The XLEN and enabled extensions, parsed from an ISA string like `rv64imac_zicsr_zifencei`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Isa {
+    pub xlen: Xlen,
+    enabled: u32,
+}
+
+impl Default for Isa {
+    /// Everything the simulator implements.
+    fn default() -> Self {
+        Self::new(Xlen::Rv64)
+    }
+}
+
+impl Isa {
+    pub fn new(xlen: Xlen) -> Self {
+        Self { xlen, enabled: Extension::ALL.iter().fold(0, |enabled, extension| enabled | extension.bit()) }
+    }
+
+    pub fn has(&self, extension: Extension) -> bool {
+        self.enabled & extension.bit() != 0
+    }
+
+    pub fn enable(&mut self, extension: Extension) {
+        self.enabled |= extension.bit();
+    }
+
+    pub fn disable(&mut self, extension: Extension) {
+        self.enabled &= !extension.bit();
+    }
+
+    /// The extensions `required` asks for that aren't enabled here.
+    pub fn missing(&self, required: &Isa) -> Vec<Extension> {
+        Extension::ALL.into_iter().filter(|extension| required.has(*extension) && !self.has(*extension)).collect()
+    }
+
+    /// The extension bits of `misa`, without the MXL field. S and U-mode are always there, B
+    /// is reported once Zba, Zbb and Zbs are all enabled.
+    pub fn misa_extensions(&self) -> u64 {
+        let letter = |letter: u8| 1 << (letter - b'a');
+        let b = self.has(Extension::Zba) && self.has(Extension::Zbb) && self.has(Extension::Zbs);
+
+        Extension::ALL.into_iter()
+            .filter(|extension| extension.is_single_letter() && self.has(*extension))
+            .fold(letter(b'i') | letter(b's') | letter(b'u'), |misa, extension| misa | letter(extension.name().as_bytes()[0]))
+            | if b { letter(b'b') } else { 0 }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let xlen = match self.xlen {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        };
+        write!(f, "rv{xlen}i")?;
+
+        for extension in Extension::ALL.into_iter().filter(|extension| self.has(*extension)) {
+            if extension.is_single_letter() {
+                write!(f, "{}", extension.name())?;
+            }
+        }
+        for extension in Extension::ALL.into_iter().filter(|extension| self.has(*extension)) {
+            if !extension.is_single_letter() {
+                write!(f, "_{}", extension.name())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Isa {
+    type Err = IsaError;
+
+    /// Also accepts the versioned strings toolchains put in ELF files, like `rv64i2p1_m2p0_zicsr2p0`.
+    fn from_str(isa: &str) -> Result<Self, Self::Err> {
+        let lowercase = isa.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(rest) = lowercase.strip_prefix("rv64") {
+            (Xlen::Rv64, rest)
+        } else if let Some(rest) = lowercase.strip_prefix("rv32") {
+            (Xlen::Rv32, rest)
+        } else {
+            return Err(IsaError::InvalidBase(isa.into()));
+        };
+
+        if !rest.starts_with(['i', 'g']) {
+            return Err(IsaError::InvalidBase(isa.into()));
+        }
+
+        let mut result = Isa { xlen, enabled: 0 };
+        let mut enable = |name: &str| match implied(name) {
+            Some(extensions) => {
+                extensions.iter().for_each(|extension| result.enable(*extension));
+                Ok(())
+            },
+            None => Err(IsaError::UnknownExtension(name.into())),
+        };
+
+        for (index, part) in rest.split('_').enumerate().filter(|(_, part)| !part.is_empty()) {
+            if index > 0 && part.starts_with(['z', 's', 'x']) {
+                enable(strip_version(part))?;
+                continue;
+            }
+
+            // A run of single letter extensions, each optionally followed by a version
+            let mut chars = part.chars().peekable();
+            while let Some(letter) = chars.next() {
+                if letter.is_ascii_digit() || letter == 'p' {
+                    return Err(IsaError::UnknownExtension(part.into()));
+                }
+                enable(letter.encode_utf8(&mut [0; 4]))?;
+
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                if chars.peek() == Some(&'p') && chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit()) {
+                    chars.next();
+                    while chars.next_if(char::is_ascii_digit).is_some() {}
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Reads the arch string from the `.riscv.attributes` section of an ELF file.
+pub fn elf_arch(elf_bytes: &[u8]) -> Option<String> {
+    let elf = Elf::parse(elf_bytes).ok()?;
+    let section = elf.section_headers.iter()
+        .find(|header| elf.shdr_strtab.get_at(header.sh_name) == Some(".riscv.attributes"))?;
+    let start = section.sh_offset as usize;
+    arch_attribute(elf_bytes.get(start..start + section.sh_size as usize)?)
+}
+
+fn uleb128(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn length_prefixed<'a>(data: &mut &'a [u8], header: usize) -> Option<&'a [u8]> {
+    let length = u32::from_le_bytes(data.get(header..header + 4)?.try_into().ok()?) as usize;
+    let contents = data.get(header + 4..length)?;
+    *data = &data[length..];
+    Some(contents)
+}
+
+/// Finds Tag_RISCV_arch in the file attributes of a build attributes section.
+fn arch_attribute(section: &[u8]) -> Option<String> {
+    let mut data = section.strip_prefix(b"A")?;
+
+    while !data.is_empty() {
+        let subsection = length_prefixed(&mut data, 0)?;
+        let Some(mut attributes) = subsection.strip_prefix(b"riscv\0") else {
+            continue;
+        };
+
+        while !attributes.is_empty() {
+            // The length covers the tag as well
+            let mut rest = attributes;
+            let tag = uleb128(&mut attributes)?;
+            let header = rest.len() - attributes.len();
+            let mut contents = length_prefixed(&mut rest, header)?;
+            attributes = rest;
+
+            // Only Tag_File applies to the whole program
+            if tag != 1 {
+                continue;
+            }
+
+            while !contents.is_empty() {
+                match uleb128(&mut contents)? {
+                    5 => {
+                        let end = contents.iter().position(|byte| *byte == 0)?;
+                        return String::from_utf8(contents[..end].to_vec()).ok();
+                    },
+                    // Odd tags hold strings, even ones numbers
+                    tag if tag % 2 == 1 => {
+                        let end = contents.iter().position(|byte| *byte == 0)?;
+                        contents = &contents[end + 1..];
+                    },
+                    _ => {
+                        uleb128(&mut contents)?;
+                    },
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// The optional extension an instruction belongs to, `None` for everything that is always
+/// available.
+pub fn required_extension(instruction: &DecodedInstr) -> Option<Extension> {
+    match instruction {
+        DecodedInstr::R(r) if r.opcode == 0b1010011 => match (r.func7, r.rs2) {
+            (0x20, 1) => Some(Extension::D), // FCVT.S.D
+            (func7, _) if func7 & 0b11 == 1 => Some(Extension::D),
+            _ => Some(Extension::F),
+        },
+        DecodedInstr::R(r) => match (r.opcode, r.func7, r.func3) {
+            (0b0110011 | 0b0111011, 0x01, _) => Some(Extension::M),
+            (0b0101111, _, _) => Some(Extension::A),
+            (0b0110011, 0x10, 0x2 | 0x4 | 0x6) => Some(Extension::Zba), // SH1ADD, SH2ADD, SH3ADD
+            (0b0110011, 0x20, 0x4 | 0x6 | 0x7) => Some(Extension::Zbb), // XNOR, ORN, ANDN
+            (0b0110011, 0x05, 0x4..=0x7) => Some(Extension::Zbb), // MIN, MINU, MAX, MAXU
+            (0b0110011, 0x30, 0x1 | 0x5) => Some(Extension::Zbb), // ROL, ROR
+            (0b0110011, 0x04, 0x4) => Some(Extension::Zbb), // ZEXT.H on RV32
+            (0b0110011, 0x05, 0x1..=0x3) => Some(Extension::Zbc), // CLMUL, CLMULR, CLMULH
+            (0b0110011, 0x14 | 0x24 | 0x34, 0x1) | (0b0110011, 0x24, 0x5) => Some(Extension::Zbs), // BSET, BCLR, BINV, BEXT
+            (0b0111011, 0x04, 0x0) | (0b0111011, 0x10, 0x2 | 0x4 | 0x6) => Some(Extension::Zba), // ADD.UW, SH*ADD.UW
+            (0b0111011, 0x04, 0x4) | (0b0111011, 0x30, 0x1 | 0x5) => Some(Extension::Zbb), // ZEXT.H, ROLW, RORW
+            _ => None,
+        },
+        // imm[11:6] tells the immediate forms apart, imm[5] is part of the shift amount on RV64
+        DecodedInstr::I(i) => match (i.opcode, i.func3, i.func7 >> 1) {
+            (0b0010011, 0x1, 0x18) => Some(Extension::Zbb), // CLZ, CTZ, CPOP, SEXT.B, SEXT.H
+            (0b0010011, 0x5, 0x18 | 0x1A) => Some(Extension::Zbb), // RORI, REV8
+            (0b0010011, 0x5, 0x0A) => Some(Extension::Zbb), // ORC.B
+            (0b0010011, 0x1, 0x0A | 0x12 | 0x1A) | (0b0010011, 0x5, 0x12) => Some(Extension::Zbs), // BSETI, BCLRI, BINVI, BEXTI
+            (0b0011011, 0x1, 0x02) => Some(Extension::Zba), // SLLI.UW
+            (0b0011011, 0x1 | 0x5, 0x18) => Some(Extension::Zbb), // CLZW, CTZW, CPOPW, RORIW
+            (0b0000111, 0x2, _) => Some(Extension::F), // FLW
+            (0b0000111, 0x3, _) => Some(Extension::D), // FLD
+            (0b1110011, 0x1..=0x7, _) => Some(Extension::Zicsr),
+            (0b0001111, 0x1, _) => Some(Extension::Zifencei),
+            _ => None,
+        },
+        DecodedInstr::S(s) => match (s.opcode, s.func) {
+            (0b0100111, 0x2) => Some(Extension::F), // FSW
+            (0b0100111, 0x3) => Some(Extension::D), // FSD
+            _ => None,
+        },
+        DecodedInstr::R4(r) if r.func2 == 1 => Some(Extension::D),
+        DecodedInstr::R4(_) => Some(Extension::F),
+        DecodedInstr::V(_) => Some(Extension::V),
+        _ => None,
+    }
+}
@@ -1,6 +1,28 @@
 pub mod components;
+pub mod bus;
+pub mod clint;
+pub mod plic;
+pub mod csr;
 pub mod stages;
 pub mod util;
 pub mod instruction_formats;
+pub mod trap;
+pub mod mmu;
+pub mod compressed;
+pub mod fpu;
+pub mod isa;
+pub mod vector;
+pub mod halt;
+pub mod htif;
+pub mod uart;
+pub mod virtio;
+pub mod syscall;
+pub mod linux;
+pub mod newlib;
+pub mod semihosting;
 pub use components::{CPU, CPUError, MemoryError};
-pub use stages::{DecodeError, ExecuteError};
\ No newline at end of file
+pub use stages::{DecodeError, ExecuteError, Xlen};
+pub use halt::HaltConfig;
+
+#[cfg(test)]
+mod tests;
\ No newline at end of file
@@ -0,0 +1,387 @@
+use std::{collections::hash_map::RandomState, fs, hash::{BuildHasher, Hasher}, time::{Instant, SystemTime, UNIX_EPOCH}};
+
+use goblin::elf::{program_header::{PT_LOAD, PT_PHDR}, Elf};
+
+use crate::{components::Memory, stages::Xlen, syscall::*};
+
+/// Room for the stack below the top of memory, mmap hands out memory below it.
+const STACK_SIZE: u64 = 1 << 20;
+
+// Syscall numbers of the generic Linux ABI, which RISC-V uses
+const SYS_GETCWD: u64 = 17;
+const SYS_IOCTL: u64 = 29;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_READV: u64 = 65;
+const SYS_WRITEV: u64 = 66;
+const SYS_NEWFSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_SET_TID_ADDRESS: u64 = 96;
+const SYS_SET_ROBUST_LIST: u64 = 99;
+const SYS_CLOCK_GETTIME: u64 = 113;
+const SYS_SCHED_YIELD: u64 = 124;
+const SYS_RT_SIGACTION: u64 = 134;
+const SYS_RT_SIGPROCMASK: u64 = 135;
+const SYS_UNAME: u64 = 160;
+const SYS_GETPID: u64 = 172;
+const SYS_GETPPID: u64 = 173;
+const SYS_GETUID: u64 = 174;
+const SYS_GETEUID: u64 = 175;
+const SYS_GETGID: u64 = 176;
+const SYS_GETEGID: u64 = 177;
+const SYS_GETTID: u64 = 178;
+const SYS_BRK: u64 = 214;
+const SYS_MUNMAP: u64 = 215;
+const SYS_MMAP: u64 = 222;
+const SYS_MPROTECT: u64 = 226;
+const SYS_MADVISE: u64 = 233;
+const SYS_GETRANDOM: u64 = 278;
+/// RV32 only has the 64 bit time variant.
+const SYS_CLOCK_GETTIME64: u64 = 403;
+
+const AT_FDCWD: i32 = -100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+const O_ACCMODE: u64 = 0b11;
+const O_CREAT: u64 = 0o100;
+const O_EXCL: u64 = 0o200;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+const TIOCGWINSZ: u64 = 0x5413;
+const ERANGE: i64 = 34;
+
+const CLOCK_REALTIME: u64 = 0;
+
+// Auxiliary vector entries
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_HWCAP: u64 = 16;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// The ISA letters Linux reports in AT_HWCAP.
+const HWCAP_LETTERS: u64 = {
+    let mut mask = 0;
+    let letters = b"imafdcv";
+    let mut i = 0;
+    while i < letters.len() {
+        mask |= 1 << (letters[i] - b'a');
+        i += 1;
+    }
+    mask
+};
+
+/// A Linux process around the guest program: its arguments and environment, open files and
+/// the memory handed out through brk and mmap.
+#[derive(Debug)]
+pub struct Linux {
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+    pub files: FileTable,
+    /// The current program break, it starts right after the highest loaded segment.
+    pub brk: u64,
+    brk_start: u64,
+    /// mmap allocations grow down from here towards the program break.
+    mmap_top: u64,
+    start: Instant,
+}
+
+impl Linux {
+    /// `args` includes the program name as the first element.
+    pub fn new(args: Vec<String>, env: Vec<String>) -> Self {
+        Self {
+            args,
+            env,
+            files: FileTable::default(),
+            brk: 0,
+            brk_start: 0,
+            mmap_top: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Lays out argc, argv, envp and the auxiliary vector at the top of memory like the kernel
+    /// does, giving back the initial stack pointer. `None` when memory is too small.
+    pub fn setup(&mut self, elf: &Elf, mem: &mut Memory, xlen: Xlen, misa: u64) -> Option<u64> {
+        let loads = || elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD);
+
+        self.brk_start = loads().map(|header| header.p_vaddr + header.p_memsz).max()?.next_multiple_of(PAGE_SIZE);
+        self.brk = self.brk_start;
+        self.mmap_top = (mem.data.len() as u64 & !(PAGE_SIZE - 1)).saturating_sub(STACK_SIZE);
+        if self.mmap_top < self.brk_start {
+            return None;
+        }
+
+        // The program headers are usually part of the first segment
+        let phoff = elf.header.e_phoff;
+        let phdr = match elf.program_headers.iter().find(|header| header.p_type == PT_PHDR) {
+            Some(header) => header.p_vaddr,
+            None => loads()
+                .find(|header| (header.p_offset..header.p_offset + header.p_filesz).contains(&phoff))
+                .map_or(0, |header| header.p_vaddr + phoff - header.p_offset),
+        };
+
+        let mut top = mem.data.len() as u64 & !0xF;
+        let mut push = |bytes: &[u8]| -> Option<u64> {
+            top = top.checked_sub(bytes.len() as u64)?;
+            guest_slice_mut(mem, top, bytes.len() as u64).ok()?.copy_from_slice(bytes);
+            Some(top)
+        };
+        let terminated = |string: &str| [string.as_bytes(), &[0]].concat();
+
+        let random = push(&random_bytes(16))?;
+        let execfn = push(&terminated(self.args.first().map_or("", String::as_str)))?;
+        let argv = self.args.iter().map(|arg| push(&terminated(arg))).collect::<Option<Vec<_>>>()?;
+        let envp = self.env.iter().map(|var| push(&terminated(var))).collect::<Option<Vec<_>>>()?;
+
+        let auxv = [
+            (AT_PHDR, phdr),
+            (AT_PHENT, elf.header.e_phentsize as u64),
+            (AT_PHNUM, elf.header.e_phnum as u64),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_ENTRY, elf.entry),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_HWCAP, misa & HWCAP_LETTERS),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random),
+            (AT_EXECFN, execfn),
+            (AT_NULL, 0),
+        ];
+
+        let words = [argv.len() as u64].into_iter()
+            .chain(argv).chain([0])
+            .chain(envp).chain([0])
+            .chain(auxv.into_iter().flat_map(|(key, value)| [key, value]))
+            .collect::<Vec<_>>();
+
+        let word = word_size(xlen);
+        let sp = top.checked_sub(words.len() as u64 * word)? & !0xF;
+        for (i, value) in words.into_iter().enumerate() {
+            write_guest(mem, sp + i as u64 * word, word, value).ok()?;
+        }
+
+        Some(sp)
+    }
+
+    pub fn syscall(&mut self, call: &SyscallArgs, mem: &mut Memory, xlen: Xlen) -> SyscallResult {
+        let [a0, a1, a2, a3, a4, a5] = call.args;
+        let word = word_size(xlen);
+
+        let result = match call.number {
+            SYS_EXIT | SYS_EXIT_GROUP => return SyscallResult::Exit(a0 as i32 as i64),
+
+            SYS_READ => self.read(mem, a0, a1, a2),
+            SYS_WRITE => self.write(mem, a0, a1, a2),
+            SYS_READV | SYS_WRITEV => self.vectored(mem, call.number == SYS_READV, a0, a1, a2, word),
+            SYS_OPENAT => guest_string(mem, a1).and_then(|path| {
+                if a0 as i32 != AT_FDCWD && !path.starts_with('/') {
+                    return Err(ENOSYS);
+                }
+
+                let mode = OpenMode {
+                    read: a2 & O_ACCMODE != 1,
+                    write: a2 & O_ACCMODE != 0,
+                    create: a2 & O_CREAT != 0,
+                    exclusive: a2 & O_EXCL != 0,
+                    truncate: a2 & O_TRUNC != 0,
+                    append: a2 & O_APPEND != 0,
+                };
+                self.files.open(&path, mode).map(|fd| fd as i64)
+            }),
+            SYS_CLOSE => self.files.close(a0).map(|_| 0),
+            // 32 bit targets get llseek, which takes the offset in two halves
+            SYS_LSEEK => match xlen {
+                Xlen::Rv64 => self.files.seek(a0, a1 as i64, a2).map(|position| position as i64),
+                Xlen::Rv32 => self.files.seek(a0, (a1 << 32 | a2) as i64, a4)
+                    .and_then(|position| write_guest(mem, a3, 8, position).map(|_| 0)),
+            },
+            SYS_FSTAT => self.files.stat(a0).and_then(|stat| write_stat(mem, a1, stat)),
+            SYS_NEWFSTATAT => guest_string(mem, a1).and_then(|path| {
+                let stat = if path.is_empty() && a3 & AT_EMPTY_PATH != 0 {
+                    self.files.stat(a0)?
+                } else {
+                    FileStat::from(&fs::metadata(path).map_err(errno)?)
+                };
+                write_stat(mem, a2, stat)
+            }),
+            SYS_IOCTL => match (a1, self.files.is_terminal(a0)) {
+                // A fixed 80x24 terminal: rows, columns and two unused pixel sizes
+                (TIOCGWINSZ, Ok(true)) => write_guest(mem, a2, 8, 80 << 16 | 24).map(|_| 0),
+                (_, Err(error)) => Err(error),
+                _ => Err(ENOTTY),
+            },
+            SYS_GETCWD => std::env::current_dir().map_err(errno).and_then(|cwd| {
+                let path = [cwd.to_string_lossy().as_bytes(), &[0]].concat();
+                if path.len() as u64 > a1 {
+                    return Err(ERANGE);
+                }
+                guest_slice_mut(mem, a0, path.len() as u64)?.copy_from_slice(&path);
+                Ok(path.len() as i64)
+            }),
+
+            SYS_BRK => Ok(self.set_brk(mem, a0) as i64),
+            SYS_MMAP => {
+                // RV32 uses mmap2, which counts the offset in pages
+                let offset = match xlen {
+                    Xlen::Rv32 => a5 * PAGE_SIZE,
+                    Xlen::Rv64 => a5,
+                };
+                self.mmap(mem, a0, a1, a3, a4, offset)
+            },
+            SYS_MUNMAP | SYS_MPROTECT | SYS_MADVISE => Ok(0),
+
+            SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => {
+                let time = match a0 {
+                    CLOCK_REALTIME => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),
+                    _ => self.start.elapsed(),
+                };
+                write_guest(mem, a1, 8, time.as_secs())
+                    .and_then(|_| write_guest(mem, a1 + 8, 8, time.subsec_nanos() as u64))
+                    .map(|_| 0)
+            },
+            SYS_GETRANDOM => guest_slice_mut(mem, a0, a1).map(|buffer| {
+                buffer.copy_from_slice(&random_bytes(buffer.len()));
+                a1 as i64
+            }),
+            SYS_UNAME => {
+                let machine = match xlen {
+                    Xlen::Rv32 => "riscv32",
+                    Xlen::Rv64 => "riscv64",
+                };
+
+                // sysname, nodename, release, version, machine and domainname, 65 bytes each
+                ["Linux", "riscv", "6.1.0", "#1", machine, "(none)"].iter().enumerate().try_for_each(|(i, field)| {
+                    let buffer = guest_slice_mut(mem, a0 + i as u64 * 65, 65)?;
+                    buffer.fill(0);
+                    buffer[..field.len()].copy_from_slice(field.as_bytes());
+                    Ok(())
+                }).map(|_| 0)
+            },
+
+            SYS_GETPID | SYS_GETTID | SYS_SET_TID_ADDRESS => Ok(std::process::id() as i64),
+            SYS_GETPPID => Ok(1),
+            SYS_GETUID | SYS_GETEUID | SYS_GETGID | SYS_GETEGID => Ok(0),
+            // Signals are never delivered and there's a single thread
+            SYS_RT_SIGACTION | SYS_RT_SIGPROCMASK | SYS_SET_ROBUST_LIST | SYS_SCHED_YIELD => Ok(0),
+
+            _ => Err(ENOSYS),
+        };
+
+        SyscallResult::Return(result.unwrap_or_else(|errno| -errno))
+    }
+
+    fn read(&mut self, mem: &mut Memory, fd: u64, address: u64, length: u64) -> Result<i64, i64> {
+        let buffer = guest_slice_mut(mem, address, length)?;
+        self.files.read(fd, buffer).map(|read| read as i64)
+    }
+
+    fn write(&mut self, mem: &Memory, fd: u64, address: u64, length: u64) -> Result<i64, i64> {
+        let buffer = guest_slice(mem, address, length)?;
+        self.files.write(fd, buffer).map(|written| written as i64)
+    }
+
+    /// readv and writev, `iov` points at `count` pairs of a base address and a length.
+    fn vectored(&mut self, mem: &mut Memory, read: bool, fd: u64, iov: u64, count: u64, word: u64) -> Result<i64, i64> {
+        let mut total = 0;
+
+        for i in 0..count {
+            let base = read_guest(mem, iov + i * 2 * word, word)?;
+            let length = read_guest(mem, iov + i * 2 * word + word, word)?;
+            let done = if read { self.read(mem, fd, base, length) } else { self.write(mem, fd, base, length) };
+
+            // Whatever was transferred before an error or a short transfer still counts
+            match done {
+                Ok(done) if done < length as i64 => return Ok(total + done),
+                Ok(done) => total += done,
+                Err(error) if total == 0 => return Err(error),
+                Err(_) => break,
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Moves the program break, an address it can't move to leaves it where it is.
+    fn set_brk(&mut self, mem: &mut Memory, address: u64) -> u64 {
+        if address < self.brk_start || address > self.mmap_top {
+            return self.brk;
+        }
+
+        // Memory given back and taken again has to read as zero
+        if address > self.brk {
+            if let Ok(grown) = guest_slice_mut(mem, self.brk, address - self.brk) {
+                grown.fill(0);
+            }
+        }
+
+        self.brk = address;
+        address
+    }
+
+    /// Maps anonymous memory or a private copy of a file. Nothing is ever unmapped.
+    fn mmap(&mut self, mem: &mut Memory, address: u64, length: u64, flags: u64, fd: u64, offset: u64) -> Result<i64, i64> {
+        let length = length.next_multiple_of(PAGE_SIZE);
+        if length == 0 {
+            return Err(EINVAL);
+        }
+
+        let address = if flags & MAP_FIXED != 0 {
+            address
+        } else {
+            let address = self.mmap_top.checked_sub(length).filter(|address| *address >= self.brk).ok_or(ENOMEM)?;
+            self.mmap_top = address;
+            address
+        };
+
+        guest_slice_mut(mem, address, length).map_err(|_| ENOMEM)?.fill(0);
+
+        if flags & MAP_ANONYMOUS == 0 {
+            let position = self.files.seek(fd, 0, 1)?;
+            self.files.seek(fd, offset as i64, 0)?;
+
+            let mut done = 0;
+            while done < length {
+                let read = self.read(mem, fd, address + done, length - done)?;
+                if read == 0 {
+                    break;
+                }
+                done += read as u64;
+            }
+
+            self.files.seek(fd, position as i64, 0)?;
+        }
+
+        Ok(address as i64)
+    }
+}
+
+/// Random bytes from the hash keys std seeds from the OS.
+fn random_bytes(length: usize) -> Vec<u8> {
+    (0..length.div_ceil(8))
+        .flat_map(|_| RandomState::new().build_hasher().finish().to_le_bytes())
+        .take(length)
+        .collect()
+}
@@ -1,26 +1,158 @@
-use std::{env, fs};
+use std::{cell::RefCell, env, fs::{self, OpenOptions}, process, rc::Rc};
 
-use cpu::{CPU, CPUError, DecodeError};
+use cpu::{clint::{Clint, Timebase, CLINT_BASE, CLINT_SIZE}, htif::Htif, plic::{Plic, PLIC_BASE, PLIC_SIZE}, isa::{elf_arch, Isa}, linux::Linux, newlib::Newlib, components::{memory_below, HOSTED_MEMORY}, semihosting::Semihosting, syscall::Syscalls, uart::{Uart, UART_BASE, UART_IRQ, UART_SIZE}, virtio::{VirtioBlock, VIRTIO_BASE, VIRTIO_IRQ, VIRTIO_SIZE}, CPU, CPUError};
+
+fn usage() -> ! {
+    eprintln!("Usage: cpu [--isa <isa string>] [--memory <MiB>] [--linux | --newlib] [--semihosting] [--uart [address]] [--clint <instructions | frequency>] [--plic] [--drive <image>] [--tohost <address>] [--test-finisher <address>] <program> [arguments...]");
+    process::exit(1);
+}
+
+fn parse_number(arg: &str) -> Option<u64> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+fn number(arg: Option<String>) -> u64 {
+    let arg = arg.unwrap_or_else(|| usage());
+
+    parse_number(&arg).unwrap_or_else(|| {
+        eprintln!("Invalid number: {}", arg);
+        process::exit(1);
+    })
+}
+
+/// Stops when a device would hide part of memory.
+fn check_device(cpu: &CPU, name: &str, base: u64, size: u64) {
+    if cpu.overlaps_memory(base, size) {
+        eprintln!("The {} at {:#x} overlaps memory, which ends at {:#x}: use less --memory", name, base, cpu.mem.data.len());
+        process::exit(1);
+    }
+}
 
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    let program_path = &args[1];
-    let full_path = env::current_dir().unwrap().join(program_path);
+    let mut args = env::args().skip(1).peekable();
+    let mut isa = Isa::default();
+    let mut tohost = None;
+    let mut test_finisher = None;
+    let mut uart = None;
+    let mut clint = None;
+    let mut plic = false;
+    let mut drive = None;
+    let mut memory = None;
+    let mut linux = false;
+    let mut newlib = false;
+    let mut semihosting = false;
+    let mut program_path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--isa" => {
+                let string = args.next().unwrap_or_else(|| usage());
+                isa = string.parse().unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                });
+            },
+            "--tohost" => tohost = Some(number(args.next())),
+            "--test-finisher" => test_finisher = Some(number(args.next())),
+            // The address is optional, what isn't a number is the program
+            "--uart" => uart = Some(args.next_if(|next| parse_number(next).is_some()).map_or(UART_BASE, |address| number(Some(address)))),
+            // mtime counts instructions, or follows the host clock at the given frequency
+            "--clint" => clint = Some(match args.next().as_deref() {
+                Some("instructions") => Timebase::Instructions,
+                frequency => Timebase::WallClock { frequency: number(frequency.map(String::from)) },
+            }),
+            "--plic" => plic = true,
+            "--drive" => drive = Some(args.next().unwrap_or_else(|| usage())),
+            "--memory" => memory = Some(number(args.next()) as usize * 1024 * 1024),
+            "--linux" => linux = true,
+            "--newlib" => newlib = true,
+            "--semihosting" => semihosting = true,
+            // Everything after the program is passed on to it
+            _ => {
+                program_path = Some(arg);
+                break;
+            },
+        }
+    }
+
+    let program_path = program_path.unwrap_or_else(|| usage());
+    let full_path = env::current_dir().unwrap().join(&program_path);
 
     let bytes = fs::read(full_path).expect("Failed to read program");
 
-    let mut cpu = CPU::new(5012*32);
+    // Without --memory, memory stops short of the devices instead of running into them
+    let devices = [
+        uart,
+        drive.as_ref().map(|_| VIRTIO_BASE),
+        plic.then_some(PLIC_BASE),
+        clint.as_ref().map(|_| CLINT_BASE),
+    ].into_iter().flatten().collect::<Vec<_>>();
+    let memory = memory.unwrap_or_else(|| memory_below(if linux || newlib { HOSTED_MEMORY } else { 5012*32 }, &devices));
+    let mut cpu = CPU::with_isa(memory, isa);
+    cpu.htif = tohost.map(|tohost| Htif::new(tohost, None));
+    cpu.halt.test_finisher = test_finisher;
+    let uart = uart.map(|base| {
+        check_device(&cpu, "UART", base, UART_SIZE);
+        let uart = Rc::new(RefCell::new(Uart::default()));
+        cpu.map(base, UART_SIZE, uart.clone());
+        uart
+    });
+    let mut plic = plic.then(Plic::new);
+    if let (Some(plic), Some(uart)) = (&mut plic, uart) {
+        plic.connect(UART_IRQ, move || uart.borrow_mut().interrupt());
+    }
+    if let Some(path) = drive {
+        let block = OpenOptions::new().read(true).write(true).open(&path).and_then(VirtioBlock::new).unwrap_or_else(|error| {
+            eprintln!("Failed to open {}: {}", path, error);
+            process::exit(1);
+        });
+        check_device(&cpu, "virtio block device", VIRTIO_BASE, VIRTIO_SIZE);
+        cpu.add_virtio_block(VIRTIO_BASE, block);
+        if let (Some(plic), Some(block)) = (&mut plic, cpu.virtio.clone()) {
+            plic.connect(VIRTIO_IRQ, move || block.borrow().interrupt());
+        }
+    }
+    if let Some(plic) = plic {
+        check_device(&cpu, "PLIC", PLIC_BASE, PLIC_SIZE);
+        cpu.add_plic(PLIC_BASE, plic);
+    }
+    if let Some(timebase) = clint {
+        check_device(&cpu, "CLINT", CLINT_BASE, CLINT_SIZE);
+        cpu.add_clint(CLINT_BASE, Clint::new(timebase));
+    }
+    let guest_args = [program_path].into_iter().chain(args).collect::<Vec<_>>();
+    if semihosting {
+        cpu.semihosting = Some(Semihosting::new(guest_args.join(" ")));
+    }
+    if linux {
+        let guest_env = env::vars().map(|(key, value)| format!("{}={}", key, value)).collect();
+        cpu.syscalls = Syscalls::Linux(Linux::new(guest_args, guest_env));
+    } else if newlib {
+        cpu.syscalls = Syscalls::Newlib(Newlib::new(guest_args));
+    }
     cpu.load_elf(&bytes).unwrap();
 
-    loop {
-        let result = cpu.cycle();
-
-        if let Err(error) = result {
-            if let CPUError::DecodeError { source: DecodeError::EndOfProgram, pc } = error {
-                println!("Program ended at PC 0x{:08x}.", pc);
-            }
+    if let Some(arch) = elf_arch(&bytes) {
+        match arch.parse::<Isa>() {
+            Ok(required) => for extension in cpu.isa.missing(&required) {
+                eprintln!("Warning: the program is built for {}, but the {} extension is disabled", arch, extension.name());
+            },
+            Err(error) => eprintln!("Warning: can't check the program's ISA {}: {}", arch, error),
+        }
+    }
 
-            break;
+    // The guest's exit code becomes ours, anything else that stops the CPU is a failure
+    loop {
+        match cpu.cycle() {
+            Ok(()) => {},
+            Err(CPUError::Exit { code }) => process::exit(code as i32),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            },
         }
     }
 }
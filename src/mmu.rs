@@ -0,0 +1,258 @@
+use crate::{components::Memory, csr::Privilege, stages::Xlen, trap::Exception};
+
+pub const PAGE_SIZE: u64 = 4096;
+const TLB_SIZE: usize = 256;
+
+pub const PTE_V: u64 = 1 << 0;
+pub const PTE_R: u64 = 1 << 1;
+pub const PTE_W: u64 = 1 << 2;
+pub const PTE_X: u64 = 1 << 3;
+pub const PTE_U: u64 = 1 << 4;
+pub const PTE_G: u64 = 1 << 5;
+pub const PTE_A: u64 = 1 << 6;
+pub const PTE_D: u64 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl AccessType {
+    pub fn page_fault(&self) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionPageFault,
+            AccessType::Load => Exception::LoadPageFault,
+            AccessType::Store => Exception::StorePageFault,
+        }
+    }
+
+    pub fn access_fault(&self) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionAccessFault,
+            AccessType::Load => Exception::LoadAccessFault,
+            AccessType::Store => Exception::StoreAccessFault,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingMode {
+    Bare,
+    Sv32,
+    Sv39,
+    Sv48,
+}
+
+impl PagingMode {
+    /// Decodes satp.MODE, `None` for modes this implementation doesn't support.
+    pub fn from_satp(satp: u64, xlen: Xlen) -> Option<Self> {
+        match xlen {
+            Xlen::Rv32 => match satp >> 31 {
+                0 => Some(PagingMode::Bare),
+                _ => Some(PagingMode::Sv32),
+            },
+            Xlen::Rv64 => match satp >> 60 {
+                0 => Some(PagingMode::Bare),
+                8 => Some(PagingMode::Sv39),
+                9 => Some(PagingMode::Sv48),
+                _ => None,
+            },
+        }
+    }
+
+    fn levels(&self) -> u32 {
+        match self {
+            PagingMode::Bare => 0,
+            PagingMode::Sv32 => 2,
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+        }
+    }
+
+    fn vpn_bits(&self) -> u32 {
+        match self {
+            PagingMode::Sv32 => 10,
+            _ => 9,
+        }
+    }
+
+    fn pte_size(&self) -> u64 {
+        match self {
+            PagingMode::Sv32 => 4,
+            _ => 8,
+        }
+    }
+
+    fn root_table(&self, satp: u64) -> u64 {
+        match self {
+            PagingMode::Sv32 => (satp & 0x3F_FFFF) * PAGE_SIZE,
+            _ => (satp & 0xFFF_FFFF_FFFF) * PAGE_SIZE,
+        }
+    }
+}
+
+/// The state of the hart that decides whether a page may be accessed.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessContext {
+    pub satp: u64,
+    pub xlen: Xlen,
+    /// The effective privilege, which takes MPRV into account for loads and stores.
+    pub privilege: Privilege,
+    pub sum: bool,
+    pub mxr: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    vpn: u64,
+    ppn: u64,
+    flags: u64,
+}
+
+/// Translates virtual addresses through the page tables in memory, caching the result of
+/// every walk per 4 KiB page in a direct-mapped TLB.
+pub struct Mmu {
+    tlb: Vec<Option<TlbEntry>>,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self { tlb: vec![None; TLB_SIZE] }
+    }
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the cached translation of one virtual address, or every translation when `None`.
+    pub fn flush(&mut self, address: Option<u64>) {
+        match address {
+            Some(address) => self.tlb[Self::tlb_index(address / PAGE_SIZE)] = None,
+            None => self.tlb.fill(None),
+        }
+    }
+
+    fn tlb_index(vpn: u64) -> usize {
+        vpn as usize % TLB_SIZE
+    }
+
+    pub fn translate(&mut self, mem: &mut Memory, address: u64, access: AccessType, context: &AccessContext) -> Result<u64, Exception> {
+        let mode = PagingMode::from_satp(context.satp, context.xlen).unwrap_or(PagingMode::Bare);
+
+        if mode == PagingMode::Bare || context.privilege == Privilege::Machine {
+            return Ok(address);
+        }
+
+        let vpn = address / PAGE_SIZE;
+        let offset = address % PAGE_SIZE;
+
+        if let Some(entry) = self.tlb[Self::tlb_index(vpn)] {
+            // A store to a clean page goes through the walk to set the dirty bit
+            if entry.vpn == vpn && (access != AccessType::Store || entry.flags & PTE_D != 0) {
+                if !permitted(entry.flags, access, context) {
+                    return Err(access.page_fault());
+                }
+
+                return Ok(entry.ppn * PAGE_SIZE + offset);
+            }
+        }
+
+        let (ppn, flags) = self.walk(mem, mode, address, access, context)?;
+        self.tlb[Self::tlb_index(vpn)] = Some(TlbEntry { vpn, ppn, flags });
+
+        Ok(ppn * PAGE_SIZE + offset)
+    }
+
+    /// Walks the page table, returning the physical page of `address` and the leaf PTE flags.
+    fn walk(&self, mem: &mut Memory, mode: PagingMode, address: u64, access: AccessType, context: &AccessContext) -> Result<(u64, u64), Exception> {
+        let levels = mode.levels();
+        let vpn_bits = mode.vpn_bits();
+        let va_bits = 12 + levels * vpn_bits;
+
+        // Sv39 and Sv48 addresses have to be sign extended from their top bit
+        if mode != PagingMode::Sv32 {
+            let top = (address as i64) >> (va_bits - 1);
+            if top != 0 && top != -1 {
+                return Err(access.page_fault());
+            }
+        }
+
+        let vpn = |level: u32| (address >> (12 + level * vpn_bits)) & ((1 << vpn_bits) - 1);
+
+        let mut table = mode.root_table(context.satp);
+        let mut level = levels - 1;
+
+        loop {
+            let pte_address = table + vpn(level) * mode.pte_size();
+            let mut pte = match mode {
+                PagingMode::Sv32 => mem.read_word(pte_address as usize),
+                _ => mem.read_double_word(pte_address as usize),
+            }.map_err(|_| access.access_fault())?;
+
+            // Bits 63:54 are reserved (Svpbmt/Svnapot aren't implemented)
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) || (mode != PagingMode::Sv32 && pte >> 54 != 0) {
+                return Err(access.page_fault());
+            }
+
+            let ppn = match mode {
+                PagingMode::Sv32 => (pte >> 10) & 0x3F_FFFF,
+                _ => (pte >> 10) & 0xFFF_FFFF_FFFF,
+            };
+
+            if pte & (PTE_R | PTE_X) == 0 {
+                if level == 0 {
+                    return Err(access.page_fault());
+                }
+
+                table = ppn * PAGE_SIZE;
+                level -= 1;
+                continue;
+            }
+
+            if !permitted(pte, access, context) {
+                return Err(access.page_fault());
+            }
+
+            // A superpage has to be aligned to its own size
+            let superpage_mask = (1 << (level * vpn_bits)) - 1;
+            if ppn & superpage_mask != 0 {
+                return Err(access.page_fault());
+            }
+
+            let dirty = access == AccessType::Store;
+            if pte & PTE_A == 0 || (dirty && pte & PTE_D == 0) {
+                let updated = pte | PTE_A | if dirty { PTE_D } else { 0 };
+
+                match mode {
+                    PagingMode::Sv32 => mem.write_word(pte_address as usize, updated),
+                    _ => mem.write_double_word(pte_address as usize, updated),
+                }.map_err(|_| access.access_fault())?;
+
+                pte = updated;
+            }
+
+            return Ok((ppn | ((address / PAGE_SIZE) & superpage_mask), pte & 0xFF));
+        }
+    }
+}
+
+fn permitted(flags: u64, access: AccessType, context: &AccessContext) -> bool {
+    let user_page = flags & PTE_U != 0;
+
+    match context.privilege {
+        Privilege::User if !user_page => return false,
+        // Supervisor mode may never execute user pages, and only touch their data with SUM set
+        Privilege::Supervisor if user_page && (access == AccessType::Fetch || !context.sum) => return false,
+        _ => {},
+    }
+
+    match access {
+        AccessType::Fetch => flags & PTE_X != 0,
+        AccessType::Load => flags & PTE_R != 0 || (context.mxr && flags & PTE_X != 0),
+        AccessType::Store => flags & PTE_W != 0,
+    }
+}
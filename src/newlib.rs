@@ -0,0 +1,154 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use goblin::elf::{program_header::PT_LOAD, Elf};
+
+use crate::{components::Memory, stages::Xlen, syscall::*};
+
+/// Room for the stack below the top of memory, the heap can't grow into it.
+const STACK_SIZE: u64 = 64 * 1024;
+
+// Syscall numbers of libgloss, the generic Linux ones plus a few of its own
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_GETTIMEOFDAY: u64 = 169;
+const SYS_BRK: u64 = 214;
+const SYS_OPEN: u64 = 1024;
+
+const AT_FDCWD: i32 = -100;
+
+// Open flags of newlib, which aren't the Linux ones
+const O_ACCMODE: u64 = 0b11;
+const O_APPEND: u64 = 0x8;
+const O_CREAT: u64 = 0x200;
+const O_TRUNC: u64 = 0x400;
+const O_EXCL: u64 = 0x800;
+
+/// newlib's own ENOSYS, the Linux value means something else there.
+const NEWLIB_ENOSYS: i64 = 88;
+
+/// The host side of a bare-metal newlib program: its arguments, open files and heap.
+#[derive(Debug)]
+pub struct Newlib {
+    pub args: Vec<String>,
+    pub files: FileTable,
+    /// The current program break, it starts right after the highest loaded segment.
+    pub brk: u64,
+    brk_start: u64,
+    /// The bottom of the stack, the break can't move past it.
+    brk_limit: u64,
+}
+
+impl Newlib {
+    /// `args` includes the program name as the first element.
+    pub fn new(args: Vec<String>) -> Self {
+        Self {
+            args,
+            files: FileTable::default(),
+            brk: 0,
+            brk_start: 0,
+            brk_limit: 0,
+        }
+    }
+
+    /// Puts argc and argv at the top of memory where crt0 expects them, giving back the initial
+    /// stack pointer. `None` when memory is too small.
+    pub fn setup(&mut self, elf: &Elf, mem: &mut Memory, xlen: Xlen) -> Option<u64> {
+        self.brk_start = elf.program_headers.iter()
+            .filter(|header| header.p_type == PT_LOAD)
+            .map(|header| header.p_vaddr + header.p_memsz)
+            .max()?
+            .next_multiple_of(16);
+        self.brk = self.brk_start;
+        self.brk_limit = (mem.data.len() as u64).checked_sub(STACK_SIZE).filter(|limit| *limit >= self.brk_start)?;
+
+        let mut top = mem.data.len() as u64 & !0xF;
+        let mut argv = Vec::new();
+        for arg in &self.args {
+            let bytes = [arg.as_bytes(), &[0]].concat();
+            top = top.checked_sub(bytes.len() as u64)?;
+            guest_slice_mut(mem, top, bytes.len() as u64).ok()?.copy_from_slice(&bytes);
+            argv.push(top);
+        }
+
+        // argc, argv and the NULL that ends it, there's no environment
+        let words = [argv.len() as u64].into_iter().chain(argv).chain([0]).collect::<Vec<_>>();
+
+        let word = word_size(xlen);
+        let sp = top.checked_sub(words.len() as u64 * word)? & !0xF;
+        for (i, value) in words.into_iter().enumerate() {
+            write_guest(mem, sp + i as u64 * word, word, value).ok()?;
+        }
+
+        Some(sp)
+    }
+
+    pub fn syscall(&mut self, call: &SyscallArgs, mem: &mut Memory, xlen: Xlen) -> SyscallResult {
+        let [a0, a1, a2, ..] = call.args;
+
+        let result = match call.number {
+            SYS_EXIT => return SyscallResult::Exit(a0 as i32 as i64),
+
+            SYS_READ => guest_slice_mut(mem, a1, a2).and_then(|buffer| self.files.read(a0, buffer)).map(|read| read as i64),
+            SYS_WRITE => guest_slice(mem, a1, a2).and_then(|buffer| self.files.write(a0, buffer)).map(|written| written as i64),
+            SYS_OPEN => self.open(mem, a0, a1),
+            SYS_OPENAT if a0 as i32 == AT_FDCWD => self.open(mem, a1, a2),
+            SYS_OPENAT => Err(NEWLIB_ENOSYS),
+            SYS_CLOSE => self.files.close(a0).map(|_| 0),
+            // off_t is a long, so only 32 bits on RV32
+            SYS_LSEEK => {
+                let offset = match xlen {
+                    Xlen::Rv32 => a1 as i32 as i64,
+                    Xlen::Rv64 => a1 as i64,
+                };
+                self.files.seek(a0, offset, a2).map(|position| position as i64)
+            },
+            SYS_FSTAT => self.files.stat(a0).and_then(|stat| write_stat(mem, a1, stat)),
+            SYS_BRK => Ok(self.set_brk(mem, a0) as i64),
+            SYS_GETTIMEOFDAY => {
+                // time_t is 64 bits on both, suseconds_t is a long. The timezone is ignored
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                write_guest(mem, a0, 8, time.as_secs())
+                    .and_then(|_| write_guest(mem, a0 + 8, word_size(xlen), time.subsec_micros() as u64))
+                    .map(|_| 0)
+            },
+
+            _ => Err(NEWLIB_ENOSYS),
+        };
+
+        SyscallResult::Return(result.unwrap_or_else(|errno| -errno))
+    }
+
+    fn open(&mut self, mem: &Memory, path: u64, flags: u64) -> Result<i64, i64> {
+        let path = guest_string(mem, path)?;
+        let mode = OpenMode {
+            read: flags & O_ACCMODE != 1,
+            write: flags & O_ACCMODE != 0,
+            create: flags & O_CREAT != 0,
+            exclusive: flags & O_EXCL != 0,
+            truncate: flags & O_TRUNC != 0,
+            append: flags & O_APPEND != 0,
+        };
+        self.files.open(&path, mode).map(|fd| fd as i64)
+    }
+
+    /// Moves the program break, an address it can't move to leaves it where it is.
+    fn set_brk(&mut self, mem: &mut Memory, address: u64) -> u64 {
+        if address < self.brk_start || address > self.brk_limit {
+            return self.brk;
+        }
+
+        if address > self.brk {
+            if let Ok(grown) = guest_slice_mut(mem, self.brk, address - self.brk) {
+                grown.fill(0);
+            }
+        }
+
+        self.brk = address;
+        address
+    }
+}
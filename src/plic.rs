@@ -0,0 +1,162 @@
+use crate::{bus::Bus, components::MemoryError, csr::{MIP_MEIP, MIP_SEIP}};
+
+/// Where QEMU's virt machine has its PLIC, the range ends right where the UART starts.
+pub const PLIC_BASE: u64 = 0x0C00_0000;
+pub const PLIC_SIZE: u64 = 0x0400_0000;
+
+/// Interrupt sources like on virt, source 0 means "no interrupt".
+pub const PLIC_SOURCES: usize = 96;
+/// Hart 0's M-mode and S-mode contexts.
+const CONTEXTS: usize = 2;
+/// Priorities 0 to 7, 0 never interrupts.
+const PRIORITY_MASK: u32 = 0b111;
+
+// Register offsets
+const PRIORITY: u64 = 0x00_0000;
+const PENDING: u64 = 0x00_1000;
+const ENABLE: u64 = 0x00_2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// The platform-level interrupt controller: level triggered sources with a priority each,
+/// routed to the machine and supervisor external interrupts of a single hart through per
+/// context enables, thresholds and claim/complete registers.
+#[derive(Default)]
+pub struct Plic {
+    priority: Vec<u32>,
+    /// One bit per source, set while its line is high.
+    pending: u128,
+    /// Sources claimed by a handler that hasn't completed them yet.
+    claimed: u128,
+    enable: [u128; CONTEXTS],
+    threshold: [u32; CONTEXTS],
+    /// Devices whose interrupt line is sampled every cycle.
+    lines: Vec<(usize, Box<dyn FnMut() -> bool>)>,
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Self {
+            priority: vec![0; PLIC_SOURCES],
+            ..Default::default()
+        }
+    }
+
+    /// Connects the interrupt line `line` to `source`, e.g. a UART's `Uart::interrupt`.
+    pub fn connect(&mut self, source: usize, line: impl FnMut() -> bool + 'static) {
+        assert!(source > 0 && source < PLIC_SOURCES, "invalid interrupt source {}", source);
+        self.lines.push((source, Box::new(line)));
+    }
+
+    /// Drives the line of `source` from outside, for devices that aren't connected.
+    pub fn set_level(&mut self, source: usize, level: bool) {
+        if source > 0 && source < PLIC_SOURCES {
+            if level {
+                self.pending |= 1 << source;
+            } else {
+                self.pending &= !(1 << source);
+            }
+        }
+    }
+
+    /// Samples the connected lines, called once per cycle. Sources that can't interrupt, with
+    /// priority 0 or enabled in no context, aren't sampled and read as not pending, so devices
+    /// the guest doesn't use are left alone.
+    pub fn update(&mut self) {
+        let enabled = self.enable.iter().fold(0, |enabled, enable| enabled | enable);
+        let mut levels = Vec::new();
+        for (source, line) in &mut self.lines {
+            let live = self.priority[*source] > 0 && (enabled >> *source) & 1 == 1;
+            levels.push((*source, live && line()));
+        }
+        for (source, level) in levels {
+            self.set_level(source, level);
+        }
+    }
+
+    /// The MEIP and SEIP bits of `mip`.
+    pub fn interrupts(&self) -> u64 {
+        let machine = if self.best(0) != 0 { MIP_MEIP } else { 0 };
+        let supervisor = if self.best(1) != 0 { MIP_SEIP } else { 0 };
+        machine | supervisor
+    }
+
+    /// The pending, enabled and unclaimed source with the highest priority above the
+    /// context's threshold, the lowest ID wins a tie. 0 when there's none.
+    fn best(&self, context: usize) -> usize {
+        let candidates = self.pending & self.enable[context] & !self.claimed;
+
+        (1..PLIC_SOURCES)
+            .filter(|source| (candidates >> source) & 1 == 1 && self.priority[*source] > self.threshold[context])
+            .fold(0, |best, source| if best == 0 || self.priority[source] > self.priority[best] { source } else { best })
+    }
+
+    fn claim(&mut self, context: usize) -> usize {
+        let source = self.best(context);
+        if source != 0 {
+            self.claimed |= 1 << source;
+        }
+        source
+    }
+}
+
+impl Bus for Plic {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, MemoryError> {
+        let word = |bits: u128, index: u64| if index < 4 { (bits >> (32 * index)) as u32 } else { 0 };
+
+        let value = match offset {
+            PRIORITY..PENDING => self.priority.get((offset / 4) as usize).copied().unwrap_or(0),
+            PENDING..ENABLE => word(self.pending, (offset - PENDING) / 4),
+            ENABLE..CONTEXT => {
+                let context = ((offset - ENABLE) / ENABLE_STRIDE) as usize;
+                let index = (offset - ENABLE) % ENABLE_STRIDE / 4;
+                self.enable.get(context).map_or(0, |enable| word(*enable, index))
+            },
+            _ => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match ((offset - CONTEXT) % CONTEXT_STRIDE, context < CONTEXTS) {
+                    (0, true) => self.threshold[context],
+                    (4, true) => self.claim(context) as u32,
+                    _ => 0,
+                }
+            },
+        };
+
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), MemoryError> {
+        let value = value as u32;
+        let sources = (1u128 << PLIC_SOURCES) - 1;
+
+        match offset {
+            PRIORITY..PENDING => if let Some(priority) = self.priority.get_mut((offset / 4) as usize).filter(|_| offset >= 4) {
+                *priority = value & PRIORITY_MASK;
+            },
+            // Pending bits only follow the sources
+            PENDING..ENABLE => {},
+            ENABLE..CONTEXT => {
+                let context = ((offset - ENABLE) / ENABLE_STRIDE) as usize;
+                let index = (offset - ENABLE) % ENABLE_STRIDE / 4;
+                if let Some(enable) = self.enable.get_mut(context).filter(|_| index < 4) {
+                    let shift = 32 * index;
+                    *enable = (*enable & !(0xFFFF_FFFF << shift)) | ((value as u128) << shift);
+                    // Source 0 doesn't exist
+                    *enable &= sources & !1;
+                }
+            },
+            _ => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match ((offset - CONTEXT) % CONTEXT_STRIDE, context < CONTEXTS) {
+                    (0, true) => self.threshold[context] = value & PRIORITY_MASK,
+                    // Completing lets the source interrupt again
+                    (4, true) if (value as usize) < PLIC_SOURCES => self.claimed &= !(1 << value),
+                    _ => {},
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
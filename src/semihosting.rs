@@ -0,0 +1,135 @@
+use std::{io::{self, Write}, time::Instant};
+
+use crate::{components::Memory, stages::Xlen, syscall::*};
+
+/// `slli x0, x0, 0x1f` right before the EBREAK.
+pub const ENTRY: u32 = 0x01f01013;
+/// `ebreak`
+pub const EBREAK: u32 = 0x00100073;
+/// `srai x0, x0, 7` right after the EBREAK.
+pub const EXIT: u32 = 0x40705013;
+
+// Operation numbers in a0
+const SYS_OPEN: u64 = 0x01;
+const SYS_CLOSE: u64 = 0x02;
+const SYS_WRITEC: u64 = 0x03;
+const SYS_WRITE0: u64 = 0x04;
+const SYS_WRITE: u64 = 0x05;
+const SYS_READ: u64 = 0x06;
+const SYS_CLOCK: u64 = 0x10;
+const SYS_GET_CMDLINE: u64 = 0x15;
+const SYS_EXIT: u64 = 0x18;
+
+/// The reason SYS_EXIT gives for a normal exit, anything else is a failure.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Semihosting on the host: the magic EBREAK sequence asks the debugger, which is us, to do
+/// I/O for the guest. Like with the syscalls pointers are physical addresses.
+#[derive(Debug)]
+pub struct Semihosting {
+    /// What SYS_GET_CMDLINE gives back, the program and its arguments.
+    pub cmdline: String,
+    pub files: FileTable,
+    start: Instant,
+}
+
+impl Semihosting {
+    pub fn new(cmdline: String) -> Self {
+        Self {
+            cmdline,
+            files: FileTable::default(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Runs the operation in a0 with the argument in a1, which mostly points to a block of
+    /// XLEN sized parameters. Failures return -1.
+    pub fn call(&mut self, operation: u64, argument: u64, mem: &mut Memory, xlen: Xlen) -> SyscallResult {
+        let word = word_size(xlen);
+
+        let result = match operation {
+            SYS_OPEN => parameters(mem, argument, word).and_then(|[path, mode, length]| {
+                let path = String::from_utf8_lossy(guest_slice(mem, path, length)?).into_owned();
+                self.open(&path, mode)
+            }),
+            // The console stays open
+            SYS_CLOSE => parameters(mem, argument, word).and_then(|[handle]| match handle {
+                0..=2 => Ok(0),
+                _ => self.files.close(handle).map(|_| 0),
+            }),
+            SYS_WRITEC => guest_slice(mem, argument, 1).and_then(|byte| self.files.write(1, byte)).map(|_| 0),
+            SYS_WRITE0 => guest_string(mem, argument).and_then(|string| self.files.write(1, string.as_bytes())).map(|_| 0),
+            // Both give back how many bytes weren't transferred
+            SYS_WRITE => parameters(mem, argument, word).and_then(|[handle, buffer, length]| {
+                let written = self.files.write(handle, guest_slice(mem, buffer, length)?)?;
+                Ok((length - written as u64) as i64)
+            }),
+            SYS_READ => parameters(mem, argument, word).and_then(|[handle, buffer, length]| {
+                let read = self.files.read(handle, guest_slice_mut(mem, buffer, length)?)?;
+                Ok((length - read as u64) as i64)
+            }),
+            SYS_CLOCK => Ok((self.start.elapsed().as_millis() / 10) as i64),
+            SYS_GET_CMDLINE => parameters(mem, argument, word).and_then(|[buffer, length]| {
+                let cmdline = [self.cmdline.as_bytes(), &[0]].concat();
+                if cmdline.len() as u64 > length {
+                    return Err(EINVAL);
+                }
+
+                guest_slice_mut(mem, buffer, cmdline.len() as u64)?.copy_from_slice(&cmdline);
+                write_guest(mem, argument + word, word, self.cmdline.len() as u64)?;
+                Ok(0)
+            }),
+            SYS_EXIT => {
+                let _ = io::stdout().flush();
+
+                // RV32 passes the reason itself, RV64 a block with the reason and the exit code
+                let code = match xlen {
+                    Xlen::Rv32 => Ok(if argument == ADP_STOPPED_APPLICATION_EXIT { 0 } else { 1 }),
+                    Xlen::Rv64 => parameters(mem, argument, word).map(|[reason, code]| match reason {
+                        ADP_STOPPED_APPLICATION_EXIT => code as i32 as i64,
+                        _ => 1,
+                    }),
+                };
+                return SyscallResult::Exit(code.unwrap_or(1));
+            },
+
+            _ => Err(ENOSYS),
+        };
+
+        SyscallResult::Return(result.unwrap_or(-1))
+    }
+
+    /// Opens a file with one of the twelve fopen modes, ":tt" is the console.
+    fn open(&mut self, path: &str, mode: u64) -> Result<i64, i64> {
+        if path == ":tt" {
+            // Reading is stdin, writing stdout and appending stderr
+            return match mode {
+                0..=3 => Ok(0),
+                4..=7 => Ok(1),
+                8..=11 => Ok(2),
+                _ => Err(EINVAL),
+            };
+        }
+
+        // r, r+, w, w+, a and a+, every one of them with and without b
+        let mode = match mode / 2 {
+            0 => OpenMode { read: true, ..Default::default() },
+            1 => OpenMode { read: true, write: true, ..Default::default() },
+            2 => OpenMode { write: true, create: true, truncate: true, ..Default::default() },
+            3 => OpenMode { read: true, write: true, create: true, truncate: true, ..Default::default() },
+            4 => OpenMode { append: true, create: true, ..Default::default() },
+            5 => OpenMode { read: true, append: true, create: true, ..Default::default() },
+            _ => return Err(EINVAL),
+        };
+        self.files.open(path, mode).map(|handle| handle as i64)
+    }
+}
+
+/// Reads the first `N` words of a parameter block.
+fn parameters<const N: usize>(mem: &Memory, address: u64, word: u64) -> Result<[u64; N], i64> {
+    let mut parameters = [0; N];
+    for (i, parameter) in parameters.iter_mut().enumerate() {
+        *parameter = read_guest(mem, address + i as u64 * word, word)?;
+    }
+    Ok(parameters)
+}
@@ -1,7 +1,15 @@
-use crate::{components::{Memory, MemoryError, ProgramCounter}, instruction_formats::{BType, IType, JType, RType, SType, UType}, util::extract_bits};
+use crate::{components::{Memory, MemoryError, ProgramCounter}, compressed::{expand, is_compressed}, fpu::{self, Format, RoundingMode, SignInjection}, instruction_formats::{BType, IType, JType, R4Type, RType, SType, UType, VType}, util::extract_bits, vector::VectorEffect};
 
+/// Fetches the instruction at the PC, only the low 16 bits are set for compressed instructions.
 pub fn fetch_instruction(pc: &ProgramCounter, memory: &Memory) -> Result<u32, MemoryError> {
-    memory.read_word(pc.address as usize).map(|data| data as u32)
+    let low = memory.read_half_word(pc.address as usize, false)? as u32;
+
+    if is_compressed(low) {
+        return Ok(low);
+    }
+
+    let high = memory.read_half_word(pc.address as usize + 2, false)? as u32;
+    Ok(low | (high << 16))
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -11,15 +19,17 @@ pub enum DecodedInstr {
     S(SType),
     B(BType),
     U(UType),
-    J(JType)
+    J(JType),
+    R4(R4Type),
+    V(VType),
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum DecodeError {
     #[error("Unknown opcode: {0:8x}")]
     UnknownOpcode(u8),
-    #[error("End of program")]
-    EndOfProgram,
+    #[error("Illegal compressed instruction: {0:04x}")]
+    IllegalCompressed(u16),
 }
 
 pub fn decode_instruction(instruction: u32) -> Result<DecodedInstr, DecodeError> {
@@ -38,13 +48,33 @@ pub fn decode_instruction(instruction: u32) -> Result<DecodedInstr, DecodeError>
         0b1110011 => Ok(DecodedInstr::I(IType::from(instruction))),
         0b0011011 => Ok(DecodedInstr::I(IType::from(instruction))),
         0b0111011 => Ok(DecodedInstr::R(RType::from(instruction))),
+        0b0101111 => Ok(DecodedInstr::R(RType::from(instruction))),
+        // The vector loads and stores share the FP opcodes, with widths the FP ones don't use
+        0b0000111 | 0b0100111 if matches!(extract_bits(instruction, 14, 12), 0b000 | 0b101 | 0b110 | 0b111) => Ok(DecodedInstr::V(VType::from(instruction))),
+        0b0000111 => Ok(DecodedInstr::I(IType::from(instruction))),
+        0b0100111 => Ok(DecodedInstr::S(SType::from(instruction))),
+        0b1010111 => Ok(DecodedInstr::V(VType::from(instruction))),
+        0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => Ok(DecodedInstr::R4(R4Type::from(instruction))),
+        0b1010011 => Ok(DecodedInstr::R(RType::from(instruction))),
         0b0001111 => Ok(DecodedInstr::I(IType::from(instruction))),
-        0b1111111 => Err(DecodeError::EndOfProgram),
         _ => Err(DecodeError::UnknownOpcode(opcode))
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Decodes a 16 bit instruction through the 32 bit instruction it expands to.
+pub fn decode_compressed(instruction: u16, xlen: Xlen) -> Result<DecodedInstr, DecodeError> {
+    let expanded = expand(instruction, xlen).ok_or(DecodeError::IllegalCompressed(instruction))?;
+    decode_instruction(expanded)
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Xlen {
+    Rv32,
+    #[default]
+    Rv64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MemSize {
     Byte,
     Half,
@@ -52,6 +82,17 @@ pub enum MemSize {
     Double,
 }
 
+impl MemSize {
+    pub fn bytes(&self) -> u64 {
+        match self {
+            MemSize::Byte => 1,
+            MemSize::Half => 2,
+            MemSize::Word => 4,
+            MemSize::Double => 8,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct WriteMem {
     pub address: u64,
@@ -65,6 +106,8 @@ pub struct ReadMem {
     pub size: MemSize,
     pub rd: u8,
     pub signed: bool,
+    /// FLW and FLD load into the floating point registers.
+    pub fp: bool,
 }
 
 #[derive(Debug, PartialEq)]
@@ -73,6 +116,95 @@ pub struct WriteBack {
     pub value: u64
 }
 
+#[derive(Debug, PartialEq)]
+pub enum CsrOp {
+    Write,
+    Set,
+    Clear,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CsrAccess {
+    pub csr: u16,
+    pub rd: u8,
+    pub op: CsrOp,
+    pub value: u64,
+    /// CSRRS/CSRRC with rs1 = x0 (or a zero immediate) only read the CSR.
+    pub write: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AmoOp {
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+impl AmoOp {
+    /// Combines the value in memory with the operand from rs2, word sized operations only look
+    /// at the low 32 bits of both.
+    pub fn apply(&self, loaded: u64, operand: u64, size: &MemSize) -> u64 {
+        let (loaded, operand) = match size {
+            MemSize::Word => (loaded as i32 as i64, operand as i32 as i64),
+            _ => (loaded as i64, operand as i64),
+        };
+        let unsigned = |value: i64| match size {
+            MemSize::Word => value as u32 as u64,
+            _ => value as u64,
+        };
+
+        match self {
+            AmoOp::Swap => operand as u64,
+            AmoOp::Add => loaded.wrapping_add(operand) as u64,
+            AmoOp::Xor => (loaded ^ operand) as u64,
+            AmoOp::And => (loaded & operand) as u64,
+            AmoOp::Or => (loaded | operand) as u64,
+            AmoOp::Min => loaded.min(operand) as u64,
+            AmoOp::Max => loaded.max(operand) as u64,
+            AmoOp::Minu => unsigned(loaded).min(unsigned(operand)),
+            AmoOp::Maxu => unsigned(loaded).max(unsigned(operand)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AtomicOp {
+    LoadReserved,
+    StoreConditional,
+    Amo(AmoOp),
+}
+
+/// A read-modify-write of naturally aligned memory, rd receives the sign extended old value
+/// (or the SC success code).
+#[derive(Debug, PartialEq)]
+pub struct AtomicAccess {
+    pub address: u64,
+    pub size: MemSize,
+    pub rd: u8,
+    pub op: AtomicOp,
+    pub value: u64,
+}
+
+/// Instructions that change the privileged state rather than registers or memory.
+#[derive(Debug, PartialEq)]
+pub enum SystemOp {
+    Ecall,
+    Ebreak,
+    Sret,
+    Mret,
+    Wfi,
+    /// Flushes the cached translations of one virtual address, or all of them when `None`.
+    SfenceVma { address: Option<u64> },
+    /// Makes earlier stores visible to instruction fetches.
+    FenceI,
+}
+
 #[derive(Default)]
 pub struct ExecuteResult {
     // pub alu_result: Option<u32>,
@@ -80,6 +212,13 @@ pub struct ExecuteResult {
     pub write_mem: Option<WriteMem>,
     pub write_back: Option<WriteBack>,
     pub branch_addr: Option<u64>,
+    pub csr: Option<CsrAccess>,
+    pub system: Option<SystemOp>,
+    pub atomic: Option<AtomicAccess>,
+    pub fp_write_back: Option<WriteBack>,
+    /// Exception flags to accrue in `fflags`.
+    pub fflags: u8,
+    pub vector: Option<VectorEffect>,
 }
 
 impl ExecuteResult {
@@ -102,6 +241,36 @@ impl ExecuteResult {
         self.branch_addr = Some(branch);
         self
     }
+
+    pub fn with_csr(mut self, csr: CsrAccess) -> Self {
+        self.csr = Some(csr);
+        self
+    }
+
+    pub fn with_system(mut self, system: SystemOp) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    pub fn with_atomic(mut self, atomic: AtomicAccess) -> Self {
+        self.atomic = Some(atomic);
+        self
+    }
+
+    pub fn with_fp_write_back(mut self, write_back: WriteBack) -> Self {
+        self.fp_write_back = Some(write_back);
+        self
+    }
+
+    pub fn with_fflags(mut self, fflags: u8) -> Self {
+        self.fflags = fflags;
+        self
+    }
+
+    pub fn with_vector(mut self, vector: VectorEffect) -> Self {
+        self.vector = Some(vector);
+        self
+    }
 }
 
 pub fn execute_r(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult> {
@@ -119,7 +288,7 @@ pub fn execute_r(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult>
             },
             (0x00, 0x1) => { // SLL Shift Left Logical
                 Some(ExecuteResult::default()
-                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val << (rs2_val & 0x1F)) as u64})
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val << (rs2_val & 0x3F)) as u64})
                 )
             },
             (0x00, 0x2) => { // SLT Set less than
@@ -139,12 +308,12 @@ pub fn execute_r(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult>
             },
             (0x00, 0x5) => { // SRL Shift right logical
                 Some(ExecuteResult::default()
-                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u64) >> (rs2_val & 0x1f)) as u64})
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64) >> (rs2_val & 0x3F)})
                 )
             },
             (0x20, 0x5) => { // SRA Shift right arithmetic
                 Some(ExecuteResult::default()
-                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as i64) >> (rs2_val & 0x1f)) as u64})
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val >> (rs2_val & 0x3F)) as u64})
                 )
             },
             (0x00, 0x6) => { // OR
@@ -156,9 +325,291 @@ pub fn execute_r(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult>
                 Some(ExecuteResult::default()
                     .with_write_back(WriteBack { rd: r.rd, value: (rs1_val & rs2_val) as u64})
                 )
-            }
+            },
+            (0x10, 0x2) => { // SH1ADD Shift left by 1 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val << 1).wrapping_add(rs2_val) as u64 })
+                )
+            },
+            (0x10, 0x4) => { // SH2ADD Shift left by 2 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val << 2).wrapping_add(rs2_val) as u64 })
+                )
+            },
+            (0x10, 0x6) => { // SH3ADD Shift left by 3 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val << 3).wrapping_add(rs2_val) as u64 })
+                )
+            },
+            (0x20, 0x4) => { // XNOR Exclusive NOR
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: !(rs1_val ^ rs2_val) as u64 })
+                )
+            },
+            (0x20, 0x6) => { // ORN OR with inverted operand
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val | !rs2_val) as u64 })
+                )
+            },
+            (0x20, 0x7) => { // ANDN AND with inverted operand
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val & !rs2_val) as u64 })
+                )
+            },
+            (0x05, 0x4) => { // MIN Minimum
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: rs1_val.min(rs2_val) as u64 })
+                )
+            },
+            (0x05, 0x5) => { // MINU Minimum unsigned
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64).min(rs2_val as u64) })
+                )
+            },
+            (0x05, 0x6) => { // MAX Maximum
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: rs1_val.max(rs2_val) as u64 })
+                )
+            },
+            (0x05, 0x7) => { // MAXU Maximum unsigned
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64).max(rs2_val as u64) })
+                )
+            },
+            (0x30, 0x1) => { // ROL Rotate left
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64).rotate_left((rs2_val & 0x3F) as u32) })
+                )
+            },
+            (0x30, 0x5) => { // ROR Rotate right
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64).rotate_right((rs2_val & 0x3F) as u32) })
+                )
+            },
+            (0x05, 0x1) => { // CLMUL Carry-less multiply (low half)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: carry_less_mul(rs1_val as u64, rs2_val as u64) as u64 })
+                )
+            },
+            (0x05, 0x2) => { // CLMULR Carry-less multiply (reversed)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (carry_less_mul(rs1_val as u64, rs2_val as u64) >> 63) as u64 })
+                )
+            },
+            (0x05, 0x3) => { // CLMULH Carry-less multiply (high half)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (carry_less_mul(rs1_val as u64, rs2_val as u64) >> 64) as u64 })
+                )
+            },
+            (0x14, 0x1) => { // BSET Set bit
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64) | (1 << (rs2_val & 0x3F)) })
+                )
+            },
+            (0x24, 0x1) => { // BCLR Clear bit
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64) & !(1 << (rs2_val & 0x3F)) })
+                )
+            },
+            (0x34, 0x1) => { // BINV Invert bit
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u64) ^ (1 << (rs2_val & 0x3F)) })
+                )
+            },
+            (0x24, 0x5) => { // BEXT Extract bit
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u64) >> (rs2_val & 0x3F)) & 1 })
+                )
+            },
+            (0x01, 0x0) => { // MUL Multiply
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: rs1_val.wrapping_mul(rs2_val) as u64 })
+                )
+            },
+            (0x01, 0x1) => { // MULH Multiply high (signed x signed)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (((rs1_val as i128) * (rs2_val as i128)) >> 64) as u64 })
+                )
+            },
+            (0x01, 0x2) => { // MULHSU Multiply high (signed x unsigned)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (((rs1_val as i128) * (rs2_val as u64 as i128)) >> 64) as u64 })
+                )
+            },
+            (0x01, 0x3) => { // MULHU Multiply high (unsigned x unsigned)
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (((rs1_val as u64 as u128) * (rs2_val as u64 as u128)) >> 64) as u64 })
+                )
+            },
+            (0x01, 0x4) => { // DIV Divide
+                let value = match rs2_val {
+                    0 => -1,
+                    _ => rs1_val.wrapping_div(rs2_val),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as u64 })
+                )
+            },
+            (0x01, 0x5) => { // DIVU Divide unsigned
+                let value = match rs2_val {
+                    0 => u64::MAX,
+                    _ => (rs1_val as u64) / (rs2_val as u64),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value })
+                )
+            },
+            (0x01, 0x6) => { // REM Remainder
+                let value = match rs2_val {
+                    0 => rs1_val,
+                    _ => rs1_val.wrapping_rem(rs2_val),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as u64 })
+                )
+            },
+            (0x01, 0x7) => { // REMU Remainder unsigned
+                let value = match rs2_val {
+                    0 => rs1_val as u64,
+                    _ => (rs1_val as u64) % (rs2_val as u64),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value })
+                )
+            },
             _ => None
-        }
+        },
+        0b0111011 => match (r.func7, r.func3) {
+            (0x00, 0x0) => { // ADDW Add word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as i32).wrapping_add(rs2_val as i32) as i64 as u64 })
+                )
+            },
+            (0x20, 0x0) => { // SUBW Subtract word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as i32).wrapping_sub(rs2_val as i32) as i64 as u64 })
+                )
+            },
+            (0x00, 0x1) => { // SLLW Shift left logical word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as i32) << (rs2_val & 0x1F)) as i64 as u64 })
+                )
+            },
+            (0x00, 0x5) => { // SRLW Shift right logical word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u32) >> (rs2_val & 0x1F)) as i32 as i64 as u64 })
+                )
+            },
+            (0x20, 0x5) => { // SRAW Shift right arithmetic word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as i32) >> (rs2_val & 0x1F)) as i64 as u64 })
+                )
+            },
+            (0x04, 0x0) => { // ADD.UW Add unsigned word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u32 as u64).wrapping_add(rs2_val as u64) })
+                )
+            },
+            (0x10, 0x2) => { // SH1ADD.UW Shift unsigned word left by 1 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u32 as u64) << 1).wrapping_add(rs2_val as u64) })
+                )
+            },
+            (0x10, 0x4) => { // SH2ADD.UW Shift unsigned word left by 2 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u32 as u64) << 2).wrapping_add(rs2_val as u64) })
+                )
+            },
+            (0x10, 0x6) => { // SH3ADD.UW Shift unsigned word left by 3 and add
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: ((rs1_val as u32 as u64) << 3).wrapping_add(rs2_val as u64) })
+                )
+            },
+            (0x04, 0x4) if r.rs2 == 0 => { // ZEXT.H Zero extend half word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: rs1_val as u16 as u64 })
+                )
+            },
+            (0x30, 0x1) => { // ROLW Rotate left word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u32).rotate_left((rs2_val & 0x1F) as u32) as i32 as i64 as u64 })
+                )
+            },
+            (0x30, 0x5) => { // RORW Rotate right word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as u32).rotate_right((rs2_val & 0x1F) as u32) as i32 as i64 as u64 })
+                )
+            },
+            (0x01, 0x0) => { // MULW Multiply word
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: (rs1_val as i32).wrapping_mul(rs2_val as i32) as i64 as u64 })
+                )
+            },
+            (0x01, 0x4) => { // DIVW Divide word
+                let value = match rs2_val as i32 {
+                    0 => -1,
+                    divisor => (rs1_val as i32).wrapping_div(divisor),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as i64 as u64 })
+                )
+            },
+            (0x01, 0x5) => { // DIVUW Divide word unsigned
+                let value = match rs2_val as u32 {
+                    0 => u32::MAX,
+                    divisor => (rs1_val as u32) / divisor,
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as i32 as i64 as u64 })
+                )
+            },
+            (0x01, 0x6) => { // REMW Remainder word
+                let value = match rs2_val as i32 {
+                    0 => rs1_val as i32,
+                    divisor => (rs1_val as i32).wrapping_rem(divisor),
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as i64 as u64 })
+                )
+            },
+            (0x01, 0x7) => { // REMUW Remainder word unsigned
+                let value = match rs2_val as u32 {
+                    0 => rs1_val as u32,
+                    divisor => (rs1_val as u32) % divisor,
+                };
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: r.rd, value: value as i32 as i64 as u64 })
+                )
+            },
+            _ => None
+        },
+        0b0101111 => {
+            // func7 holds funct5 and the aq/rl bits, which don't matter with a single hart
+            let size = match r.func3 {
+                0x2 => MemSize::Word,
+                0x3 => MemSize::Double,
+                _ => return None,
+            };
+
+            let op = match r.func7 >> 2 {
+                0b00010 if r.rs2 == 0 => AtomicOp::LoadReserved, // LR Load reserved
+                0b00011 => AtomicOp::StoreConditional, // SC Store conditional
+                0b00001 => AtomicOp::Amo(AmoOp::Swap), // AMOSWAP Atomic swap
+                0b00000 => AtomicOp::Amo(AmoOp::Add), // AMOADD Atomic add
+                0b00100 => AtomicOp::Amo(AmoOp::Xor), // AMOXOR Atomic xor
+                0b01100 => AtomicOp::Amo(AmoOp::And), // AMOAND Atomic and
+                0b01000 => AtomicOp::Amo(AmoOp::Or), // AMOOR Atomic or
+                0b10000 => AtomicOp::Amo(AmoOp::Min), // AMOMIN Atomic minimum
+                0b10100 => AtomicOp::Amo(AmoOp::Max), // AMOMAX Atomic maximum
+                0b11000 => AtomicOp::Amo(AmoOp::Minu), // AMOMINU Atomic minimum unsigned
+                0b11100 => AtomicOp::Amo(AmoOp::Maxu), // AMOMAXU Atomic maximum unsigned
+                _ => return None,
+            };
+
+            Some(ExecuteResult::default()
+                .with_atomic(AtomicAccess { address: rs1_val as u64, size, rd: r.rd, op, value: rs2_val as u64 })
+            )
+        },
         _ => None
     }
 }
@@ -174,32 +625,37 @@ pub fn execute_i(i: &IType, rs1_val: i64, pc: u64) -> Option<ExecuteResult> {
         0b0000011 => match i.func3 {
             0x0 => { // LB Load byte
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Byte, rd: i.rd, signed: true })
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Byte, rd: i.rd, signed: true, fp: false })
                 )
             },
             0x1 => { // LH Load half word
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Half, rd: i.rd, signed: true })
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Half, rd: i.rd, signed: true, fp: false })
                 )
             },
             0x2 => { // LW Load word
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Word, rd: i.rd, signed: true })
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Word, rd: i.rd, signed: true, fp: false })
                 )
             },
             0x4 => { // LBU Load byte unsigned
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Byte, rd: i.rd, signed: false })
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Byte, rd: i.rd, signed: false, fp: false })
                 )
             },
             0x5 => { // LHU Load half word unsigned
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Half, rd: i.rd, signed: false })
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Half, rd: i.rd, signed: false, fp: false })
+                )
+            },
+            0x6 => { // LWU Load word unsigned
+                Some(ExecuteResult::default()
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Word, rd: i.rd, signed: false, fp: false })
                 )
             },
             0x3 => { // LD Load double
                 Some(ExecuteResult::default()
-                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Double, rd: i.rd, signed: true }),
+                    .with_read_mem(ReadMem { address: rs1_val.wrapping_add(i.imm as i64) as u64, size: MemSize::Double, rd: i.rd, signed: true, fp: false }),
                 )
             }
             _ => None
@@ -235,18 +691,55 @@ pub fn execute_i(i: &IType, rs1_val: i64, pc: u64) -> Option<ExecuteResult> {
                     .with_write_back(WriteBack { rd: i.rd, value: (rs1_val & i.imm as i64) as u64 })
                 )
             },
-            0x1 => { // SLLI Shift left logical immediate
-                Some(ExecuteResult::default()
-                    .with_write_back(WriteBack { rd: i.rd, value: (rs1_val << (i.shamt & 0x1f)) as u64 })
-                )
+            0x1 => {
+                match i.func7 >> 1 {
+                    0x00 => Some(ExecuteResult::default() // SLLI Shift left logical immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val << i.shamt) as u64 })
+                    ),
+                    0x18 => { // The unary Zbb instructions use rs2 as a second function code
+                        let value = match i.shamt {
+                            0 => (rs1_val as u64).leading_zeros() as u64, // CLZ Count leading zeros
+                            1 => (rs1_val as u64).trailing_zeros() as u64, // CTZ Count trailing zeros
+                            2 => (rs1_val as u64).count_ones() as u64, // CPOP Count set bits
+                            4 => rs1_val as i8 as u64, // SEXT.B Sign extend byte
+                            5 => rs1_val as i16 as u64, // SEXT.H Sign extend half word
+                            _ => return None,
+                        };
+                        Some(ExecuteResult::default()
+                            .with_write_back(WriteBack { rd: i.rd, value })
+                        )
+                    },
+                    0x0A => Some(ExecuteResult::default() // BSETI Set bit immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64) | (1 << i.shamt) })
+                    ),
+                    0x12 => Some(ExecuteResult::default() // BCLRI Clear bit immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64) & !(1 << i.shamt) })
+                    ),
+                    0x1A => Some(ExecuteResult::default() // BINVI Invert bit immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64) ^ (1 << i.shamt) })
+                    ),
+                    _ => None
+                }
             },
-            0x5 => { 
-                match i.func7 {
-                    0x0 => Some(ExecuteResult::default() // SRLI Shift right logical immediate
-                        .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as u64) >> (i.shamt & 0x1f)) as u64 })
+            0x5 => { // RV64 uses imm[11:6] as funct6, bit 5 belongs to the shift amount
+                match i.func7 >> 1 {
+                    0x00 => Some(ExecuteResult::default() // SRLI Shift right logical immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64) >> i.shamt })
+                    ),
+                    0x10 => Some(ExecuteResult::default() // SRAI Shift right arithmetic immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val >> i.shamt) as u64})
+                    ),
+                    0x18 => Some(ExecuteResult::default() // RORI Rotate right immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64).rotate_right(i.shamt as u32) })
+                    ),
+                    0x12 => Some(ExecuteResult::default() // BEXTI Extract bit immediate
+                        .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as u64) >> i.shamt) & 1 })
+                    ),
+                    0x0A if i.shamt == 0x07 => Some(ExecuteResult::default() // ORC.B OR-combine bytes
+                        .with_write_back(WriteBack { rd: i.rd, value: or_combine(rs1_val as u64) })
                     ),
-                    0x20 => Some(ExecuteResult::default() // SRAI Shift right arithmetic immediate
-                        .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as i64) >> (i.shamt & 0x1f)) as u64})
+                    0x1A if i.shamt == 0x38 => Some(ExecuteResult::default() // REV8 Reverse bytes
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u64).swap_bytes() })
                     ),
                     _ => None
                 }
@@ -256,42 +749,87 @@ pub fn execute_i(i: &IType, rs1_val: i64, pc: u64) -> Option<ExecuteResult> {
         0b0001111 => {
             match i.func3 {
                 0x0 => { // FENCE Wait until all memory operations finished
-                    None
+                    // There's a single hart and every access completes before the next
+                    // instruction, so memory is always ordered already
+                    Some(ExecuteResult::default())
                 },
-                0x1 => { // FENCE.I Synchronize the instruction cache with memoryr
-                    None
+                0x1 => { // FENCE.I Synchronize the instruction cache with memory
+                    Some(ExecuteResult::default()
+                        .with_system(SystemOp::FenceI)
+                    )
                 },
                 _ => None,
             }
         },
         0b1110011 => {
+            // The CSR address is the unsigned 12 bit immediate, the immediate forms reuse rs1 as a 5 bit uimm
+            let csr_address = (i.imm as u16) & 0xFFF;
+
             match i.func3 {
+                0x0 if i.rd != 0 => None,
+                0x0 if i.func7 == 0x09 => { // SFENCE.VMA Supervisor memory-management fence
+                    Some(ExecuteResult::default()
+                        .with_system(SystemOp::SfenceVma { address: (i.rs1 != 0).then_some(rs1_val as u64) })
+                    )
+                },
+                0x0 if i.rs1 != 0 => None,
                 0x0 => match i.imm {
                     0x0 => { // ECALL Environment Call
-                        None
+                        Some(ExecuteResult::default()
+                            .with_system(SystemOp::Ecall)
+                        )
                     },
                     0x1 => { // EBREAK Environment Breakpoint
-                        None
+                        Some(ExecuteResult::default()
+                            .with_system(SystemOp::Ebreak)
+                        )
+                    },
+                    0x102 => { // SRET Supervisor-mode trap return
+                        Some(ExecuteResult::default()
+                            .with_system(SystemOp::Sret)
+                        )
+                    },
+                    0x302 => { // MRET Machine-mode trap return
+                        Some(ExecuteResult::default()
+                            .with_system(SystemOp::Mret)
+                        )
+                    },
+                    0x105 => { // WFI Wait for interrupt
+                        Some(ExecuteResult::default()
+                            .with_system(SystemOp::Wfi)
+                        )
                     },
                     _ => None
                 },
                 0x1 => { // CSRRW CSR Read/Write
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Write, value: rs1_val as u64, write: true })
+                    )
                 },
                 0x2 => { // CSRRS CSR Read/Set
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Set, value: rs1_val as u64, write: i.rs1 != 0 })
+                    )
                 },
                 0x3 => { // CSRRC CSR Read/Clear
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Clear, value: rs1_val as u64, write: i.rs1 != 0 })
+                    )
                 },
                 0x5 => { // CSRRWI CSR Read/Write Immediate
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Write, value: i.rs1 as u64, write: true })
+                    )
                 },
                 0x6 => { // CSRRSI CSR Read/Set Immediate
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Set, value: i.rs1 as u64, write: i.rs1 != 0 })
+                    )
                 },
                 0x7 => { // CSRRCI CSR Read/Clear Immediate
-                    None
+                    Some(ExecuteResult::default()
+                        .with_csr(CsrAccess { csr: csr_address, rd: i.rd, op: CsrOp::Clear, value: i.rs1 as u64, write: i.rs1 != 0 })
+                    )
                 }
                 _ => None
             }
@@ -300,21 +838,41 @@ pub fn execute_i(i: &IType, rs1_val: i64, pc: u64) -> Option<ExecuteResult> {
             match i.func3 {
                 0x0 => { // ADDIW Add immediate word
                     Some(ExecuteResult::default()
-                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val.wrapping_add(i.imm as i64)) as u64 })
+                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as i32).wrapping_add(i.imm) as i64 as u64 })
                     )
                 },
-                0x1 => { // SLLI Shift left logical immediate
-                    Some(ExecuteResult::default()
-                        .with_write_back(WriteBack { rd: i.rd, value: (rs1_val << (i.shamt & 0x1f)) as u64 })
-                    )
+                0x1 => {
+                    match i.func7 {
+                        0x00 => Some(ExecuteResult::default() // SLLIW Shift left logical immediate word
+                            .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as i32) << i.shamt) as i64 as u64 })
+                        ),
+                        0x04 | 0x05 => Some(ExecuteResult::default() // SLLI.UW Shift left unsigned word immediate
+                            .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u32 as u64) << i.shamt })
+                        ),
+                        0x30 => {
+                            let value = match i.shamt {
+                                0 => (rs1_val as u32).leading_zeros(), // CLZW Count leading zeros in word
+                                1 => (rs1_val as u32).trailing_zeros(), // CTZW Count trailing zeros in word
+                                2 => (rs1_val as u32).count_ones(), // CPOPW Count set bits in word
+                                _ => return None,
+                            };
+                            Some(ExecuteResult::default()
+                                .with_write_back(WriteBack { rd: i.rd, value: value as u64 })
+                            )
+                        },
+                        _ => None
+                    }
                 },
-                0x5 => { 
+                0x5 => { // Word shifts only take a 5 bit shift amount, imm[5] set is reserved
                     match i.func7 {
-                        0x0 => Some(ExecuteResult::default() // SRLI Shift right logical immediate
-                            .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as u64) >> (i.shamt & 0x1f)) as u64 })
+                        0x00 => Some(ExecuteResult::default() // SRLIW Shift right logical immediate word
+                            .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as u32) >> i.shamt) as i32 as i64 as u64 })
+                        ),
+                        0x20 => Some(ExecuteResult::default() // SRAIW Shift right arithmetic immediate word
+                            .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as i32) >> i.shamt) as i64 as u64 })
                         ),
-                        0x20 => Some(ExecuteResult::default() // SRAI Shift right arithmetic immediate
-                            .with_write_back(WriteBack { rd: i.rd, value: ((rs1_val as i64) >> (i.shamt & 0x1f)) as u64})
+                        0x30 => Some(ExecuteResult::default() // RORIW Rotate right immediate word
+                            .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u32).rotate_right(i.shamt as u32) as i32 as i64 as u64 })
                         ),
                         _ => None
                     }
@@ -395,7 +953,7 @@ pub fn execute_b(b: &BType, rs1_val: i64, rs2_val: i64, pc: u64) -> Option<Execu
                 }
             },
             0x6 => { // BLTU Branch if lesser than (unsigned)
-                if (rs1_val as u32) < (rs2_val as u32) {
+                if (rs1_val as u64) < (rs2_val as u64) {
                     Some(ExecuteResult::default()
                         .with_branch(pc.wrapping_add(b.imm as u64))
                     )
@@ -404,7 +962,7 @@ pub fn execute_b(b: &BType, rs1_val: i64, rs2_val: i64, pc: u64) -> Option<Execu
                 }
             },
             0x7 => { // BGEU Branch if greater than or equal (unsigned)
-                if (rs1_val as u32) >= (rs2_val as u32) {
+                if (rs1_val as u64) >= (rs2_val as u64) {
                     Some(ExecuteResult::default()
                         .with_branch(pc.wrapping_add(b.imm as u64))
                     )
@@ -438,7 +996,7 @@ pub fn execute_j(j: &JType, pc: u64) -> Option<ExecuteResult> {
     match j.opcode {
         0b1101111 => { //JAL Jump and link
             Some(ExecuteResult::default()
-                .with_write_back(WriteBack { rd: j.rd, value: pc + 4 })
+                .with_write_back(WriteBack { rd: j.rd, value: pc.wrapping_add(4) })
                 .with_branch(pc.wrapping_add(j.imm as u64))
             )
         }
@@ -466,5 +1024,319 @@ pub fn execute(instruction: &DecodedInstr, rs1_val: i64, rs2_val: i64, pc: u64)
             .ok_or(ExecuteError::UnimplementedInstruction { instr_type: "U".into(), instruction: instruction.clone() }),
         DecodedInstr::J(j) => execute_j(j, pc)
             .ok_or(ExecuteError::UnimplementedInstruction { instr_type: "J".into(), instruction: instruction.clone() }),
+        DecodedInstr::R4(_) => Err(ExecuteError::UnimplementedInstruction { instr_type: "R4".into(), instruction: instruction.clone() }),
+        DecodedInstr::V(_) => Err(ExecuteError::UnimplementedInstruction { instr_type: "V".into(), instruction: instruction.clone() }),
+    }
+}
+
+/// The full 128 bit carry-less product, CLMUL, CLMULH and CLMULR each pick 64 bits of it.
+fn carry_less_mul(a: u64, b: u64) -> u128 {
+    (0..64)
+        .filter(|bit| (b >> bit) & 1 == 1)
+        .fold(0, |product, bit| product ^ ((a as u128) << bit))
+}
+
+/// Sets every byte that has at least one bit set to 0xFF.
+fn or_combine(value: u64) -> u64 {
+    value.to_le_bytes().iter().rev().fold(0, |result, byte| (result << 8) | if *byte != 0 { 0xFF } else { 0 })
+}
+
+fn execute_clmul32(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult> {
+    let product = carry_less_mul(rs1_val as u32 as u64, rs2_val as u32 as u64);
+    let value = match r.func3 {
+        0x2 => product >> 31, // CLMULR
+        0x3 => product >> 32, // CLMULH
+        _ => return None
+    };
+
+    Some(ExecuteResult::default()
+        .with_write_back(WriteBack { rd: r.rd, value: value as u64 })
+    )
+}
+
+fn execute_mulh32(r: &RType, rs1_val: i64, rs2_val: i64) -> Option<ExecuteResult> {
+    // A 32x32 bit product always fits in 64 bits, so the high half is just the upper word
+    let product = match r.func3 {
+        0x1 => (rs1_val as i32 as i64).wrapping_mul(rs2_val as i32 as i64), // MULH
+        0x2 => (rs1_val as i32 as i64).wrapping_mul(rs2_val as u32 as i64), // MULHSU
+        0x3 => ((rs1_val as u32 as u64) * (rs2_val as u32 as u64)) as i64, // MULHU
+        _ => return None
+    };
+
+    Some(ExecuteResult::default()
+        .with_write_back(WriteBack { rd: r.rd, value: (product >> 32) as u64 })
+    )
+}
+
+/// Executes an instruction with RV32 semantics. Registers are expected to hold their 32 bit
+/// value sign extended to 64 bits, which makes the RV32 arithmetic identical to the RV64 word
+/// (`*W`) instructions. RV64-only encodings are rejected and every result is truncated back
+/// to 32 bits.
+pub fn execute_rv32(instruction: &DecodedInstr, rs1_val: i64, rs2_val: i64, pc: u64) -> Result<ExecuteResult, ExecuteError> {
+    let unimplemented = |instr_type: &str| ExecuteError::UnimplementedInstruction { instr_type: instr_type.into(), instruction: instruction.clone() };
+
+    let mut result = match instruction {
+        DecodedInstr::R(r) if r.opcode == 0b0111011 => return Err(unimplemented("R")),
+        DecodedInstr::R(r) if r.opcode == 0b0101111 && r.func3 == 0x3 => return Err(unimplemented("R")), // *.D atomics
+        DecodedInstr::R(r) if r.opcode == 0b0101111 => execute_r(r, rs1_val, rs2_val).ok_or_else(|| unimplemented("R"))?,
+        DecodedInstr::R(r) => match (r.func7, r.func3) {
+            (0x00 | 0x20, 0x0 | 0x1 | 0x5) | (0x01, 0x0 | 0x4..=0x7) | (0x30, 0x1 | 0x5) | (0x04, 0x4)
+                => execute_r(&RType { opcode: 0b0111011, ..r.clone() }, rs1_val, rs2_val),
+            (0x01, 0x1..=0x3) => execute_mulh32(r, rs1_val, rs2_val),
+            (0x05, 0x2 | 0x3) => execute_clmul32(r, rs1_val, rs2_val),
+            (0x14 | 0x24 | 0x34, _) => execute_r(r, rs1_val, rs2_val & 0x1F), // Bit indices are mod XLEN
+            _ => execute_r(r, rs1_val, rs2_val),
+        }.ok_or_else(|| unimplemented("R"))?,
+        DecodedInstr::I(i) => match (i.opcode, i.func3) {
+            (0b0011011, _) | (0b0000011, 0x3 | 0x6) => return Err(unimplemented("I")), // *IW, LD, LWU
+            (0b0010011, 0x1) if matches!(i.imm, 0x604 | 0x605) => execute_i(i, rs1_val, pc), // SEXT.B, SEXT.H
+            (0b0010011, 0x5) if i.imm == 0x698 => Some(ExecuteResult::default() // REV8 only swaps 4 bytes on RV32
+                .with_write_back(WriteBack { rd: i.rd, value: (rs1_val as u32).swap_bytes() as u64 })
+            ),
+            // The single bit instructions and ORC.B, with the shift amount below 32
+            (0b0010011, 0x1 | 0x5) if matches!(i.func7, 0x14 | 0x24 | 0x34) => execute_i(i, rs1_val, pc),
+            (0b0010011, 0x0 | 0x1 | 0x5) => execute_i(&IType { opcode: 0b0011011, ..i.clone() }, rs1_val, pc),
+            _ => execute_i(i, rs1_val, pc),
+        }.ok_or_else(|| unimplemented("I"))?,
+        DecodedInstr::S(s) if s.func == 0x3 => return Err(unimplemented("S")), // SD
+        _ => execute(instruction, rs1_val, rs2_val, pc)?,
+    };
+
+    if let Some(write_back) = &mut result.write_back {
+        write_back.value = write_back.value as i32 as u64;
+    }
+    if let Some(read_mem) = &mut result.read_mem {
+        read_mem.address &= 0xFFFF_FFFF;
+    }
+    if let Some(write_mem) = &mut result.write_mem {
+        write_mem.address &= 0xFFFF_FFFF;
+    }
+    if let Some(branch_addr) = &mut result.branch_addr {
+        *branch_addr &= 0xFFFF_FFFF;
+    }
+    if let Some(atomic) = &mut result.atomic {
+        atomic.address &= 0xFFFF_FFFF;
+    }
+    if let Some(SystemOp::SfenceVma { address: Some(address) }) = &mut result.system {
+        *address &= 0xFFFF_FFFF;
+    }
+
+    Ok(result)
+}
+
+/// Whether the instruction belongs to the F or D extension and has to go through `execute_fp`.
+pub fn is_floating_point(instruction: &DecodedInstr) -> bool {
+    let opcode = match instruction {
+        DecodedInstr::R(r) => r.opcode,
+        DecodedInstr::I(i) => i.opcode,
+        DecodedInstr::S(s) => s.opcode,
+        DecodedInstr::R4(_) => return true,
+        _ => return false,
+    };
+
+    matches!(opcode, 0b0000111 | 0b0100111 | 0b1010011)
+}
+
+/// Source operands of a floating point instruction. Only rs1 can come from the integer
+/// registers, as base address or as the source of a move or conversion.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FpOperands {
+    pub x_rs1: u64,
+    pub f_rs1: u64,
+    pub f_rs2: u64,
+    pub f_rs3: u64,
+}
+
+fn fp_format(fmt: u8) -> Option<Format> {
+    match fmt {
+        0b00 => Some(Format::Single),
+        0b01 => Some(Format::Double),
+        _ => None,
+    }
+}
+
+/// The static rounding mode of the instruction, or `frm` for the dynamic mode 0b111.
+fn rounding_mode(rm: u8, frm: u8) -> Option<RoundingMode> {
+    RoundingMode::from_bits(if rm == 0b111 { frm } else { rm })
+}
+
+fn fp_result(rd: u8, format: Format, (bits, fflags): (u64, u8)) -> Option<ExecuteResult> {
+    let value = match format {
+        Format::Single => fpu::nan_box(bits),
+        Format::Double => bits,
+    };
+
+    Some(ExecuteResult::default()
+        .with_fp_write_back(WriteBack { rd, value })
+        .with_fflags(fflags)
+    )
+}
+
+fn int_result(rd: u8, (value, fflags): (u64, u8)) -> Option<ExecuteResult> {
+    Some(ExecuteResult::default()
+        .with_write_back(WriteBack { rd, value })
+        .with_fflags(fflags)
+    )
+}
+
+pub fn execute_fp_load(i: &IType, rs1_val: u64) -> Option<ExecuteResult> {
+    let address = rs1_val.wrapping_add(i.imm as i64 as u64);
+
+    match i.func3 {
+        0x2 => { // FLW Load float
+            Some(ExecuteResult::default()
+                .with_read_mem(ReadMem { address, size: MemSize::Word, rd: i.rd, signed: false, fp: true })
+            )
+        },
+        0x3 => { // FLD Load double
+            Some(ExecuteResult::default()
+                .with_read_mem(ReadMem { address, size: MemSize::Double, rd: i.rd, signed: false, fp: true })
+            )
+        },
+        _ => None
     }
-}
\ No newline at end of file
+}
+
+pub fn execute_fp_store(s: &SType, rs1_val: u64, rs2_val: u64) -> Option<ExecuteResult> {
+    let address = rs1_val.wrapping_add(s.imm as i64 as u64);
+
+    match s.func {
+        0x2 => { // FSW Store float
+            Some(ExecuteResult::default()
+                .with_write_mem(WriteMem { address, data: rs2_val, size: MemSize::Word })
+            )
+        },
+        0x3 => { // FSD Store double
+            Some(ExecuteResult::default()
+                .with_write_mem(WriteMem { address, data: rs2_val, size: MemSize::Double })
+            )
+        },
+        _ => None
+    }
+}
+
+pub fn execute_fma(r: &R4Type, operands: &FpOperands, frm: u8) -> Option<ExecuteResult> {
+    let format = fp_format(r.func2)?;
+    let rm = rounding_mode(r.func3, frm)?;
+
+    let (negate_product, negate_addend) = match r.opcode {
+        0b1000011 => (false, false), // FMADD Fused multiply-add
+        0b1000111 => (false, true), // FMSUB Fused multiply-subtract
+        0b1001011 => (true, false), // FNMSUB Fused negative multiply-subtract
+        0b1001111 => (true, true), // FNMADD Fused negative multiply-add
+        _ => return None,
+    };
+
+    let a = fpu::unbox(format, operands.f_rs1);
+    let b = fpu::unbox(format, operands.f_rs2);
+    let c = fpu::unbox(format, operands.f_rs3);
+
+    fp_result(r.rd, format, fpu::fused_mul_add(format, a, b, c, negate_product, negate_addend, rm))
+}
+
+/// The OP-FP opcode, func7 holds funct5 and the 2 bit format.
+pub fn execute_op_fp(r: &RType, operands: &FpOperands, frm: u8, xlen: Xlen) -> Option<ExecuteResult> {
+    let format = fp_format(r.func7 & 0b11)?;
+    let a = fpu::unbox(format, operands.f_rs1);
+    let b = fpu::unbox(format, operands.f_rs2);
+    let rm = || rounding_mode(r.func3, frm);
+    let rv64 = xlen == Xlen::Rv64;
+
+    match r.func7 >> 2 {
+        0b00000 => fp_result(r.rd, format, fpu::add(format, a, b, rm()?)), // FADD
+        0b00001 => fp_result(r.rd, format, fpu::sub(format, a, b, rm()?)), // FSUB
+        0b00010 => fp_result(r.rd, format, fpu::mul(format, a, b, rm()?)), // FMUL
+        0b00011 => fp_result(r.rd, format, fpu::div(format, a, b, rm()?)), // FDIV
+        0b01011 if r.rs2 == 0 => fp_result(r.rd, format, fpu::sqrt(format, a, rm()?)), // FSQRT
+        0b00100 => { // FSGNJ, FSGNJN, FSGNJX Sign injection
+            let op = match r.func3 {
+                0x0 => SignInjection::Copy,
+                0x1 => SignInjection::Negate,
+                0x2 => SignInjection::Xor,
+                _ => return None,
+            };
+            fp_result(r.rd, format, (fpu::sign_inject(format, a, b, op), 0))
+        },
+        0b00101 => match r.func3 { // FMIN, FMAX
+            0x0 => fp_result(r.rd, format, fpu::min_max(format, a, b, false)),
+            0x1 => fp_result(r.rd, format, fpu::min_max(format, a, b, true)),
+            _ => None
+        },
+        0b01000 => { // FCVT.S.D, FCVT.D.S Convert between precisions
+            let from = match (format, r.rs2) {
+                (Format::Single, 1) => Format::Double,
+                (Format::Double, 0) => Format::Single,
+                _ => return None,
+            };
+            fp_result(r.rd, format, fpu::convert(from, format, fpu::unbox(from, operands.f_rs1), rm()?))
+        },
+        0b10100 => { // FEQ, FLT, FLE Compare
+            let (result, fflags) = match r.func3 {
+                0x2 => fpu::eq(format, a, b),
+                0x1 => fpu::lt(format, a, b),
+                0x0 => fpu::le(format, a, b),
+                _ => return None,
+            };
+            int_result(r.rd, (result as u64, fflags))
+        },
+        0b11100 if r.rs2 == 0 => match (r.func3, format) {
+            // FMV.X.W moves the raw bits, without checking the NaN-boxing
+            (0x0, Format::Single) => int_result(r.rd, (operands.f_rs1 as i32 as u64, 0)), // FMV.X.W
+            (0x0, Format::Double) if rv64 => int_result(r.rd, (operands.f_rs1, 0)), // FMV.X.D
+            (0x1, _) => int_result(r.rd, (fpu::classify(format, a), 0)), // FCLASS
+            _ => None
+        },
+        0b11000 => { // FCVT.W, FCVT.WU, FCVT.L, FCVT.LU Convert to integer
+            let (signed, width) = match r.rs2 {
+                0 => (true, 32),
+                1 => (false, 32),
+                2 if rv64 => (true, 64),
+                3 if rv64 => (false, 64),
+                _ => return None,
+            };
+            let (value, fflags) = fpu::to_int(format, a, signed, width, rm()?);
+            // Even the unsigned word conversion is sign extended
+            let value = if width == 32 { value as i32 as u64 } else { value };
+            int_result(r.rd, (value, fflags))
+        },
+        0b11010 => { // FCVT.S.W, FCVT.S.WU, FCVT.S.L, FCVT.S.LU Convert from integer
+            let (signed, width) = match r.rs2 {
+                0 => (true, 32),
+                1 => (false, 32),
+                2 if rv64 => (true, 64),
+                3 if rv64 => (false, 64),
+                _ => return None,
+            };
+            fp_result(r.rd, format, fpu::from_int(format, operands.x_rs1, signed, width, rm()?))
+        },
+        0b11110 if r.rs2 == 0 && r.func3 == 0 => match format {
+            Format::Single => fp_result(r.rd, format, (operands.x_rs1 & 0xFFFF_FFFF, 0)), // FMV.W.X
+            Format::Double if rv64 => fp_result(r.rd, format, (operands.x_rs1, 0)), // FMV.D.X
+            _ => None
+        },
+        _ => None
+    }
+}
+
+pub fn execute_fp(instruction: &DecodedInstr, operands: &FpOperands, frm: u8, xlen: Xlen) -> Result<ExecuteResult, ExecuteError> {
+    let result = match instruction {
+        DecodedInstr::I(i) => execute_fp_load(i, operands.x_rs1),
+        DecodedInstr::S(s) => execute_fp_store(s, operands.x_rs1, operands.f_rs2),
+        DecodedInstr::R4(r) => execute_fma(r, operands, frm),
+        DecodedInstr::R(r) => execute_op_fp(r, operands, frm, xlen),
+        _ => None,
+    };
+
+    let mut result = result.ok_or(ExecuteError::UnimplementedInstruction { instr_type: "FP".into(), instruction: instruction.clone() })?;
+
+    if xlen == Xlen::Rv32 {
+        if let Some(read_mem) = &mut result.read_mem {
+            read_mem.address &= 0xFFFF_FFFF;
+        }
+        if let Some(write_mem) = &mut result.write_mem {
+            write_mem.address &= 0xFFFF_FFFF;
+        }
+    }
+
+    Ok(result)
+}
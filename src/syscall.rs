@@ -0,0 +1,280 @@
+use std::{fs::{self, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, sync::{mpsc::{self, Receiver}, Mutex, MutexGuard, OnceLock}, thread};
+
+use crate::{components::Memory, linux::Linux, newlib::Newlib, stages::Xlen};
+
+pub const PAGE_SIZE: u64 = 4096;
+
+// The errno values of Linux, newlib shares everything below ENOSYS
+pub const ENOENT: i64 = 2;
+pub const EIO: i64 = 5;
+pub const EBADF: i64 = 9;
+pub const ENOMEM: i64 = 12;
+pub const EFAULT: i64 = 14;
+pub const EINVAL: i64 = 22;
+pub const ENOTTY: i64 = 25;
+pub const ESPIPE: i64 = 29;
+pub const ENOSYS: i64 = 38;
+
+// File type bits of st_mode
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+
+/// Who services an ECALL before it traps to the guest.
+#[derive(Debug, Default)]
+pub enum Syscalls {
+    /// ECALLs trap like on real hardware.
+    #[default]
+    None,
+    /// Linux user-mode emulation, like qemu-user.
+    Linux(Linux),
+    /// The libgloss syscalls of bare-metal newlib programs, like Spike's proxy kernel.
+    Newlib(Newlib),
+}
+
+/// What happens after a syscall was serviced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallResult {
+    /// The value for a0, negative errno values for failures.
+    Return(i64),
+    Exit(i64),
+}
+
+/// The syscall number from a7 and the arguments from a0-a5, zero extended on RV32.
+pub struct SyscallArgs {
+    pub number: u64,
+    pub args: [u64; 6],
+}
+
+impl SyscallArgs {
+    pub fn new(regs: &[u64; 32], xlen: Xlen) -> Self {
+        let mask = match xlen {
+            Xlen::Rv32 => 0xFFFF_FFFF,
+            Xlen::Rv64 => u64::MAX,
+        };
+
+        Self {
+            number: regs[17] & mask,
+            args: std::array::from_fn(|i| regs[10 + i] & mask),
+        }
+    }
+}
+
+/// Stdin, read on a separate thread so devices can check for input without blocking. The
+/// UART, HTIF and guest reads of fd 0 all take their bytes from here, the thread starts the
+/// first time anyone asks.
+pub fn stdin() -> MutexGuard<'static, Receiver<u8>> {
+    static STDIN: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+
+    let receiver = STDIN.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                let Ok(byte) = byte else { break };
+                if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    });
+
+    receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Reads like a terminal does: waits for the first byte, then takes whatever else already
+/// arrived. Zero bytes means end of file.
+fn read_stdin(buffer: &mut [u8]) -> usize {
+    let input = stdin();
+    let mut count = 0;
+
+    while count < buffer.len() {
+        let byte = if count == 0 { input.recv().ok() } else { input.try_recv().ok() };
+        let Some(byte) = byte else { break };
+        buffer[count] = byte;
+        count += 1;
+    }
+
+    count
+}
+
+/// The errno for a failed host operation.
+pub fn errno(error: io::Error) -> i64 {
+    error.raw_os_error().map_or(EIO, i64::from)
+}
+
+pub fn guest_slice(mem: &Memory, address: u64, length: u64) -> Result<&[u8], i64> {
+    let start = usize::try_from(address).map_err(|_| EFAULT)?;
+    let end = start.checked_add(length as usize).ok_or(EFAULT)?;
+    mem.data.get(start..end).ok_or(EFAULT)
+}
+
+pub fn guest_slice_mut(mem: &mut Memory, address: u64, length: u64) -> Result<&mut [u8], i64> {
+    let start = usize::try_from(address).map_err(|_| EFAULT)?;
+    let end = start.checked_add(length as usize).ok_or(EFAULT)?;
+    mem.data.get_mut(start..end).ok_or(EFAULT)
+}
+
+/// Reads a NUL terminated string.
+pub fn guest_string(mem: &Memory, address: u64) -> Result<String, i64> {
+    let start = usize::try_from(address).map_err(|_| EFAULT)?;
+    let bytes = mem.data.get(start..).ok_or(EFAULT)?;
+    let length = bytes.iter().position(|byte| *byte == 0).ok_or(EFAULT)?;
+    Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+}
+
+pub fn word_size(xlen: Xlen) -> u64 {
+    match xlen {
+        Xlen::Rv32 => 4,
+        Xlen::Rv64 => 8,
+    }
+}
+
+/// Reads a little endian integer of `size` bytes.
+pub fn read_guest(mem: &Memory, address: u64, size: u64) -> Result<u64, i64> {
+    let bytes = guest_slice(mem, address, size)?;
+    Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64))
+}
+
+/// Writes `value` as a little endian integer of `size` bytes.
+pub fn write_guest(mem: &mut Memory, address: u64, size: u64, value: u64) -> Result<(), i64> {
+    guest_slice_mut(mem, address, size)?.copy_from_slice(&value.to_le_bytes()[..size as usize]);
+    Ok(())
+}
+
+/// Fills in a `struct stat` of the generic Linux ABI, which RV32, RV64 and libgloss share.
+pub fn write_stat(mem: &mut Memory, address: u64, stat: FileStat) -> Result<i64, i64> {
+    let buffer = guest_slice_mut(mem, address, 128)?;
+    buffer.fill(0);
+    buffer[16..20].copy_from_slice(&stat.mode.to_le_bytes()); // st_mode
+    buffer[20..24].copy_from_slice(&1u32.to_le_bytes()); // st_nlink
+    buffer[48..56].copy_from_slice(&stat.size.to_le_bytes()); // st_size
+    buffer[56..60].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes()); // st_blksize
+    buffer[64..72].copy_from_slice(&stat.size.div_ceil(512).to_le_bytes()); // st_blocks
+    Ok(0)
+}
+
+/// How a file is opened, independent of the flag encoding of the guest's ABI.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpenMode {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    pub exclusive: bool,
+    pub truncate: bool,
+    pub append: bool,
+}
+
+/// What fstat reports about a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub mode: u32,
+    pub size: u64,
+}
+
+impl From<&fs::Metadata> for FileStat {
+    fn from(metadata: &fs::Metadata) -> Self {
+        let kind = if metadata.is_dir() { S_IFDIR } else { S_IFREG };
+        let permissions = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+        Self { mode: kind | permissions, size: metadata.len() }
+    }
+}
+
+#[derive(Debug)]
+enum HostFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(fs::File),
+}
+
+/// The host files a guest has open, indexed by descriptor. 0, 1 and 2 are the host's stdio.
+#[derive(Debug)]
+pub struct FileTable {
+    files: Vec<Option<HostFile>>,
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self { files: vec![Some(HostFile::Stdin), Some(HostFile::Stdout), Some(HostFile::Stderr)] }
+    }
+}
+
+impl FileTable {
+    fn get(&mut self, fd: u64) -> Result<&mut HostFile, i64> {
+        self.files.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)
+    }
+
+    /// Opens a host file on the lowest free descriptor.
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> Result<u64, i64> {
+        let file = OpenOptions::new()
+            .read(mode.read)
+            .write(mode.write || mode.append)
+            .append(mode.append)
+            .truncate(mode.truncate)
+            .create(mode.create && !mode.exclusive)
+            .create_new(mode.create && mode.exclusive)
+            .open(path)
+            .map_err(errno)?;
+
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            },
+        };
+        self.files[fd] = Some(HostFile::File(file));
+        Ok(fd as u64)
+    }
+
+    pub fn close(&mut self, fd: u64) -> Result<(), i64> {
+        self.get(fd)?;
+        self.files[fd as usize] = None;
+        Ok(())
+    }
+
+    pub fn read(&mut self, fd: u64, buffer: &mut [u8]) -> Result<usize, i64> {
+        match self.get(fd)? {
+            HostFile::Stdin => Ok(read_stdin(buffer)),
+            HostFile::File(file) => file.read(buffer),
+            HostFile::Stdout | HostFile::Stderr => return Err(EBADF),
+        }.map_err(errno)
+    }
+
+    pub fn write(&mut self, fd: u64, buffer: &[u8]) -> Result<usize, i64> {
+        match self.get(fd)? {
+            HostFile::Stdout => io::stdout().write_all(buffer).and_then(|_| io::stdout().flush()).map(|_| buffer.len()),
+            HostFile::Stderr => io::stderr().write_all(buffer).map(|_| buffer.len()),
+            HostFile::File(file) => file.write(buffer),
+            HostFile::Stdin => return Err(EBADF),
+        }.map_err(errno)
+    }
+
+    /// Seeks with the usual `whence` values: 0 for SEEK_SET, 1 for SEEK_CUR and 2 for SEEK_END.
+    pub fn seek(&mut self, fd: u64, offset: i64, whence: u64) -> Result<u64, i64> {
+        let position = match whence {
+            0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| EINVAL)?),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+
+        match self.get(fd)? {
+            HostFile::File(file) => file.seek(position).map_err(errno),
+            _ => Err(ESPIPE),
+        }
+    }
+
+    pub fn stat(&mut self, fd: u64) -> Result<FileStat, i64> {
+        match self.get(fd)? {
+            HostFile::File(file) => Ok(FileStat::from(&file.metadata().map_err(errno)?)),
+            // Character devices make the C library line buffer stdout like on a terminal
+            _ => Ok(FileStat { mode: S_IFCHR | 0o620, size: 0 }),
+        }
+    }
+
+    pub fn is_terminal(&mut self, fd: u64) -> Result<bool, i64> {
+        Ok(!matches!(self.get(fd)?, HostFile::File(_)))
+    }
+}
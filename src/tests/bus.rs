@@ -0,0 +1,94 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{bus::*, components::*, trap::*};
+
+/// Answers every load with its offset plus one and remembers the stores.
+#[derive(Default)]
+struct Recorder {
+    loads: usize,
+    stores: Vec<(u64, u64, u64)>,
+}
+
+impl Bus for Recorder {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, MemoryError> {
+        self.loads += 1;
+        Ok(offset + 1)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), MemoryError> {
+        self.stores.push((offset, size, value));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_memory_map() {
+    let mut map = MemoryMap::default();
+    map.map(0x1000, 0x10, Rom::new(vec![0x11, 0x22, 0x33, 0x44]));
+    map.map(0x2000, 0x1000, Memory::new(0x1000));
+
+    assert_eq!(map.load(0x1000, 4).unwrap(), 0x4433_2211);
+    assert_eq!(map.load(0x1001, 2).unwrap(), 0x3322);
+    assert!(matches!(map.store(0x1000, 1, 0), Err(MemoryError::ReadOnly { address: 0 })));
+
+    map.store(0x2FF8, 8, 0x0123_4567_89AB_CDEF).unwrap();
+    assert_eq!(map.load(0x2FFC, 4).unwrap(), 0x0123_4567);
+
+    // Inside the region but past the ROM's data, and outside any region
+    assert!(map.load(0x100C, 4).is_err());
+    assert!(matches!(map.load(0x3000, 1), Err(MemoryError::Unmapped { address: 0x3000 })));
+    assert!(map.find(0xFFF).is_none());
+    assert_eq!(map.find(0x2010).map(|(_, offset)| offset), Some(0x10));
+}
+
+#[test]
+#[should_panic]
+fn test_memory_map_overlap() {
+    let mut map = MemoryMap::default();
+    map.map(0x1000, 0x100, Memory::new(0x100));
+    map.map(0x10F0, 0x100, Memory::new(0x100));
+}
+
+#[test]
+#[should_panic]
+fn test_cpu_map_overlaps_memory() {
+    let mut cpu = CPU::new(0x1000);
+    cpu.map(0x1000, 0x100, Memory::new(0x100));
+    cpu.map(0xF00, 0x200, Memory::new(0x200));
+}
+
+#[test]
+fn test_cpu_bus() {
+    let program: Vec<u8> = [
+        0x00002337u32, // lui t1, 2
+        0x00432283, // lw t0, 4(t1)
+        0x00531423, // sh t0, 8(t1)
+        0x000013B7, // lui t2, 1
+        0x0053A023, // sw t0, 0(t2)
+    ].iter().flat_map(|instruction| instruction.to_le_bytes()).collect();
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut cpu = CPU::new(0x100);
+    cpu.bus.map(0x1000, 0x100, Rom::new(program));
+    cpu.bus.map(0x2000, 0x100, recorder.clone());
+    cpu.pc.set(0x1000);
+
+    // Fetched from ROM, loaded from and stored to the device
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.regs[5], 5);
+    assert_eq!(recorder.borrow().loads, 1);
+    assert_eq!(recorder.borrow().stores, vec![(8, 2, 5)]);
+
+    // The ROM can't be written
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap: Trap::Exception { cause: Exception::StoreAccessFault, .. }, .. })));
+
+    // Devices shadow main memory
+    cpu.mem.write_word(0x80, 0x1234).unwrap();
+    cpu.bus.map(0x80, 0x4, Rom::new(vec![0x78, 0x56, 0x34, 0x12]));
+    cpu.mem.write_word(0, 0x08002283).unwrap(); // lw t0, 128(zero)
+    cpu.pc.set(0);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[5], 0x1234_5678);
+}
@@ -0,0 +1,160 @@
+use crate::{bus::Bus, clint::*, components::*, csr::*};
+
+/// A CPU running `program` followed by nops, with the machine trap handler at 0x800 and the
+/// supervisor one at 0x900.
+pub(super) fn interrupt_cpu(program: &[u32]) -> CPU {
+    let mut cpu = CPU::new(0x1000);
+    for i in 0..0x400 {
+        cpu.mem.write_word(i * 4, 0x00000013).unwrap(); // nop
+    }
+    for (i, instruction) in program.iter().enumerate() {
+        cpu.mem.write_word(i * 4, *instruction as u64).unwrap();
+    }
+    cpu.csr.mtvec = 0x800;
+    cpu.csr.stvec = 0x900;
+    cpu
+}
+
+fn clint_cpu(program: &[u32]) -> CPU {
+    let mut cpu = interrupt_cpu(program);
+    cpu.add_clint(CLINT_BASE, Clint::new(Timebase::Instructions));
+    cpu
+}
+
+#[test]
+fn test_clint_registers() {
+    let mut clint = Clint::new(Timebase::Instructions);
+    assert_eq!(clint.interrupts(), 0);
+
+    clint.store(0x0, 4, 1).unwrap();
+    assert!(clint.msip);
+    assert_eq!(clint.load(0x0, 4).unwrap(), 1);
+    assert_eq!(clint.interrupts(), MIP_MSIP);
+    clint.store(0x0, 4, 0).unwrap();
+
+    // RV32 writes the 64 bit registers in halves
+    clint.store(0xBFF8, 4, 0xFFFF_FFFE).unwrap();
+    clint.store(0xBFFC, 4, 0x1).unwrap();
+    assert_eq!(clint.mtime(), 0x1_FFFF_FFFE);
+    clint.tick();
+    clint.tick();
+    assert_eq!(clint.load(0xBFF8, 8).unwrap(), 0x2_0000_0000);
+    assert_eq!(clint.load(0xBFFC, 4).unwrap(), 0x2);
+
+    clint.store(0x4000, 8, 0x2_0000_0001).unwrap();
+    assert_eq!(clint.load(0x4000, 4).unwrap(), 1);
+    assert_eq!(clint.interrupts(), 0);
+    clint.tick();
+    assert_eq!(clint.interrupts(), MIP_MTIP);
+}
+
+#[test]
+fn test_clint_wall_clock() {
+    let mut clint = Clint::new(Timebase::WallClock { frequency: 1_000_000_000 });
+    let before = clint.mtime();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    assert!(clint.mtime() >= before + 2_000_000);
+
+    // Ticks don't move it, writes do
+    clint.set_mtime(100);
+    clint.tick();
+    assert!(clint.mtime() >= 100 && clint.mtime() < 100 + 1_000_000_000);
+}
+
+#[test]
+fn test_cpu_timer_interrupt() {
+    let mut cpu = clint_cpu(&[
+        0x020042B7, // lui t0, 0x2004
+        0x00600313, // li t1, 6
+        0x0062B023, // sd t1, 0(t0)
+        0xC0102573, // rdtime a0
+    ]);
+    cpu.csr.mie = MIP_MTIP;
+    cpu.csr.mstatus |= MSTATUS_MIE;
+
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.clint.as_ref().unwrap().borrow().mtimecmp, 6);
+    assert_eq!(cpu.regs[10], 4);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 20);
+
+    // Taken before the next fetch once mtime reaches mtimecmp
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x800);
+    assert_eq!(cpu.csr.mepc, 20);
+    assert_eq!(cpu.csr.mcause, (1 << 63) | 7);
+    assert_eq!(cpu.csr.mip & MIP_MTIP, MIP_MTIP);
+    assert_eq!(cpu.csr.mstatus & MSTATUS_MIE, 0);
+    assert_eq!(cpu.csr.instret, 5);
+
+    // Masked while the handler runs
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x804);
+}
+
+#[test]
+fn test_cpu_interrupt_enables() {
+    let mut cpu = clint_cpu(&[]);
+    cpu.clint.as_ref().unwrap().borrow_mut().msip = true;
+    cpu.clint.as_ref().unwrap().borrow_mut().mtimecmp = 0;
+
+    // Not enabled in mie, then not in mstatus
+    cpu.cycle().unwrap();
+    cpu.csr.mie = MIP_MSIP | MIP_MTIP;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 8);
+
+    // Lower privilege levels can't mask machine interrupts, software goes before the timer
+    cpu.privilege = Privilege::User;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x800);
+    assert_eq!(cpu.csr.mcause, (1 << 63) | 3);
+    assert_eq!(cpu.privilege, Privilege::Machine);
+
+    // Without a handler the interrupt comes back as an error
+    let mut cpu = clint_cpu(&[]);
+    cpu.csr.mtvec = 0;
+    cpu.csr.mie = MIP_MSIP;
+    cpu.csr.mstatus |= MSTATUS_MIE;
+    cpu.clint.as_ref().unwrap().borrow_mut().msip = true;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap: crate::trap::Trap::Interrupt(crate::trap::Interrupt::MachineSoftware), .. })));
+}
+
+#[test]
+fn test_cpu_delegated_interrupt() {
+    let mut cpu = clint_cpu(&[]);
+    cpu.csr.mideleg = MIP_SSIP;
+    cpu.csr.mie = MIP_SSIP;
+    cpu.csr.mip = MIP_SSIP;
+
+    // Delegated interrupts never interrupt M-mode, even with MIE
+    cpu.csr.mstatus |= MSTATUS_MIE;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 4);
+
+    // S-mode takes them only with SIE
+    cpu.privilege = Privilege::Supervisor;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 8);
+    cpu.csr.mstatus |= MSTATUS_SIE;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x900);
+    assert_eq!(cpu.csr.scause, (1 << 63) | 1);
+    assert_eq!(cpu.csr.sepc, 8);
+}
+
+#[test]
+fn test_default_memory_leaves_room_for_clint() {
+    // What `cpu --newlib --clint instructions` sets up
+    let memory = memory_below(HOSTED_MEMORY, &[CLINT_BASE]);
+    assert_eq!(memory, CLINT_BASE as usize);
+    let mut cpu = CPU::new(memory);
+    assert!(!cpu.overlaps_memory(CLINT_BASE, CLINT_SIZE));
+    cpu.add_clint(CLINT_BASE, Clint::new(Timebase::Instructions));
+
+    // Memory below the device stays as it is
+    assert_eq!(memory_below(0x1000, &[CLINT_BASE, 0x1000_0000]), 0x1000);
+    assert_eq!(memory_below(HOSTED_MEMORY, &[]), HOSTED_MEMORY);
+}
@@ -1,4 +1,4 @@
-use crate::components::*;
+use crate::{components::*, csr::*, fpu, htif::Htif, mmu::*, stages::Xlen, trap::*};
 
 #[test]
 fn test_program_counter_increment() {
@@ -43,4 +43,861 @@ fn test_memory_out_of_bounds() {
 
     assert!(memory.write_byte(1, 0xAA).is_err());
     assert!(memory.read_word(5).is_err());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_cpu_load_word_sign_extension() {
+    let mut cpu = CPU::new(64);
+
+    cpu.mem.write_word(0, 0x02002083).unwrap(); // lw x1, 32(x0)
+    cpu.mem.write_word(4, 0x02006103).unwrap(); // lwu x2, 32(x0)
+    cpu.mem.write_word(32, 0x8000_0000).unwrap();
+
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+
+    assert_eq!(cpu.regs[1], 0xFFFF_FFFF_8000_0000);
+    assert_eq!(cpu.regs[2], 0x8000_0000);
+}
+
+/// Builds a minimal ELF32 RISC-V executable with a single PT_LOAD segment at `vaddr`.
+fn build_elf32(vaddr: u32, code: &[u32]) -> Vec<u8> {
+    let mut elf = Vec::new();
+
+    elf.extend_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // e_type: EXEC
+    elf.extend_from_slice(&243u16.to_le_bytes()); // e_machine: RISC-V
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // e_entry
+    elf.extend_from_slice(&52u32.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    elf.extend_from_slice(&52u16.to_le_bytes()); // e_ehsize
+    elf.extend_from_slice(&32u16.to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    elf.extend_from_slice(&40u16.to_le_bytes()); // e_shentsize
+    elf.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    elf.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    let size = (code.len() * 4) as u32;
+    elf.extend_from_slice(&1u32.to_le_bytes()); // p_type: PT_LOAD
+    elf.extend_from_slice(&84u32.to_le_bytes()); // p_offset
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_vaddr
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_paddr
+    elf.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    elf.extend_from_slice(&size.to_le_bytes()); // p_memsz
+    elf.extend_from_slice(&5u32.to_le_bytes()); // p_flags: R+X
+    elf.extend_from_slice(&4u32.to_le_bytes()); // p_align
+
+    for instruction in code {
+        elf.extend_from_slice(&instruction.to_le_bytes());
+    }
+
+    elf
+}
+
+#[test]
+fn test_cpu_rv32_elf() {
+    let elf = build_elf32(0x100, &[
+        0xFFF00093, // li x1, -1
+        0x0010D113, // srli x2, x1, 1
+        0x00110193, // addi x3, x2, 1
+    ]);
+
+    let mut cpu = CPU::new(0x200);
+    cpu.load_elf(&elf).unwrap();
+    assert_eq!(cpu.xlen, Xlen::Rv32);
+    assert_eq!(cpu.pc.address, 0x100);
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.regs[1], u64::MAX);
+    assert_eq!(cpu.regs[2], 0x7FFF_FFFF);
+    assert_eq!(cpu.regs[3], 0xFFFF_FFFF_8000_0000);
+}
+
+#[test]
+fn test_cpu_rv32_rejects_rv64_instructions() {
+    let mut cpu = CPU::with_xlen(64, Xlen::Rv32);
+
+    cpu.mem.write_word(0, 0x0000B083).unwrap(); // ld x1, 0(x1)
+
+    assert!(cpu.cycle().is_err());
+}
+
+#[test]
+fn test_cpu_read_counters() {
+    let mut cpu = CPU::new(64);
+
+    cpu.mem.write_word(0, 0x00000013).unwrap(); // nop
+    cpu.mem.write_word(4, 0xC00020F3).unwrap(); // rdcycle x1
+    cpu.mem.write_word(8, 0xC0202173).unwrap(); // rdinstret x2
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.regs[1], 2);
+    assert_eq!(cpu.regs[2], 2);
+    assert_eq!(cpu.csr.instret, 3);
+}
+
+#[test]
+fn test_cpu_illegal_csr_access() {
+    let mut cpu = CPU::new(64);
+    cpu.mem.write_word(0, 0xC0009073).unwrap(); // csrw cycle, x1
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0xC0009073)));
+
+    let mut cpu = CPU::new(64);
+    cpu.mem.write_word(0, 0x123021F3).unwrap(); // csrr x3, 0x123
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x123021F3)));
+}
+
+fn load_program(cpu: &mut CPU, address: usize, program: &[u32]) {
+    for (i, instruction) in program.iter().enumerate() {
+        cpu.mem.write_word(address + i * 4, *instruction as u64).unwrap();
+    }
+}
+
+#[test]
+fn test_cpu_ecall_trap_and_mret() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x00000297, // auipc t0, 0
+        0x01428293, // addi t0, t0, 20
+        0x30529073, // csrw mtvec, t0
+        0x00000073, // ecall
+        0x00100513, // li a0, 1
+        // handler:
+        0x342025F3, // csrr a1, mcause
+        0x34102673, // csrr a2, mepc
+        0x00460613, // addi a2, a2, 4
+        0x34161073, // csrw mepc, a2
+        0x30200073, // mret
+    ]);
+    cpu.csr.mstatus |= MSTATUS_MIE;
+
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.pc.address, 0x14);
+    assert_eq!(cpu.csr.mepc, 0xC);
+    assert_eq!(cpu.csr.mstatus & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MPIE);
+
+    for _ in 0..6 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.regs[11], 11);
+    assert_eq!(cpu.regs[10], 1);
+    assert_eq!(cpu.csr.mstatus & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MIE | MSTATUS_MPIE);
+    // The trapping ecall doesn't retire
+    assert_eq!(cpu.csr.instret, 9);
+}
+
+#[test]
+fn test_cpu_unhandled_trap() {
+    let mut cpu = CPU::new(64);
+    cpu.mem.write_word(0, 0x00100073).unwrap(); // ebreak
+    cpu.halt.ebreak = false;
+
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, pc: 0 }) if trap == Trap::exception(Exception::Breakpoint, 0)));
+}
+
+#[test]
+fn test_cpu_access_fault() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x04000293, // li t0, 64
+        0x30529073, // csrw mtvec, t0
+        0x40003083, // ld ra, 1024(zero)
+    ]);
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.pc.address, 64);
+    assert_eq!(cpu.csr.mcause, Exception::LoadAccessFault.code());
+    assert_eq!(cpu.csr.mtval, 1024);
+    assert_eq!(cpu.csr.mepc, 8);
+}
+
+#[test]
+fn test_cpu_halfword_aligned_jump() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x04000293, // li t0, 64
+        0x30529073, // csrw mtvec, t0
+        0x016000EF, // jal ra, 22
+    ]);
+
+    // With the C extension a jump target only has to be 2 byte aligned
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.pc.address, 30);
+    assert_eq!(cpu.regs[1], 12);
+}
+
+#[test]
+fn test_cpu_misaligned_jump() {
+    let mut cpu = CPU::with_isa(128, "rv64im_zicsr".parse().unwrap());
+    load_program(&mut cpu, 0, &[
+        0x04000293, // li t0, 64
+        0x30529073, // csrw mtvec, t0
+        0x016000EF, // jal ra, 22
+    ]);
+
+    // Without the C extension jump targets have to be 4 byte aligned again
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.pc.address, 64);
+    assert_eq!(cpu.csr.mcause, Exception::InstructionAddressMisaligned.code());
+    assert_eq!(cpu.csr.mtval, 30);
+    assert_eq!(cpu.regs[1], 0);
+}
+
+fn load_compressed(cpu: &mut CPU, address: usize, program: &[u16]) {
+    for (i, instruction) in program.iter().enumerate() {
+        cpu.mem.write_half_word(address + i * 2, *instruction as u64).unwrap();
+    }
+}
+
+#[test]
+fn test_cpu_compressed_instructions() {
+    let mut cpu = CPU::new(128);
+    load_compressed(&mut cpu, 0, &[
+        0x4515, // c.li a0, 5
+        0x050D, // c.addi a0, 3
+        0x0293, 0x0100, // addi t0, zero, 16
+        0x9282, // c.jalr t0
+        0x0001, // c.nop
+        0x0001, // c.nop
+        0x0001, // c.nop
+        0x85AA, // c.mv a1, a0
+        0x8082, // c.jr ra
+    ]);
+
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.pc.address, 16);
+    assert_eq!(cpu.regs[1], 10);
+    assert_eq!(cpu.regs[10], 8);
+
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[11], 8);
+    assert_eq!(cpu.pc.address, 10);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 12);
+
+    // An illegal compressed instruction reports its 16 bits in mtval
+    load_compressed(&mut cpu, 12, &[0x0000]);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0)));
+}
+
+#[test]
+fn test_cpu_rv32_compressed_jal() {
+    let mut cpu = CPU::with_xlen(64, Xlen::Rv32);
+    cpu.pc.set(4);
+    load_compressed(&mut cpu, 4, &[0x2019]); // c.jal 6
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 10);
+    assert_eq!(cpu.regs[1], 6);
+}
+
+#[test]
+fn test_cpu_vectored_trap() {
+    let mut cpu = CPU::new(128);
+    cpu.csr.mtvec = 0x40 | 1;
+    cpu.pc.set(0x10);
+
+    cpu.take_trap(Trap::Interrupt(Interrupt::MachineTimer)).unwrap();
+    assert_eq!(cpu.pc.address, 0x40 + 4 * 7);
+    assert_eq!(cpu.csr.mcause, (1 << 63) | 7);
+    assert_eq!(cpu.csr.mepc, 0x10);
+
+    cpu.take_trap(Trap::exception(Exception::IllegalInstruction, 0)).unwrap();
+    assert_eq!(cpu.pc.address, 0x40);
+    assert_eq!(cpu.csr.mcause, 2);
+}
+
+#[test]
+fn test_cpu_privilege_modes_and_delegation() {
+    let mut cpu = CPU::new(256);
+    load_program(&mut cpu, 0, &[
+        0x00000297, // auipc t0, 0
+        0x05028293, // addi t0, t0, 80
+        0x30529073, // csrw mtvec, t0
+        0x00000297, // auipc t0, 0
+        0x03028293, // addi t0, t0, 48
+        0x10529073, // csrw stvec, t0
+        0x10000293, // li t0, 256
+        0x30229073, // csrw medeleg, t0
+        0x00000297, // auipc t0, 0
+        0x01028293, // addi t0, t0, 16
+        0x34129073, // csrw mepc, t0
+        0x30200073, // mret
+        // user:
+        0x00000073, // ecall
+        0x30002573, // csrr a0, mstatus
+        0xFF9FF06F, // j user
+        // s_handler:
+        0x142025F3, // csrr a1, scause
+        0x14102673, // csrr a2, sepc
+        0x00460613, // addi a2, a2, 4
+        0x14161073, // csrw sepc, a2
+        0x10200073, // sret
+        // m_handler:
+        0x342026F3, // csrr a3, mcause
+        0x30002773, // csrr a4, mstatus
+        0xFF9FF06F, // j m_handler
+    ]);
+
+    for _ in 0..12 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.privilege, Privilege::User);
+    assert_eq!(cpu.pc.address, 0x30);
+
+    // The ECALL from U-mode is delegated to the supervisor
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.privilege, Privilege::Supervisor);
+    assert_eq!(cpu.pc.address, 0x3C);
+    assert_eq!(cpu.csr.sepc, 0x30);
+    assert_eq!(cpu.csr.mstatus & MSTATUS_SPP, 0);
+
+    for _ in 0..5 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.privilege, Privilege::User);
+    assert_eq!(cpu.pc.address, 0x34);
+    assert_eq!(cpu.regs[11], Exception::EnvironmentCallFromUMode.code());
+
+    // Reading mstatus from U-mode is an illegal instruction, which isn't delegated
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.privilege, Privilege::Machine);
+    assert_eq!(cpu.pc.address, 0x50);
+    assert_eq!(cpu.csr.mtval, 0x30002573);
+    assert_eq!(cpu.csr.mstatus & MSTATUS_MPP, 0);
+
+    for _ in 0..2 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.regs[13], Exception::IllegalInstruction.code());
+    assert_eq!(cpu.regs[10], 0);
+}
+
+#[test]
+fn test_cpu_privileged_instructions() {
+    let mut cpu = CPU::new(64);
+    cpu.privilege = Privilege::Supervisor;
+    cpu.mem.write_word(0, 0x30200073).unwrap(); // mret
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x30200073)));
+
+    let mut cpu = CPU::new(64);
+    cpu.privilege = Privilege::User;
+    cpu.mem.write_word(0, 0x10200073).unwrap(); // sret
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x10200073)));
+
+    let mut cpu = CPU::new(64);
+    cpu.privilege = Privilege::Supervisor;
+    cpu.csr.mstatus |= MSTATUS_TSR;
+    cpu.mem.write_word(0, 0x10200073).unwrap(); // sret
+    assert!(cpu.cycle().is_err());
+}
+/// Maps the 4 KiB pages of an Sv39 address space below 2 MiB through the tables at 0x1000,
+/// 0x2000 and 0x3000, returning the value for satp.
+fn map_pages(cpu: &mut CPU, pages: &[(u64, u64, u64)]) -> u64 {
+    cpu.mem.write_double_word(0x1000, ((0x2000 >> 12) << 10) | PTE_V).unwrap();
+    cpu.mem.write_double_word(0x2000, ((0x3000 >> 12) << 10) | PTE_V).unwrap();
+
+    for (vaddr, paddr, flags) in pages {
+        cpu.mem.write_double_word((0x3000 + (vaddr >> 12) * 8) as usize, ((paddr >> 12) << 10) | flags).unwrap();
+    }
+
+    (8 << 60) | (0x1000 >> 12)
+}
+
+#[test]
+fn test_cpu_sv39_paging() {
+    let mut cpu = CPU::new(0x8000);
+    cpu.csr.satp = map_pages(&mut cpu, &[
+        (0x8000, 0x4000, PTE_V | PTE_R | PTE_X),
+        (0x9000, 0x5000, PTE_V | PTE_R | PTE_W),
+    ]);
+    load_program(&mut cpu, 0x4000, &[
+        0x0002B503, // ld a0, 0(t0)
+        0x00A2B423, // sd a0, 8(t0)
+        0x00033583, // ld a1, 0(t1)
+    ]);
+    cpu.mem.write_double_word(0x5000, 0x1122_3344_5566_7788).unwrap();
+    cpu.privilege = Privilege::Supervisor;
+    cpu.pc.set(0x8000);
+    cpu.regs[5] = 0x9000;
+    cpu.regs[6] = 0xA000;
+
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 0x1122_3344_5566_7788);
+    assert_eq!(cpu.mem.read_double_word(0x5008).unwrap(), 0x1122_3344_5566_7788);
+    assert_eq!(cpu.mem.read_double_word(0x3000 + 9 * 8).unwrap() & (PTE_A | PTE_D), PTE_A | PTE_D);
+    assert_eq!(cpu.last_store, Some((0x9008, 0x1122_3344_5566_7788)));
+
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::LoadPageFault, 0xA000)));
+
+    // Machine mode ignores satp unless MPRV borrows the translation of MPP
+    cpu.privilege = Privilege::Machine;
+    cpu.pc.set(0x4000);
+    cpu.regs[5] = 0x5000;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 0x1122_3344_5566_7788);
+
+    cpu.csr.mstatus |= MSTATUS_MPRV | (1 << 11);
+    cpu.pc.set(0x4000);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::LoadPageFault, 0x5000)));
+}
+
+#[test]
+fn test_cpu_sv39_fetch_fault_and_page_crossing() {
+    let mut cpu = CPU::new(0x8000);
+    cpu.csr.satp = map_pages(&mut cpu, &[
+        (0x8000, 0x4000, PTE_V | PTE_R | PTE_X),
+        (0x9000, 0x6000, PTE_V | PTE_R | PTE_W | PTE_X),
+        (0xA000, 0x5000, PTE_V | PTE_R | PTE_W | PTE_X),
+    ]);
+    load_program(&mut cpu, 0x4000, &[
+        0x00B2B023, // sd a1, 0(t0)
+        0x0002B503, // ld a0, 0(t0)
+    ]);
+    cpu.privilege = Privilege::Supervisor;
+    cpu.pc.set(0x8000);
+    cpu.regs[5] = 0x9FFC;
+    cpu.regs[11] = 0x1122_3344_5566_7788;
+
+    // The halves of a doubleword straddling two pages end up in unrelated physical pages
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.mem.read_word(0x6FFC).unwrap(), 0x5566_7788);
+    assert_eq!(cpu.mem.read_word(0x5000).unwrap(), 0x1122_3344);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 0x1122_3344_5566_7788);
+
+    cpu.pc.set(0xB000);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::InstructionPageFault, 0xB000)));
+
+    // A 32 bit instruction in the last halfword of a page continues on the next page
+    cpu.mem.write_half_word(0x6FFE, 0x0513).unwrap();
+    cpu.mem.write_half_word(0x5000, 0x02A0).unwrap(); // addi a0, zero, 42
+    cpu.pc.set(0x9FFE);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 42);
+    assert_eq!(cpu.pc.address, 0xA002);
+
+    cpu.mem.write_half_word(0x5FFE, 0x0513).unwrap();
+    cpu.pc.set(0xAFFE);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::InstructionPageFault, 0xB000)));
+}
+
+#[test]
+fn test_cpu_sfence_vma() {
+    let mut cpu = CPU::new(0x8000);
+    cpu.csr.satp = map_pages(&mut cpu, &[
+        (0x8000, 0x4000, PTE_V | PTE_R | PTE_X),
+        (0x9000, 0x5000, PTE_V | PTE_R),
+    ]);
+    load_program(&mut cpu, 0x4000, &[
+        0x0002B503, // ld a0, 0(t0)
+        0x12028073, // sfence.vma t0
+        0x0002B503, // ld a0, 0(t0)
+    ]);
+    cpu.mem.write_double_word(0x5000, 1).unwrap();
+    cpu.mem.write_double_word(0x6000, 2).unwrap();
+    cpu.privilege = Privilege::Supervisor;
+    cpu.pc.set(0x8000);
+    cpu.regs[5] = 0x9000;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 1);
+
+    map_pages(&mut cpu, &[(0x9000, 0x6000, PTE_V | PTE_R)]);
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 2);
+
+    // SFENCE.VMA is reserved for the supervisor, and TVM takes it away from there as well
+    let mut cpu = CPU::new(64);
+    cpu.privilege = Privilege::User;
+    cpu.mem.write_word(0, 0x12000073).unwrap(); // sfence.vma
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x12000073)));
+
+    let mut cpu = CPU::new(64);
+    cpu.privilege = Privilege::Supervisor;
+    cpu.csr.mstatus |= MSTATUS_TVM;
+    cpu.mem.write_word(0, 0x12000073).unwrap(); // sfence.vma
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x12000073)));
+}
+
+#[test]
+fn test_cpu_lr_sc_and_amos() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x1002B52F, // lr.d a0, (t0)
+        0x18C2B5AF, // sc.d a1, a2, (t0)
+        0x18C2B5AF, // sc.d a1, a2, (t0)
+        0x1403252F, // lr.w.aq a0, (t1)
+        0x1AD325AF, // sc.w.rl a1, a3, (t1)
+        0x00C3272F, // amoadd.w a4, a2, (t1)
+        0x0EC2B7AF, // amoswap.d.aqrl a5, a2, (t0)
+        0x02C3A52F, // amoadd.w.rl a0, a2, (t2)
+    ]);
+    cpu.mem.write_double_word(0x40, 10).unwrap();
+    cpu.mem.write_word(0x48, 0xFFFF_FFFE).unwrap();
+    cpu.regs[5] = 0x40;
+    cpu.regs[6] = 0x48;
+    cpu.regs[7] = 0x4A;
+    cpu.regs[12] = 7;
+    cpu.regs[13] = 0x1_0000_0005;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 10);
+    assert_eq!(cpu.reservation, Some(0x40));
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[11], 0);
+    assert_eq!(cpu.mem.read_double_word(0x40).unwrap(), 7);
+
+    // The reservation is gone after the first SC
+    cpu.mem.write_double_word(0x40, 10).unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[11], 1);
+    assert_eq!(cpu.mem.read_double_word(0x40).unwrap(), 10);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], -2i64 as u64);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[11], 0);
+    assert_eq!(cpu.mem.read_double_word(0x48).unwrap(), 5);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[14], 5);
+    assert_eq!(cpu.mem.read_word(0x48).unwrap(), 12);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[15], 10);
+    assert_eq!(cpu.mem.read_double_word(0x40).unwrap(), 7);
+    assert_eq!(cpu.last_store, Some((0x40, 7)));
+
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::StoreAddressMisaligned, 0x4A)));
+}
+
+#[test]
+fn test_cpu_atomics_faults() {
+    let mut cpu = CPU::new(64);
+    cpu.mem.write_word(0, 0x1403252F).unwrap(); // lr.w.aq a0, (t1)
+    cpu.regs[6] = 0x2;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::LoadAddressMisaligned, 0x2)));
+
+    cpu.regs[6] = 0x1000;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::LoadAccessFault, 0x1000)));
+
+    // AMOs on a read-only page fault like stores
+    let mut cpu = CPU::new(0x8000);
+    cpu.csr.satp = map_pages(&mut cpu, &[
+        (0x8000, 0x4000, PTE_V | PTE_R | PTE_X),
+        (0x9000, 0x5000, PTE_V | PTE_R),
+    ]);
+    load_program(&mut cpu, 0x4000, &[0x00C3272F]); // amoadd.w a4, a2, (t1)
+    cpu.privilege = Privilege::Supervisor;
+    cpu.pc.set(0x8000);
+    cpu.regs[6] = 0x9000;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::StorePageFault, 0x9000)));
+
+    // The doubleword forms don't exist on RV32
+    let mut cpu = CPU::with_xlen(64, Xlen::Rv32);
+    cpu.mem.write_word(0, 0x0EC2B7AF).unwrap(); // amoswap.d.aqrl a5, a2, (t0)
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x0EC2B7AF)));
+}
+
+#[test]
+fn test_cpu_floating_point() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x0000A087, // flw ft1, 0(ra)
+        0x0080B107, // fld ft2, 8(ra)
+        0x0010F1D3, // fadd.s ft3, ft1, ft1
+        0x12217243, // fmadd.d ft4, ft2, ft2, ft2
+        0x1860F2D3, // fdiv.s ft5, ft1, ft6
+        0xC0009153, // fcvt.w.s sp, ft1, rtz
+        0x0040B827, // fsd ft4, 16(ra)
+        0xE0018253, // fmv.x.w tp, ft3
+        0x0030AC27, // fsw ft3, 24(ra)
+    ]);
+    cpu.regs[1] = 0x40;
+    cpu.mem.write_word(0x40, 0xC020_0000).unwrap(); // -2.5
+    cpu.mem.write_double_word(0x48, 0x4000_0000_0000_0000).unwrap(); // 2.0
+    cpu.csr.mstatus = 0;
+
+    // With FS off every floating point instruction is illegal
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x0000A087)));
+    cpu.csr.mstatus = MSTATUS_FS_INITIAL;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[1], 0xFFFF_FFFF_C020_0000);
+    assert_eq!(cpu.csr.mstatus & MSTATUS_FS, MSTATUS_FS_DIRTY);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[2], 0x4000_0000_0000_0000);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[3], 0xFFFF_FFFF_C0A0_0000);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[4], 0x4018_0000_0000_0000);
+
+    // ft6 holds zero, which isn't a valid NaN-boxed single, so the division sees a NaN
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[5], 0xFFFF_FFFF_7FC0_0000);
+    assert_eq!(cpu.csr.fflags, 0);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[2], -2i64 as u64);
+    assert_eq!(cpu.csr.fflags, fpu::NX as u64);
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.mem.read_double_word(0x50).unwrap(), 0x4018_0000_0000_0000);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[4], 0xFFFF_FFFF_C0A0_0000);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.mem.read_double_word(0x58).unwrap(), 0xC0A0_0000);
+}
+
+#[test]
+fn test_cpu_floating_point_rounding_mode() {
+    let mut cpu = CPU::new(64);
+    load_program(&mut cpu, 0, &[
+        0x0010F1D3, // fadd.s ft3, ft1, ft1 (dynamic rounding)
+        0x0010D1D3, // fadd.s ft3, ft1, ft1, 5 (reserved)
+    ]);
+    cpu.fregs[1] = fpu::nan_box(0x7F7F_FFFF);
+    cpu.csr.frm = 0b001; // RTZ
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.fregs[3], fpu::nan_box(0x7F7F_FFFF));
+    assert_eq!(cpu.csr.fflags, (fpu::OF | fpu::NX) as u64);
+
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x0010D1D3)));
+
+    // An invalid frm makes the dynamic mode illegal as well
+    cpu.pc.set(0);
+    cpu.csr.frm = 0b101;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x0010F1D3)));
+}
+
+#[test]
+fn test_cpu_disabled_extensions() {
+    let mut cpu = CPU::with_isa(64, "rv64imac_zba".parse().unwrap());
+    load_program(&mut cpu, 0, &[
+        0x20C5A533, // sh1add a0, a1, a2
+        0x60259513, // cpop a0, a1
+    ]);
+    cpu.regs[11] = 3;
+    cpu.regs[12] = 1;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[10], 7);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x60259513)));
+
+    let mut cpu = CPU::with_isa(64, "rv64i".parse().unwrap());
+    load_program(&mut cpu, 0, &[
+        0x02C58533, // mul a0, a1, a2
+        0x30002573, // csrr a0, mstatus
+    ]);
+    cpu.mem.write_half_word(8, 0x4515).unwrap(); // c.li a0, 5
+
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x02C58533)));
+    cpu.pc.set(4);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x30002573)));
+    cpu.pc.set(8);
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x4515)));
+
+    // misa only lists what's enabled
+    assert_eq!(cpu.csr.read(MISA, Xlen::Rv64).unwrap(), (2 << 62) | (1 << 8) | (1 << 18) | (1 << 20));
+}
+
+#[test]
+fn test_cpu_vector() {
+    let mut cpu = CPU::new(256);
+    load_program(&mut cpu, 0, &[
+        0x0D05F2D7, // vsetvli t0, a1, e32, m1, ta, ma
+        0x02056087, // vle32.v v1, (a0)
+        0x0210B0D7, // vadd.vi v1, v1, 1
+        0x020660A7, // vse32.v v1, (a2)
+        0x421026D7, // vmv.x.s a3, v1
+    ]);
+    cpu.regs[10] = 0x80;
+    cpu.regs[11] = 3;
+    cpu.regs[12] = 0xC0;
+    for (index, value) in [10, 20, 30, 40].into_iter().enumerate() {
+        cpu.mem.write_word(0x80 + index * 4, value).unwrap();
+    }
+    cpu.csr.mstatus = 0;
+
+    // With VS off every vector instruction is illegal
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::IllegalInstruction, 0x0D05F2D7)));
+    cpu.csr.mstatus = MSTATUS_VS_INITIAL;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.regs[5], 3);
+    assert_eq!((cpu.csr.vl, cpu.csr.vtype), (3, 0xD0));
+    assert_eq!(cpu.csr.mstatus & MSTATUS_VS, MSTATUS_VS_DIRTY);
+
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.regs[13], 11);
+    assert_eq!(cpu.mem.read_word(0xC0).unwrap(), 11);
+    assert_eq!(cpu.mem.read_word(0xC8).unwrap(), 31);
+    // vl = 3 leaves the fourth element alone
+    assert_eq!(cpu.mem.read_word(0xCC).unwrap(), 0);
+}
+
+#[test]
+fn test_cpu_vector_fault_sets_vstart() {
+    let mut cpu = CPU::new(128);
+    load_program(&mut cpu, 0, &[
+        0x04000293, // li t0, 64
+        0x30529073, // csrw mtvec, t0
+        0x02056087, // vle32.v v1, (a0)
+    ]);
+    cpu.csr.vl = 4;
+    cpu.csr.vtype = 0x10;
+    cpu.regs[10] = 120;
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+
+    // The third element is past the end of memory
+    assert_eq!(cpu.pc.address, 64);
+    assert_eq!(cpu.csr.mcause, Exception::LoadAccessFault.code());
+    assert_eq!(cpu.csr.mtval, 128);
+    assert_eq!(cpu.csr.vstart, 2);
+}
+
+#[test]
+fn test_cpu_self_modifying_code() {
+    let mut cpu = CPU::new(64);
+    load_program(&mut cpu, 0, &[
+        0x02A00337, // lui t1, 0x02A00
+        0x51330313, // addi t1, t1, 0x513
+        0x00602A23, // sw t1, 20(zero)
+        0x0330000F, // fence rw, rw
+        0x0000100F, // fence.i
+        0x00100513, // li a0, 1 (overwritten with li a0, 42)
+    ]);
+
+    for _ in 0..6 {
+        cpu.cycle().unwrap();
+    }
+
+    assert_eq!(cpu.regs[10], 42);
+    assert_eq!(cpu.pc.address, 24);
+}
+
+#[test]
+fn test_cpu_ecall_and_ebreak_exit() {
+    let mut cpu = CPU::new(64);
+    load_program(&mut cpu, 0, &[
+        0x00300513, // li a0, 3
+        0x05D00893, // li a7, 93
+        0x00000073, // ecall
+        0x00100073, // ebreak
+    ]);
+
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 3 })));
+
+    // Any other ECALL is still an unhandled trap
+    cpu.pc.set(8);
+    cpu.regs[17] = 64;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Trap { trap, .. }) if trap == Trap::exception(Exception::EnvironmentCallFromMMode, 0)));
+
+    cpu.pc.set(12);
+    cpu.regs[10] = -1i64 as u64;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: -1 })));
+
+    // With a trap handler installed the guest gets to see both
+    cpu.csr.mtvec = 0x20;
+    cpu.pc.set(12);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x20);
+}
+
+#[test]
+fn test_cpu_tohost_and_test_finisher_exit() {
+    let mut cpu = CPU::new(0x80);
+    load_program(&mut cpu, 0, &[
+        0x01100293, // li t0, 17
+        0x04502023, // sw t0, 64(zero)
+        0x001002B7, // lui t0, 0x100
+        0x00025337, // lui t1, 0x25
+        0x5553031B, // addiw t1, t1, 0x555
+        0x0062A023, // sw t1, 0(t0)
+    ]);
+    cpu.htif = Some(Htif::new(0x40, None));
+    cpu.halt.test_finisher = Some(0x10_0000);
+
+    cpu.cycle().unwrap();
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 8 })));
+    assert_eq!(cpu.mem.read_word(0x40).unwrap(), 17);
+
+    // A tohost write without the low bit set is a proxied syscall, not an exit
+    cpu.pc.set(4);
+    cpu.regs[5] = 0x60;
+    cpu.cycle().unwrap();
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 0 })));
+
+    cpu.pc.set(20);
+    cpu.regs[6] = 0x0002_3333;
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 2 })));
+}
+
+#[test]
+fn test_cpu_atomics_reach_tohost_and_test_finisher() {
+    let mut cpu = CPU::new(0x80);
+    load_program(&mut cpu, 0, &[
+        0x001002B7, // lui t0, 0x100
+        0x00005337, // lui t1, 0x5
+        0x5553031B, // addiw t1, t1, 0x555
+        0x0862A02F, // amoswap.w zero, t1, (t0)
+        0x04000293, // li t0, 0x40
+        0x00700313, // li t1, 7
+        0x1002B3AF, // lr.d t2, (t0)
+        0x1862BE2F, // sc.d t3, t1, (t0)
+    ]);
+    cpu.htif = Some(Htif::new(0x40, None));
+    cpu.halt.test_finisher = Some(0x10_0000);
+
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 0 })));
+
+    cpu.pc.set(16);
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 3 })));
+}
@@ -0,0 +1,91 @@
+use crate::{compressed::*, stages::{decode_compressed, DecodeError, Xlen}};
+
+#[test]
+fn test_compressed_is_compressed() {
+    assert!(is_compressed(0x4505));
+    assert!(!is_compressed(0x00A00513));
+}
+
+#[test]
+fn test_compressed_expand_rv64() {
+    let expansions = [
+        (0x0800, 0x01010413), // addi s0, sp, 16
+        (0x2588, 0x0085B507), // fld fa0, 8(a1)
+        (0x42D0, 0x0046A603), // lw a2, 4(a3)
+        (0x6B98, 0x0107B703), // ld a4, 16(a5)
+        (0xAC8C, 0x00B4BC27), // fsd fa1, 24(s1)
+        (0xDCE8, 0x06A4AE23), // sw a0, 124(s1)
+        (0xFC68, 0x0EA43C23), // sd a0, 248(s0)
+        (0x0001, 0x00000013), // nop
+        (0x1501, 0xFE050513), // addi a0, a0, -32
+        (0x25FD, 0x01F5859B), // addiw a1, a1, 31
+        (0x52FD, 0xFFF00293), // li t0, -1
+        (0x7101, 0xE0010113), // addi sp, sp, -512
+        (0x7781, 0xFFFE07B7), // lui a5, 0xfffe0
+        (0x6785, 0x000017B7), // lui a5, 1
+        (0x907D, 0x03F45413), // srli s0, s0, 63
+        (0x8505, 0x40155513), // srai a0, a0, 1
+        (0x99ED, 0xFFB5F593), // andi a1, a1, -5
+        (0x8C05, 0x40940433), // sub s0, s0, s1
+        (0x8E35, 0x00D64633), // xor a2, a2, a3
+        (0x8F5D, 0x00F76733), // or a4, a4, a5
+        (0x8CE9, 0x00A4F4B3), // and s1, s1, a0
+        (0x9D0D, 0x40B5053B), // subw a0, a0, a1
+        (0x9E35, 0x00D6063B), // addw a2, a2, a3
+        (0x1302, 0x02031313), // slli t1, t1, 32
+        (0x347E, 0x1F813407), // fld fs0, 504(sp)
+        (0x50FE, 0x0FC12083), // lw ra, 252(sp)
+        (0x63A2, 0x00813383), // ld t2, 8(sp)
+        (0x8082, 0x00008067), // jr ra
+        (0x8572, 0x01C00533), // mv a0, t3 (add a0, zero, t3)
+        (0x9002, 0x00100073), // ebreak
+        (0x9282, 0x000280E7), // jalr t0
+        (0x95B2, 0x00C585B3), // add a1, a1, a2
+        (0xBFA6, 0x1E913C27), // fsd fs1, 504(sp)
+        (0xDF86, 0x0E112E23), // sw ra, 252(sp)
+        (0xE41E, 0x00713423), // sd t2, 8(sp)
+    ];
+
+    for (compressed, expanded) in expansions {
+        assert_eq!(expand(compressed, Xlen::Rv64), Some(expanded), "0x{compressed:04x}");
+    }
+}
+
+#[test]
+fn test_compressed_expand_rv32() {
+    let expansions = [
+        (0x61C8, 0x0045A507), // flw fa0, 4(a1)
+        (0xFCEC, 0x06B4AE27), // fsw fa1, 124(s1)
+        (0x747E, 0x0FC12407), // flw fs0, 252(sp)
+        (0xFFA6, 0x0E912E27), // fsw fs1, 252(sp)
+        (0x857D, 0x41F55513), // srai a0, a0, 31
+        (0x2021, 0x008000EF), // jal 8
+        (0xB001, 0x801FF06F), // j -2048
+        (0xC911, 0x00050A63), // beqz a0, 20
+        (0xF081, 0xF00490E3), // bnez s1, -256
+    ];
+
+    for (compressed, expanded) in expansions {
+        assert_eq!(expand(compressed, Xlen::Rv32), Some(expanded), "0x{compressed:04x}");
+    }
+}
+
+#[test]
+fn test_compressed_reserved() {
+    // The all zero instruction, C.ADDI4SPN with a zero immediate
+    assert_eq!(expand(0x0000, Xlen::Rv64), None);
+    // C.LWSP and C.LDSP with rd = x0
+    assert_eq!(expand(0x4002, Xlen::Rv64), None);
+    assert_eq!(expand(0x6002, Xlen::Rv64), None);
+    // C.JR with rs1 = x0
+    assert_eq!(expand(0x8002, Xlen::Rv64), None);
+    // C.ADDI16SP and C.LUI with a zero immediate
+    assert_eq!(expand(0x6101, Xlen::Rv64), None);
+    assert_eq!(expand(0x6781, Xlen::Rv64), None);
+    // C.SLLI with shamt[5] set is only valid on RV64
+    assert_eq!(expand(0x1302, Xlen::Rv32), None);
+    // C.SUBW exists on RV64 only
+    assert_eq!(expand(0x9D0D, Xlen::Rv32), None);
+
+    assert!(matches!(decode_compressed(0x0000, Xlen::Rv64), Err(DecodeError::IllegalCompressed(0))));
+}
@@ -0,0 +1,222 @@
+use crate::{csr::*, stages::Xlen};
+
+#[test]
+fn test_csr_counters() {
+    let mut csr = CsrFile::new();
+
+    csr.tick();
+    csr.tick();
+    csr.retire();
+
+    assert_eq!(csr.read(CYCLE, Xlen::Rv64).unwrap(), 2);
+    assert_eq!(csr.read(TIME, Xlen::Rv64).unwrap(), 2);
+    assert_eq!(csr.read(INSTRET, Xlen::Rv64).unwrap(), 1);
+}
+
+#[test]
+fn test_csr_high_counters() {
+    let mut csr = CsrFile::new();
+    csr.cycle = 0x1_2345_6789;
+
+    assert_eq!(csr.read(CYCLEH, Xlen::Rv32).unwrap(), 0x1);
+    assert!(matches!(csr.read(CYCLEH, Xlen::Rv64), Err(CsrError::Unknown(CYCLEH))));
+}
+
+#[test]
+fn test_csr_read_only() {
+    let mut csr = CsrFile::new();
+
+    assert!(is_read_only(CYCLE));
+    assert!(matches!(csr.write(CYCLE, 0, Xlen::Rv64), Err(CsrError::ReadOnly(CYCLE))));
+    assert!(matches!(csr.write(0x123, 0, Xlen::Rv64), Err(CsrError::Unknown(0x123))));
+}
+
+
+#[test]
+fn test_csr_mtvec_mode() {
+    let mut csr = CsrFile::new();
+
+    csr.write(MTVEC, 0x1001, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MTVEC, Xlen::Rv64).unwrap(), 0x1001);
+
+    // Reserved modes leave the previous value in place
+    csr.write(MTVEC, 0x2002, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MTVEC, Xlen::Rv64).unwrap(), 0x1001);
+}
+
+#[test]
+fn test_csr_machine_registers() {
+    let mut csr = CsrFile::new();
+
+    csr.write(MEPC, 0x1003, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MEPC, Xlen::Rv64).unwrap(), 0x1002);
+
+    csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_MPP, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MSTATUS, Xlen::Rv64).unwrap() & 0xFFFF_FFFF, MSTATUS_MIE | MSTATUS_MPP);
+    // UXL and SXL report 64 bit
+    assert_eq!(csr.read(MSTATUS, Xlen::Rv64).unwrap() >> 32, 0b1010);
+
+    // MPP = 0b10 is reserved and keeps the previous mode
+    csr.write(MSTATUS, 0b10 << 11, Xlen::Rv64).unwrap();
+    assert_eq!(csr.mstatus & MSTATUS_MPP, MSTATUS_MPP);
+
+    csr.write(MIE, u64::MAX, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MIE, Xlen::Rv64).unwrap(), MIP_SSIP | MIP_MSIP | MIP_STIP | MIP_MTIP | MIP_SEIP | MIP_MEIP);
+
+    assert!(matches!(csr.write(MHARTID, 1, Xlen::Rv64), Err(CsrError::ReadOnly(MHARTID))));
+}
+
+#[test]
+fn test_csr_misa() {
+    let csr = CsrFile::new();
+
+    let misa = csr.read(MISA, Xlen::Rv64).unwrap();
+    assert_eq!(misa >> 62, 2);
+    assert_ne!(misa & (1 << (b'I' - b'A')), 0);
+    assert_ne!(misa & (1 << (b'M' - b'A')), 0);
+    assert_ne!(misa & (1 << (b'C' - b'A')), 0);
+    assert_ne!(misa & (1 << (b'F' - b'A')), 0);
+    assert_ne!(misa & (1 << (b'D' - b'A')), 0);
+
+    assert_eq!(csr.read(MISA, Xlen::Rv32).unwrap() >> 30, 1);
+
+    // Without C bit 1 of the exception PCs reads as zero
+    let mut csr = CsrFile::new();
+    csr.write(MEPC, 0x1006, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MEPC, Xlen::Rv64).unwrap(), 0x1006);
+    csr.extensions &= !(1 << (b'C' - b'A'));
+    assert_eq!(csr.read(MEPC, Xlen::Rv64).unwrap(), 0x1004);
+    assert_eq!(csr.read(MISA, Xlen::Rv64).unwrap() & (1 << (b'C' - b'A')), 0);
+}
+
+#[test]
+fn test_csr_rv32_counter_halves() {
+    let mut csr = CsrFile::new();
+
+    csr.write(MCYCLE, 0xFFFF_FFFF_1234_5678, Xlen::Rv32).unwrap();
+    csr.write(MCYCLEH, 0xAB, Xlen::Rv32).unwrap();
+
+    assert_eq!(csr.cycle, 0xAB_1234_5678);
+    assert_eq!(csr.read(MCYCLEH, Xlen::Rv32).unwrap(), 0xAB);
+}
+
+#[test]
+fn test_csr_supervisor_views() {
+    let mut csr = CsrFile::new();
+
+    csr.write(MSTATUS, MSTATUS_MIE | MSTATUS_SIE | MSTATUS_MPP, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(SSTATUS, Xlen::Rv64).unwrap() & 0xFFFF_FFFF, MSTATUS_SIE);
+
+    csr.write(SSTATUS, 0, Xlen::Rv64).unwrap();
+    assert_eq!(csr.mstatus, MSTATUS_MIE | MSTATUS_MPP);
+
+    // Only delegated interrupts show up in sie and sip
+    csr.write(MIDELEG, u64::MAX, Xlen::Rv64).unwrap();
+    assert_eq!(csr.mideleg, MIP_SSIP | MIP_STIP | MIP_SEIP);
+    csr.write(MIE, MIP_MTIP | MIP_STIP, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(SIE, Xlen::Rv64).unwrap(), MIP_STIP);
+
+    csr.write(SIP, u64::MAX, Xlen::Rv64).unwrap();
+    assert_eq!(csr.mip, MIP_SSIP);
+}
+
+#[test]
+fn test_csr_delegation_registers() {
+    let mut csr = CsrFile::new();
+
+    csr.write(MEDELEG, u64::MAX, Xlen::Rv64).unwrap();
+    assert_eq!(csr.medeleg & (1 << 11), 0);
+    assert_ne!(csr.medeleg & (1 << 8), 0);
+}
+
+#[test]
+fn test_csr_access_checks() {
+    let mut csr = CsrFile::new();
+
+    assert!(csr.check_access(MSTATUS, Privilege::Machine).is_ok());
+    assert!(matches!(csr.check_access(MSTATUS, Privilege::Supervisor), Err(CsrError::Privileged(MSTATUS))));
+    assert!(csr.check_access(SSTATUS, Privilege::Supervisor).is_ok());
+    assert!(matches!(csr.check_access(SSTATUS, Privilege::User), Err(CsrError::Privileged(SSTATUS))));
+
+    // Counters need to be enabled for every mode below M
+    assert!(csr.check_access(CYCLE, Privilege::Supervisor).is_err());
+    csr.mcounteren = 1;
+    assert!(csr.check_access(CYCLE, Privilege::Supervisor).is_ok());
+    assert!(csr.check_access(CYCLE, Privilege::User).is_err());
+    csr.scounteren = 1;
+    assert!(csr.check_access(CYCLE, Privilege::User).is_ok());
+}
+
+#[test]
+fn test_csr_satp() {
+    let mut csr = CsrFile::new();
+
+    csr.write(SATP, (8 << 60) | 0x80000, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(SATP, Xlen::Rv64).unwrap(), (8 << 60) | 0x80000);
+
+    // Sv57 isn't supported, so the write is dropped entirely
+    csr.write(SATP, (10 << 60) | 0x1234, Xlen::Rv64).unwrap();
+    assert_eq!(csr.satp, (8 << 60) | 0x80000);
+
+    csr.write(SATP, (1 << 31) | 0x1234, Xlen::Rv32).unwrap();
+    assert_eq!(csr.read(SATP, Xlen::Rv32).unwrap(), (1 << 31) | 0x1234);
+
+    assert!(csr.check_access(SATP, Privilege::Supervisor).is_ok());
+    csr.mstatus |= MSTATUS_TVM;
+    assert!(matches!(csr.check_access(SATP, Privilege::Supervisor), Err(CsrError::Privileged(SATP))));
+    assert!(csr.check_access(SATP, Privilege::Machine).is_ok());
+}
+
+#[test]
+fn test_csr_pmp_storage() {
+    let mut csr = CsrFile::new();
+
+    csr.write(PMPADDR0, 0x3F_FFFF_FFFF_FFFF, Xlen::Rv64).unwrap();
+    csr.write(PMPCFG0, 0xF, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(PMPADDR0, Xlen::Rv64).unwrap(), 0x3F_FFFF_FFFF_FFFF);
+    assert_eq!(csr.read(PMPCFG0, Xlen::Rv64).unwrap(), 0xF);
+
+    assert!(csr.read(PMPCFG0 + 1, Xlen::Rv64).is_err());
+    assert!(csr.read(PMPCFG0 + 1, Xlen::Rv32).is_ok());
+}
+#[test]
+fn test_csr_floating_point() {
+    let mut csr = CsrFile::new();
+    assert_eq!(csr.mstatus & MSTATUS_FS, MSTATUS_FS_INITIAL);
+
+    csr.write(FCSR, 0xFFF, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(FFLAGS, Xlen::Rv64).unwrap(), 0x1F);
+    assert_eq!(csr.read(FRM, Xlen::Rv64).unwrap(), 0b111);
+    csr.write(FRM, 0b001, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(FCSR, Xlen::Rv64).unwrap(), 0x3F);
+
+    // Writing the floating point state marks it dirty, which shows up in SD
+    assert_eq!(csr.mstatus & MSTATUS_FS, MSTATUS_FS_DIRTY);
+    assert_eq!(csr.read(MSTATUS, Xlen::Rv64).unwrap() >> 63, 1);
+    assert_eq!(csr.read(SSTATUS, Xlen::Rv32).unwrap() >> 31, 1);
+
+    csr.write(SSTATUS, 0, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(MSTATUS, Xlen::Rv64).unwrap() >> 63, 0);
+    assert!(matches!(csr.check_access(FCSR, Privilege::User), Err(CsrError::Disabled(FCSR))));
+}
+
+#[test]
+fn test_csr_vector() {
+    let mut csr = CsrFile::new();
+    assert_eq!(csr.mstatus & MSTATUS_VS, MSTATUS_VS_INITIAL);
+    assert_eq!(csr.read(VLENB, Xlen::Rv64).unwrap(), 16);
+    assert_eq!(csr.read(VTYPE, Xlen::Rv64).unwrap(), 1 << 63);
+    assert_eq!(csr.read(VTYPE, Xlen::Rv32).unwrap(), 1 << 31);
+
+    // vl, vtype and vlenb can only be changed by the vector instructions
+    assert!(csr.write(VL, 4, Xlen::Rv64).is_err());
+
+    csr.write(VCSR, 0b111, Xlen::Rv64).unwrap();
+    assert_eq!(csr.read(VXRM, Xlen::Rv64).unwrap(), 0b11);
+    assert_eq!(csr.read(VXSAT, Xlen::Rv64).unwrap(), 1);
+    assert_eq!(csr.mstatus & MSTATUS_VS, MSTATUS_VS_DIRTY);
+    assert_eq!(csr.read(MSTATUS, Xlen::Rv64).unwrap() >> 63, 1);
+
+    csr.write(MSTATUS, 0, Xlen::Rv64).unwrap();
+    assert!(matches!(csr.check_access(VL, Privilege::User), Err(CsrError::Disabled(VL))));
+}
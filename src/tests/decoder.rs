@@ -25,6 +25,17 @@ fn test_itype_decode() {
     assert_eq!(i.imm, -0x04);
 }
 
+#[test]
+fn test_itype_decode_rv64_shamt() {
+    let raw = 0x4285D593; // srai a1, a1, 40
+    let i = IType::from(raw);
+
+    assert_eq!(i.opcode, 0x13);
+    assert_eq!(i.func3, 0x05);
+    assert_eq!(i.shamt, 40);
+    assert_eq!(i.func7 >> 1, 0x10);
+}
+
 #[test]
 fn test_stype_decode() {
     let raw = 0xFE752C23;
@@ -69,6 +80,20 @@ fn test_jtype_decode() {
     assert_eq!(j.imm, -0xFFFFE);
 }
 
+#[test]
+fn test_r4type_decode() {
+    let raw = 0x12217243; // fmadd.d ft4, ft2, ft2, ft2
+    let r = R4Type::from(raw);
+
+    assert_eq!(r.opcode, 0x43);
+    assert_eq!(r.rd, 0x04);
+    assert_eq!(r.func3, 0x07);
+    assert_eq!(r.rs1, 0x02);
+    assert_eq!(r.rs2, 0x02);
+    assert_eq!(r.func2, 0x01);
+    assert_eq!(r.rs3, 0x02);
+}
+
 #[test]
 fn test_opcode_decode() {
     let instructions = [
@@ -81,7 +106,15 @@ fn test_opcode_decode() {
         (0x67, DecodedInstr::I(IType::from(0))),
         (0x37, DecodedInstr::U(UType::from(0))),
         (0x17, DecodedInstr::U(UType::from(0))),
-        (0x73, DecodedInstr::I(IType::from(0)))
+        (0x73, DecodedInstr::I(IType::from(0))),
+        (0x2007, DecodedInstr::I(IType::from(0))),
+        (0x2027, DecodedInstr::S(SType::from(0))),
+        (0x07, DecodedInstr::V(VType::from(0))),
+        (0x6027, DecodedInstr::V(VType::from(0))),
+        (0x57, DecodedInstr::V(VType::from(0))),
+        (0x43, DecodedInstr::R4(R4Type::from(0))),
+        (0x4F, DecodedInstr::R4(R4Type::from(0))),
+        (0x53, DecodedInstr::R(RType::from(0)))
     ];
 
     fn same_variant(a: &DecodedInstr, b: &DecodedInstr) -> bool {
@@ -140,7 +173,7 @@ fn read_expected_instructions(test: &str) -> Result<Vec<DecodedInstr>, io::Error
             let instr_type = splitted_data.remove(1);
 
             let data = splitted_data.iter()
-                .filter_map(|keypair| read_key_pair(&keypair))
+                .filter_map(|keypair| read_key_pair(keypair))
                 .collect::<HashMap<&str, u32>>();
 
             let opcode = *data.get("op")? as u8;
@@ -164,7 +197,7 @@ fn read_expected_instructions(test: &str) -> Result<Vec<DecodedInstr>, io::Error
                         func3: *data.get("func").unwrap_or(&0) as u8,
                         rs1: *data.get("rs1").unwrap_or(&0) as u8,
                         imm: ((*data.get("imm").unwrap_or(&0) as i32) << 20) >> 20,
-                        shamt: (*data.get("imm").unwrap_or(&0) & 0x1F) as u8,
+                        shamt: (*data.get("imm").unwrap_or(&0) & 0x3F) as u8,
                         func7: (*data.get("imm").unwrap_or(&0) >> 5) as u8,
                     }))
                 },
@@ -200,7 +233,7 @@ fn read_expected_instructions(test: &str) -> Result<Vec<DecodedInstr>, io::Error
                         imm: ((*data.get("imm").unwrap_or(&0) as i32) << 11) >> 11
                     }))
                 },
-                _ => return None
+                _ => None
             }
         })
         .collect::<Vec<DecodedInstr>>();
@@ -214,7 +247,7 @@ fn run_test(test: &str) -> Result<(), io::Error> {
 
     for (instruction, expected) in instructions.iter().zip(expected) {
         let decoded = decode_instruction(*instruction)
-            .expect(&format!("Couldn't decode instruction 0x{:08x}", instruction));
+            .unwrap_or_else(|_| panic!("Couldn't decode instruction 0x{:08x}", instruction));
 
         assert_eq!(decoded, expected);
     }
@@ -0,0 +1,119 @@
+use crate::fpu::*;
+
+const ONE: u64 = 0x3F80_0000;
+const TWO: u64 = 0x4000_0000;
+const THREE: u64 = 0x4040_0000;
+const SIGNALING_NAN: u64 = 0x7F80_0001;
+
+#[test]
+fn test_fpu_rounding_modes() {
+    // 1/3 isn't representable, so the rounding mode picks the neighbour
+    assert_eq!(div(Format::Single, ONE, THREE, RoundingMode::NearestEven), (0x3EAA_AAAB, NX));
+    assert_eq!(div(Format::Single, ONE, THREE, RoundingMode::TowardZero), (0x3EAA_AAAA, NX));
+    assert_eq!(div(Format::Single, ONE, THREE, RoundingMode::Up), (0x3EAA_AAAB, NX));
+    assert_eq!(div(Format::Single, ONE | 0x8000_0000, THREE, RoundingMode::Up), (0xBEAA_AAAA, NX));
+
+    // 0.1 + 0.2 lands one ulp above 0.3
+    assert_eq!(add(Format::Double, 0x3FB9_9999_9999_999A, 0x3FC9_9999_9999_999A, RoundingMode::NearestEven), (0x3FD3_3333_3333_3334, NX));
+    assert_eq!(add(Format::Single, ONE, TWO, RoundingMode::NearestEven), (THREE, 0));
+
+    assert_eq!(RoundingMode::from_bits(0b100), Some(RoundingMode::NearestMaxMagnitude));
+    assert_eq!(RoundingMode::from_bits(0b101), None);
+}
+
+#[test]
+fn test_fpu_exception_flags() {
+    assert_eq!(div(Format::Single, ONE, 0, RoundingMode::NearestEven), (0x7F80_0000, DZ));
+    assert_eq!(sqrt(Format::Single, 0xBF80_0000, RoundingMode::NearestEven), (0x7FC0_0000, NV));
+    assert_eq!(sqrt(Format::Double, 0x4010_0000_0000_0000, RoundingMode::NearestEven), (0x4000_0000_0000_0000, 0));
+
+    // Overflow goes to infinity or the largest finite value depending on the direction
+    assert_eq!(mul(Format::Single, 0x7F7F_FFFF, TWO, RoundingMode::NearestEven), (0x7F80_0000, OF | NX));
+    assert_eq!(mul(Format::Single, 0x7F7F_FFFF, TWO, RoundingMode::TowardZero), (0x7F7F_FFFF, OF | NX));
+
+    // Underflow is only raised for inexact tiny results
+    assert_eq!(mul(Format::Single, 0x0080_0000, 0x3F00_0000, RoundingMode::NearestEven), (0x0040_0000, 0));
+    assert_eq!(mul(Format::Single, 0x0000_0001, 0x3F00_0000, RoundingMode::NearestEven), (0, UF | NX));
+    assert_eq!(mul(Format::Single, 0x0000_0001, 0x3F00_0000, RoundingMode::Up), (0x0000_0001, UF | NX));
+
+    // Infinity minus infinity is invalid
+    assert_eq!(sub(Format::Single, 0x7F80_0000, 0x7F80_0000, RoundingMode::NearestEven), (0x7FC0_0000, NV));
+}
+
+#[test]
+fn test_fpu_fused_multiply_add() {
+    // (1 + 2^-23)^2 - (1 + 2^-22) is exactly 2^-46, which a separate multiply would round away
+    let a = 0x3F80_0001;
+    assert_eq!(fused_mul_add(Format::Single, a, a, 0x3F80_0002, false, true, RoundingMode::NearestEven), (0x2880_0000, 0));
+    assert_eq!(fused_mul_add(Format::Single, TWO, THREE, ONE, true, true, RoundingMode::NearestEven), (0xC0E0_0000, 0));
+
+    // 0 * inf is invalid even when the addend is a quiet NaN
+    assert_eq!(fused_mul_add(Format::Single, 0, 0x7F80_0000, 0x7FC0_0000, false, false, RoundingMode::NearestEven), (0x7FC0_0000, NV));
+}
+
+#[test]
+fn test_fpu_nan_boxing() {
+    assert_eq!(nan_box(ONE), 0xFFFF_FFFF_3F80_0000);
+    assert_eq!(unbox(Format::Single, 0xFFFF_FFFF_3F80_0000), ONE);
+    // Improperly boxed values read as the canonical NaN
+    assert_eq!(unbox(Format::Single, 0x0000_0000_3F80_0000), 0x7FC0_0000);
+    assert_eq!(Format::Double.canonical_nan(), 0x7FF8_0000_0000_0000);
+}
+
+#[test]
+fn test_fpu_compare_and_min_max() {
+    assert_eq!(eq(Format::Single, 0, 0x8000_0000), (true, 0));
+    assert_eq!(eq(Format::Single, 0x7FC0_0000, ONE), (false, 0));
+    assert_eq!(eq(Format::Single, SIGNALING_NAN, ONE), (false, NV));
+    assert_eq!(lt(Format::Single, 0x7FC0_0000, ONE), (false, NV));
+    assert_eq!(lt(Format::Single, 0xBF80_0000, ONE), (true, 0));
+    assert_eq!(le(Format::Single, ONE, ONE), (true, 0));
+
+    assert_eq!(min_max(Format::Single, 0, 0x8000_0000, false), (0x8000_0000, 0));
+    assert_eq!(min_max(Format::Single, 0, 0x8000_0000, true), (0, 0));
+    assert_eq!(min_max(Format::Single, 0x7FC0_0000, ONE, false), (ONE, 0));
+    assert_eq!(min_max(Format::Single, SIGNALING_NAN, ONE, true), (ONE, NV));
+    assert_eq!(min_max(Format::Single, SIGNALING_NAN, 0x7FC0_0000, true), (0x7FC0_0000, NV));
+}
+
+#[test]
+fn test_fpu_classify_and_sign_injection() {
+    assert_eq!(classify(Format::Single, 0xFF80_0000), 1 << 0);
+    assert_eq!(classify(Format::Single, 0xBF80_0000), 1 << 1);
+    assert_eq!(classify(Format::Single, 0x8000_0000), 1 << 3);
+    assert_eq!(classify(Format::Single, 0), 1 << 4);
+    assert_eq!(classify(Format::Single, 0x0000_0001), 1 << 5);
+    assert_eq!(classify(Format::Double, 0x3FF0_0000_0000_0000), 1 << 6);
+    assert_eq!(classify(Format::Single, SIGNALING_NAN), 1 << 8);
+    assert_eq!(classify(Format::Single, 0x7FC0_0000), 1 << 9);
+
+    assert_eq!(sign_inject(Format::Single, ONE, 0x8000_0000, SignInjection::Copy), 0xBF80_0000);
+    assert_eq!(sign_inject(Format::Single, ONE, ONE, SignInjection::Negate), 0xBF80_0000);
+    assert_eq!(sign_inject(Format::Single, 0xBF80_0000, 0x8000_0000, SignInjection::Xor), ONE);
+}
+
+#[test]
+fn test_fpu_integer_conversions() {
+    let two_and_a_half = 0x4020_0000;
+    assert_eq!(to_int(Format::Single, two_and_a_half, true, 32, RoundingMode::NearestEven), (2, NX));
+    assert_eq!(to_int(Format::Single, two_and_a_half, true, 32, RoundingMode::NearestMaxMagnitude), (3, NX));
+    assert_eq!(to_int(Format::Single, two_and_a_half | 0x8000_0000, true, 64, RoundingMode::Down), (-3i64 as u64, NX));
+
+    // Out of range values saturate, NaN converts to the largest positive integer
+    assert_eq!(to_int(Format::Single, 0xBF80_0000, false, 32, RoundingMode::NearestEven), (0, NV));
+    assert_eq!(to_int(Format::Single, 0x7FC0_0000, true, 32, RoundingMode::NearestEven), (0x7FFF_FFFF, NV));
+    assert_eq!(to_int(Format::Double, 0x43E0_0000_0000_0000, true, 64, RoundingMode::NearestEven), (i64::MAX as u64, NV));
+
+    assert_eq!(from_int(Format::Single, -1i64 as u64, true, 32, RoundingMode::NearestEven), (0xBF80_0000, 0));
+    assert_eq!(from_int(Format::Single, 0xFFFF_FFFF, false, 32, RoundingMode::NearestEven), (0x4F80_0000, NX));
+    assert_eq!(from_int(Format::Single, (1 << 24) + 1, true, 64, RoundingMode::NearestEven), (0x4B80_0000, NX));
+    assert_eq!(from_int(Format::Double, u64::MAX, false, 64, RoundingMode::TowardZero), (0x43EF_FFFF_FFFF_FFFF, NX));
+}
+
+#[test]
+fn test_fpu_format_conversions() {
+    assert_eq!(convert(Format::Double, Format::Single, 0x3FB9_9999_9999_999A, RoundingMode::NearestEven), (0x3DCC_CCCD, NX));
+    assert_eq!(convert(Format::Single, Format::Double, ONE, RoundingMode::NearestEven), (0x3FF0_0000_0000_0000, 0));
+    assert_eq!(convert(Format::Single, Format::Double, SIGNALING_NAN, RoundingMode::NearestEven), (0x7FF8_0000_0000_0000, NV));
+    assert_eq!(convert(Format::Double, Format::Single, 0x47F0_0000_0000_0000, RoundingMode::NearestEven), (0x7F80_0000, OF | NX));
+}
@@ -0,0 +1,12 @@
+use crate::halt::*;
+
+#[test]
+fn test_halt_store() {
+    let halt = HaltConfig { test_finisher: Some(0x10_0000), ..Default::default() };
+
+    assert_eq!(halt.store(0x10_0000, FINISHER_PASS), Some(0));
+    assert_eq!(halt.store(0x10_0000, (42 << 16) | FINISHER_FAIL), Some(42));
+    assert_eq!(halt.store(0x10_0000, 0x7777), None);
+
+    assert_eq!(HaltConfig::default().store(0x10_0000, FINISHER_PASS), None);
+}
@@ -0,0 +1,135 @@
+use goblin::elf::Elf;
+
+use crate::{components::*, htif::*};
+
+use super::linux::build_elf64;
+
+/// Adds a symbol table with the given symbols to an ELF from `build_elf64`.
+fn with_symbols(mut elf: Vec<u8>, symbols: &[(&str, u64)]) -> Vec<u8> {
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; 24];
+    for (name, value) in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes()); // st_name
+        symtab.extend_from_slice(&[0x11, 0]); // st_info: global object, st_other
+        symtab.extend_from_slice(&1u16.to_le_bytes()); // st_shndx
+        symtab.extend_from_slice(&value.to_le_bytes()); // st_value
+        symtab.extend_from_slice(&8u64.to_le_bytes()); // st_size
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+
+    let strtab_offset = elf.len() as u64;
+    elf.extend_from_slice(&strtab);
+    elf.resize(elf.len().next_multiple_of(8), 0);
+    let symtab_offset = elf.len() as u64;
+    elf.extend_from_slice(&symtab);
+    let shoff = elf.len() as u64;
+
+    // A null section, the string table and the symbol table linking to it
+    let sections: [(u32, u64, u64, u32, u64); 3] = [
+        (0, 0, 0, 0, 0),
+        (3, strtab_offset, strtab.len() as u64, 0, 0),
+        (2, symtab_offset, symtab.len() as u64, 1, 24),
+    ];
+    for (kind, offset, size, link, entsize) in sections {
+        elf.extend_from_slice(&0u32.to_le_bytes()); // sh_name
+        elf.extend_from_slice(&kind.to_le_bytes()); // sh_type
+        elf.extend_from_slice(&0u64.to_le_bytes()); // sh_flags
+        elf.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+        elf.extend_from_slice(&offset.to_le_bytes()); // sh_offset
+        elf.extend_from_slice(&size.to_le_bytes()); // sh_size
+        elf.extend_from_slice(&link.to_le_bytes()); // sh_link
+        elf.extend_from_slice(&(if kind == 2 { 1u32 } else { 0 }).to_le_bytes()); // sh_info
+        elf.extend_from_slice(&8u64.to_le_bytes()); // sh_addralign
+        elf.extend_from_slice(&entsize.to_le_bytes()); // sh_entsize
+    }
+
+    elf[40..48].copy_from_slice(&shoff.to_le_bytes()); // e_shoff
+    elf[60..62].copy_from_slice(&3u16.to_le_bytes()); // e_shnum
+    elf
+}
+
+#[test]
+fn test_htif_from_elf() {
+    let bytes = with_symbols(build_elf64(0x10000, &[0x00000073]), &[("fromhost", 0x11040), ("tohost", 0x11000)]);
+    let htif = Htif::from_elf(&Elf::parse(&bytes).unwrap()).unwrap();
+    assert_eq!((htif.tohost, htif.fromhost), (0x11000, Some(0x11040)));
+
+    let mut cpu = CPU::new(1 << 20);
+    cpu.load_elf(&bytes).unwrap();
+    assert_eq!(cpu.htif.map(|htif| htif.tohost), Some(0x11000));
+
+    // Programs without the symbol don't get the device
+    assert!(Htif::from_elf(&Elf::parse(&build_elf64(0x10000, &[0x00000073])).unwrap()).is_none());
+}
+
+#[test]
+fn test_htif_commands() {
+    let mut mem = Memory::new(0x100);
+    let mut htif = Htif::new(0x40, Some(0x48));
+
+    // Nothing to do for a zero
+    assert_eq!(htif.command(&mut mem), None);
+
+    // The console answers every character, one response at a time
+    mem.write_double_word(0x40, 0x0101_0000_0000_0021).unwrap();
+    assert_eq!(htif.command(&mut mem), None);
+    assert_eq!(mem.read_double_word(0x40).unwrap(), 0);
+    assert_eq!(mem.read_double_word(0x48).unwrap(), 0x0101_0000_0000_0000);
+
+    mem.write_double_word(0x40, 0x0101_0000_0000_000A).unwrap();
+    assert_eq!(htif.command(&mut mem), None);
+    htif.poll(&mut mem);
+    assert_eq!(mem.read_double_word(0x48).unwrap(), 0x0101_0000_0000_0000);
+    mem.write_double_word(0x48, 0).unwrap();
+    htif.poll(&mut mem);
+    assert_eq!(mem.read_double_word(0x48).unwrap(), 0x0101_0000_0000_0000);
+    mem.write_double_word(0x48, 0).unwrap();
+    htif.poll(&mut mem);
+    assert_eq!(mem.read_double_word(0x48).unwrap(), 0);
+
+    // Proxied syscalls aren't run, but they're answered
+    mem.write_double_word(0x80, 64).unwrap();
+    mem.write_double_word(0x40, 0x80).unwrap();
+    assert_eq!(htif.command(&mut mem), None);
+    assert_eq!(mem.read_double_word(0x40).unwrap(), 0);
+    assert_eq!(mem.read_double_word(0x80).unwrap() as i64, -38);
+    assert_eq!(mem.read_double_word(0x48).unwrap(), 1);
+    mem.write_double_word(0x48, 0).unwrap();
+
+    mem.write_double_word(0x40, (3 << 1) | 1).unwrap();
+    assert_eq!(htif.command(&mut mem), Some(3));
+}
+
+#[test]
+fn test_cpu_htif() {
+    let mut cpu = CPU::with_xlen(0x100, crate::stages::Xlen::Rv32);
+    cpu.htif = Some(Htif::new(0x40, Some(0x48)));
+    for (i, instruction) in [
+        0x02100293u32, // li t0, '!'
+        0x04502023, // sw t0, 64(zero)
+        0x010102B7, // lui t0, 0x1010
+        0x04502223, // sw t0, 68(zero)
+        0x04C02303, // lw t1, 76(zero)
+        0x00100293, // li t0, 1
+        0x04502023, // sw t0, 64(zero)
+        0x04002223, // sw zero, 68(zero)
+    ].into_iter().enumerate() {
+        cpu.mem.write_word(i * 4, instruction as u64).unwrap();
+    }
+
+    // The low word goes first, like riscv-tests and compilers write it, the store of the upper
+    // one starts the command
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.mem.read_double_word(0x40).unwrap(), 0x21);
+    for _ in 0..3 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.regs[6], 0x0101_0000);
+    assert_eq!(cpu.mem.read_double_word(0x40).unwrap(), 0);
+
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert!(matches!(cpu.cycle(), Err(CPUError::Exit { code: 0 })));
+}
@@ -0,0 +1,92 @@
+use crate::{isa::*, stages::{decode_instruction, Xlen}};
+
+#[test]
+fn test_isa_parse() {
+    let isa: Isa = "rv64imac_zicsr_zifencei_zba_zbb".parse().unwrap();
+    assert_eq!(isa.xlen, Xlen::Rv64);
+    assert!(isa.has(Extension::Zba));
+    assert!(isa.has(Extension::Zbb));
+    assert!(!isa.has(Extension::Zbc));
+    assert!(!isa.has(Extension::Zbs));
+
+    // B is shorthand for Zba, Zbb and Zbs
+    let isa: Isa = "RV32GCB".parse().unwrap();
+    assert_eq!(isa.xlen, Xlen::Rv32);
+    assert!(isa.has(Extension::Zbs));
+    assert!(!isa.has(Extension::Zbc));
+
+    assert_eq!("rv128i".parse::<Isa>(), Err(IsaError::InvalidBase("rv128i".into())));
+    assert_eq!("rv64iq".parse::<Isa>(), Err(IsaError::UnknownExtension("q".into())));
+    assert_eq!("rv64i_zbkb".parse::<Isa>(), Err(IsaError::UnknownExtension("zbkb".into())));
+
+    assert!(Isa::default().has(Extension::Zbc));
+    assert_eq!("rv64e".parse::<Isa>(), Err(IsaError::InvalidBase("rv64e".into())));
+
+    // G pulls in the CSR instructions and FENCE.I, D depends on F
+    let isa: Isa = "rv32gc".parse().unwrap();
+    assert!(isa.has(Extension::Zicsr) && isa.has(Extension::Zifencei) && isa.has(Extension::C));
+    let isa: Isa = "rv64imd".parse().unwrap();
+    assert!(isa.has(Extension::F) && isa.has(Extension::Zicsr));
+    assert!(!isa.has(Extension::A) && !isa.has(Extension::C) && !isa.has(Extension::Zifencei));
+    assert!("rv64gcv".parse::<Isa>().unwrap().has(Extension::V));
+    assert!(!"rv64gc".parse::<Isa>().unwrap().has(Extension::V));
+}
+
+#[test]
+fn test_isa_required_extension() {
+    let extension = |raw| required_extension(&decode_instruction(raw).unwrap());
+
+    assert_eq!(extension(0x00C58533), None); // add a0, a1, a2
+    assert_eq!(extension(0x02C58533), Some(Extension::M)); // mul a0, a1, a2
+    assert_eq!(extension(0x00B6252F), Some(Extension::A)); // amoadd.w a0, a1, (a2)
+    assert_eq!(extension(0x0020F053), Some(Extension::F)); // fadd.s ft0, ft1, ft2
+    assert_eq!(extension(0x4010F053), Some(Extension::D)); // fcvt.s.d ft0, ft1
+    assert_eq!(extension(0x00053007), Some(Extension::D)); // fld ft0, 0(a0)
+    assert_eq!(extension(0x00052027), Some(Extension::F)); // fsw ft0, 0(a0)
+    assert_eq!(extension(0x1A20F043), Some(Extension::D)); // fmadd.d ft0, ft1, ft2, ft3
+    assert_eq!(extension(0x30002573), Some(Extension::Zicsr)); // csrr a0, mstatus
+    assert_eq!(extension(0x0000100F), Some(Extension::Zifencei)); // fence.i
+    assert_eq!(extension(0x0FF0000F), None); // fence
+    assert_eq!(extension(0x00000073), None); // ecall
+    assert_eq!(extension(0x20C5A533), Some(Extension::Zba)); // sh1add a0, a1, a2
+    assert_eq!(extension(0x0845951B), Some(Extension::Zba)); // slli.uw a0, a1, 4
+    assert_eq!(extension(0x60259513), Some(Extension::Zbb)); // cpop a0, a1
+    assert_eq!(extension(0x2875D513), Some(Extension::Zbb)); // orc.b a0, a1
+    assert_eq!(extension(0x0805C53B), Some(Extension::Zbb)); // zext.h a0, a1
+    assert_eq!(extension(0x0AC5B533), Some(Extension::Zbc)); // clmulh a0, a1, a2
+    assert_eq!(extension(0x4BF5D513), Some(Extension::Zbs)); // bexti a0, a1, 63
+    assert_eq!(extension(0x40B55513), None); // srai a0, a0, 11
+    assert_eq!(extension(0x022180D7), Some(Extension::V)); // vadd.vv v1, v2, v3
+}
+
+#[test]
+fn test_isa_versioned_strings() {
+    // The format toolchains put in .riscv.attributes
+    let isa: Isa = "rv64i2p1_m2p0_a2p1_c2p0_zicsr2p0_zifencei2p0_zmmul1p0_zaamo1p0_zca1p0".parse().unwrap();
+    assert_eq!(isa, "rv64imac_zicsr_zifencei".parse().unwrap());
+    assert_eq!(isa.to_string(), "rv64imac_zicsr_zifencei");
+
+    assert_eq!("rv32i2_m2".parse::<Isa>().unwrap().to_string(), "rv32im");
+    assert_eq!(Isa::default().to_string(), "rv64imafdcv_zicsr_zifencei_zba_zbb_zbc_zbs");
+    assert_eq!("rv64i2p0_zfoo1p0".parse::<Isa>(), Err(IsaError::UnknownExtension("zfoo".into())));
+}
+
+#[test]
+fn test_isa_missing_and_misa() {
+    let isa: Isa = "rv64imac_zicsr".parse().unwrap();
+    let required: Isa = "rv64gc".parse().unwrap();
+    assert_eq!(isa.missing(&required), [Extension::F, Extension::D, Extension::Zifencei]);
+    assert!(required.missing(&isa).is_empty());
+
+    let letters = |letters: &str| letters.bytes().fold(0, |misa, letter| misa | 1 << (letter - b'a'));
+    assert_eq!(isa.misa_extensions(), letters("imacsu"));
+    assert_eq!("rv64i_zba_zbb_zbs".parse::<Isa>().unwrap().misa_extensions(), letters("ibsu"));
+    assert_eq!("rv64i_zba_zbb".parse::<Isa>().unwrap().misa_extensions(), letters("isu"));
+}
+
+#[test]
+fn test_isa_elf_arch() {
+    let elf = std::fs::read("./testdata/programs/basic.bin").unwrap();
+    assert_eq!(elf_arch(&elf).as_deref(), Some("rv64i2p0_m2p0_a2p0_f2p0_d2p0"));
+    assert_eq!(elf_arch(&elf[..64]), None);
+}
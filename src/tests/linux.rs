@@ -0,0 +1,188 @@
+use crate::{components::*, csr::Privilege, linux::*, stages::Xlen, syscall::*};
+
+/// Builds a minimal ELF64 RISC-V executable with a single PT_LOAD segment at `vaddr`, the
+/// program headers are part of the segment.
+pub fn build_elf64(vaddr: u64, code: &[u32]) -> Vec<u8> {
+    let mut elf = Vec::new();
+
+    elf.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // e_type: EXEC
+    elf.extend_from_slice(&243u16.to_le_bytes()); // e_machine: RISC-V
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&(vaddr + 120).to_le_bytes()); // e_entry
+    elf.extend_from_slice(&64u64.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    elf.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+    elf.extend_from_slice(&56u16.to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    elf.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    elf.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    elf.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+    let size = 120 + code.len() as u64 * 4;
+    elf.extend_from_slice(&1u32.to_le_bytes()); // p_type: PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // p_flags: R+X
+    elf.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_vaddr
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_paddr
+    elf.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    elf.extend_from_slice(&(size + 0x2000).to_le_bytes()); // p_memsz
+    elf.extend_from_slice(&4u64.to_le_bytes()); // p_align
+
+    for instruction in code {
+        elf.extend_from_slice(&instruction.to_le_bytes());
+    }
+
+    elf
+}
+
+fn linux_cpu(args: &[&str], env: &[&str]) -> CPU {
+    let mut cpu = CPU::new(4 << 20);
+    let to_strings = |strings: &[&str]| strings.iter().map(|string| string.to_string()).collect();
+    cpu.syscalls = Syscalls::Linux(Linux::new(to_strings(args), to_strings(env)));
+    cpu.load_elf(&build_elf64(0x10000, &[0x00000073])).unwrap(); // ecall
+    cpu
+}
+
+/// Runs the ECALL at the entry point with the given syscall number and arguments.
+pub(super) fn syscall(cpu: &mut CPU, number: u64, args: &[u64]) -> Result<u64, CPUError> {
+    cpu.pc.set(0x10078);
+    cpu.regs[17] = number;
+    cpu.regs[10..10 + args.len()].copy_from_slice(args);
+    cpu.cycle()?;
+    Ok(cpu.regs[10])
+}
+
+fn brk(cpu: &CPU) -> u64 {
+    match &cpu.syscalls {
+        Syscalls::Linux(linux) => linux.brk,
+        _ => unreachable!(),
+    }
+}
+
+pub(super) fn write_string(cpu: &mut CPU, address: usize, string: &str) {
+    cpu.mem.data[address..address + string.len() + 1].copy_from_slice(&[string.as_bytes(), &[0]].concat());
+}
+
+#[test]
+fn test_linux_initial_stack() {
+    let cpu = linux_cpu(&["prog", "hello"], &["HOME=/"]);
+    let sp = cpu.regs[2] as usize;
+    let word = |i: usize| cpu.mem.read_double_word(sp + i * 8).unwrap();
+    let string = |address: u64| guest_string(&cpu.mem, address).unwrap();
+
+    assert_eq!(sp % 16, 0);
+    assert_eq!(cpu.privilege, Privilege::User);
+    assert_eq!(cpu.pc.address, 0x10078);
+
+    assert_eq!(word(0), 2);
+    assert_eq!(string(word(1)), "prog");
+    assert_eq!(string(word(2)), "hello");
+    assert_eq!(word(3), 0);
+    assert_eq!(string(word(4)), "HOME=/");
+    assert_eq!(word(5), 0);
+
+    let auxv = (6..).step_by(2).map(|i| (word(i), word(i + 1))).take_while(|(key, _)| *key != 0).collect::<Vec<_>>();
+    let aux = |key| auxv.iter().find(|(k, _)| *k == key).unwrap().1;
+    assert_eq!(aux(3), 0x10040); // AT_PHDR
+    assert_eq!(aux(5), 1); // AT_PHNUM
+    assert_eq!(aux(6), 4096); // AT_PAGESZ
+    assert_eq!(aux(9), 0x10078); // AT_ENTRY
+    assert_eq!(string(aux(31)), "prog"); // AT_EXECFN
+    assert_ne!(aux(25), 0); // AT_RANDOM
+}
+
+#[test]
+fn test_linux_memory_syscalls() {
+    let mut cpu = linux_cpu(&["prog"], &[]);
+
+    // The break starts at the page after the segment's memory size
+    let start = brk(&cpu);
+    assert_eq!(start, 0x13000);
+    assert_eq!(syscall(&mut cpu, 214, &[0]).unwrap(), start); // brk(0)
+    assert_eq!(syscall(&mut cpu, 214, &[start + 0x1800]).unwrap(), start + 0x1800);
+    assert_eq!(syscall(&mut cpu, 214, &[0x100]).unwrap(), start + 0x1800);
+
+    // mmap(NULL, 5000, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+    let first = syscall(&mut cpu, 222, &[0, 5000, 3, 0x22, u64::MAX, 0]).unwrap();
+    let second = syscall(&mut cpu, 222, &[0, 4096, 3, 0x22, u64::MAX, 0]).unwrap();
+    assert_eq!(first % 4096, 0);
+    assert_eq!(first - second, 4096);
+    assert!(second > start && first + 0x2000 <= cpu.regs[2]);
+
+    let failed = syscall(&mut cpu, 222, &[0, 64 << 20, 3, 0x22, u64::MAX, 0]).unwrap();
+    assert_eq!(failed as i64, -ENOMEM);
+}
+
+#[test]
+fn test_linux_file_syscalls() {
+    let mut cpu = linux_cpu(&["prog"], &[]);
+    let path = std::env::temp_dir().join(format!("riscv-linux-test-{}", std::process::id()));
+    write_string(&mut cpu, 0x20000, path.to_str().unwrap());
+    write_string(&mut cpu, 0x20100, "Hello, ");
+    write_string(&mut cpu, 0x20110, "world!");
+
+    // openat(AT_FDCWD, path, O_RDWR | O_CREAT | O_TRUNC, 0644)
+    let fd = syscall(&mut cpu, 56, &[-100i64 as u64, 0x20000, 0o1102, 0o644]).unwrap();
+    assert_eq!(fd, 3);
+
+    // writev with two buffers
+    for (i, (base, length)) in [(0x20100, 7), (0x20110, 6)].into_iter().enumerate() {
+        cpu.mem.write_double_word(0x20200 + i * 16, base).unwrap();
+        cpu.mem.write_double_word(0x20208 + i * 16, length).unwrap();
+    }
+    assert_eq!(syscall(&mut cpu, 66, &[fd, 0x20200, 2]).unwrap(), 13);
+
+    assert_eq!(syscall(&mut cpu, 62, &[fd, 7, 0]).unwrap(), 7); // lseek(fd, 7, SEEK_SET)
+    assert_eq!(syscall(&mut cpu, 63, &[fd, 0x20300, 100]).unwrap(), 6); // read
+    assert_eq!(&cpu.mem.data[0x20300..0x20306], b"world!");
+
+    assert_eq!(syscall(&mut cpu, 80, &[fd, 0x20400]).unwrap(), 0); // fstat
+    assert_eq!(cpu.mem.read_word(0x20410).unwrap() as u32 & 0o170000, S_IFREG);
+    assert_eq!(cpu.mem.read_double_word(0x20430).unwrap(), 13);
+
+    assert_eq!(syscall(&mut cpu, 57, &[fd]).unwrap(), 0); // close
+    assert_eq!(syscall(&mut cpu, 57, &[fd]).unwrap() as i64, -EBADF);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello, world!");
+    std::fs::remove_file(&path).unwrap();
+
+    let missing = syscall(&mut cpu, 56, &[-100i64 as u64, 0x20000, 0, 0]).unwrap();
+    assert_eq!(missing as i64, -ENOENT);
+
+    // stdout is a terminal
+    assert_eq!(syscall(&mut cpu, 80, &[1, 0x20400]).unwrap(), 0);
+    assert_eq!(cpu.mem.read_word(0x20410).unwrap() as u32 & 0o170000, S_IFCHR);
+}
+
+#[test]
+fn test_linux_misc_syscalls() {
+    let mut cpu = linux_cpu(&["prog"], &[]);
+
+    assert_eq!(syscall(&mut cpu, 160, &[0x20000]).unwrap(), 0); // uname
+    assert_eq!(guest_string(&cpu.mem, 0x20000).unwrap(), "Linux");
+    assert_eq!(guest_string(&cpu.mem, 0x20000 + 4 * 65).unwrap(), "riscv64");
+
+    assert_eq!(syscall(&mut cpu, 113, &[0, 0x20100]).unwrap(), 0); // clock_gettime(CLOCK_REALTIME)
+    assert!(cpu.mem.read_double_word(0x20100).unwrap() > 1_600_000_000);
+    assert!(cpu.mem.read_double_word(0x20108).unwrap() < 1_000_000_000);
+
+    assert_eq!(syscall(&mut cpu, 278, &[0x20200, 32, 0]).unwrap(), 32); // getrandom
+    assert!(cpu.mem.data[0x20200..0x20220].iter().any(|byte| *byte != 0));
+
+    assert_eq!(syscall(&mut cpu, 9999, &[]).unwrap() as i64, -ENOSYS);
+    assert!(matches!(syscall(&mut cpu, 94, &[7]), Err(CPUError::Exit { code: 7 }))); // exit_group
+}
+
+#[test]
+fn test_linux_rv32_syscalls() {
+    let mut cpu = linux_cpu(&["prog"], &[]);
+    cpu.xlen = Xlen::Rv32;
+
+    // Errors are sign extended like every other 32 bit value
+    assert_eq!(syscall(&mut cpu, 57, &[42]).unwrap(), -EBADF as u64);
+
+    // Arguments only use the low 32 bits
+    assert_eq!(syscall(&mut cpu, 160, &[0xFFFF_FFFF_0002_0000]).unwrap(), 0);
+    assert_eq!(guest_string(&cpu.mem, 0x20000 + 4 * 65).unwrap(), "riscv32");
+}
@@ -0,0 +1,158 @@
+use crate::{components::Memory, csr::Privilege, mmu::*, stages::Xlen, trap::Exception};
+
+const ROOT: u64 = 0x1000;
+const SV39: u64 = 8 << 60;
+
+fn context(satp: u64, privilege: Privilege) -> AccessContext {
+    AccessContext { satp, xlen: Xlen::Rv64, privilege, sum: false, mxr: false }
+}
+
+/// Builds an Sv39 table in `mem` that maps the 4 KiB page at `vaddr` to `paddr`, with the
+/// intermediate tables at 0x2000 and 0x3000.
+fn map_sv39(mem: &mut Memory, vaddr: u64, paddr: u64, flags: u64) {
+    let vpn = |level: u64| (vaddr >> (12 + 9 * level)) & 0x1FF;
+
+    mem.write_double_word((ROOT + vpn(2) * 8) as usize, ((0x2000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word((0x2000 + vpn(1) * 8) as usize, ((0x3000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word((0x3000 + vpn(0) * 8) as usize, ((paddr >> 12) << 10) | flags).unwrap();
+}
+
+fn leaf_pte(mem: &Memory, vaddr: u64) -> u64 {
+    mem.read_double_word((0x3000 + ((vaddr >> 12) & 0x1FF) * 8) as usize).unwrap()
+}
+
+#[test]
+fn test_mmu_bare_and_machine_mode() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+
+    assert_eq!(mmu.translate(&mut mem, 0x1234, AccessType::Load, &context(0, Privilege::Supervisor)), Ok(0x1234));
+    assert_eq!(mmu.translate(&mut mem, 0x1234, AccessType::Load, &context(SV39 | (ROOT >> 12), Privilege::Machine)), Ok(0x1234));
+}
+
+#[test]
+fn test_mmu_sv39_translation() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = SV39 | (ROOT >> 12);
+    map_sv39(&mut mem, 0x4000_5000, 0x6000, PTE_V | PTE_R | PTE_W);
+
+    assert_eq!(mmu.translate(&mut mem, 0x4000_5123, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x6123));
+    assert_eq!(leaf_pte(&mem, 0x4000_5000) & (PTE_A | PTE_D), PTE_A);
+
+    // The first store to a page marks it dirty, even though the translation is cached
+    assert_eq!(mmu.translate(&mut mem, 0x4000_5008, AccessType::Store, &context(satp, Privilege::Supervisor)), Ok(0x6008));
+    assert_eq!(leaf_pte(&mem, 0x4000_5000) & (PTE_A | PTE_D), PTE_A | PTE_D);
+
+    assert_eq!(mmu.translate(&mut mem, 0x4000_5000, AccessType::Fetch, &context(satp, Privilege::Supervisor)), Err(Exception::InstructionPageFault));
+    assert_eq!(mmu.translate(&mut mem, 0x4000_6000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+}
+
+#[test]
+fn test_mmu_sv39_user_pages() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = SV39 | (ROOT >> 12);
+    map_sv39(&mut mem, 0x5000, 0x6000, PTE_V | PTE_R | PTE_X | PTE_U);
+    map_sv39(&mut mem, 0x7000, 0x7000, PTE_V | PTE_X);
+
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Fetch, &context(satp, Privilege::User)), Ok(0x6000));
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Store, &context(satp, Privilege::User)), Err(Exception::StorePageFault));
+    assert_eq!(mmu.translate(&mut mem, 0x7000, AccessType::Fetch, &context(satp, Privilege::User)), Err(Exception::InstructionPageFault));
+
+    // Supervisor mode needs SUM to read user pages and can never execute them
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+    let sum = AccessContext { sum: true, ..context(satp, Privilege::Supervisor) };
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &sum), Ok(0x6000));
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Fetch, &sum), Err(Exception::InstructionPageFault));
+
+    // MXR makes execute-only pages readable
+    assert_eq!(mmu.translate(&mut mem, 0x7000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+    let mxr = AccessContext { mxr: true, ..context(satp, Privilege::Supervisor) };
+    assert_eq!(mmu.translate(&mut mem, 0x7000, AccessType::Load, &mxr), Ok(0x7000));
+}
+
+#[test]
+fn test_mmu_sv39_superpages() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = SV39 | (ROOT >> 12);
+
+    // A 1 GiB gigapage in the root table and a 2 MiB megapage one level down
+    mem.write_double_word((ROOT + 8) as usize, ((0x8000_0000 >> 12) << 10) | PTE_V | PTE_R | PTE_A).unwrap();
+    mem.write_double_word(ROOT as usize, ((0x2000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word(0x2000 + 8, ((0x40_0000 >> 12) << 10) | PTE_V | PTE_R | PTE_A).unwrap();
+    mem.write_double_word(0x2000 + 16, ((0x40_1000 >> 12) << 10) | PTE_V | PTE_R | PTE_A).unwrap();
+
+    assert_eq!(mmu.translate(&mut mem, 0x4123_4567, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x8123_4567));
+    assert_eq!(mmu.translate(&mut mem, 0x20_3456, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x40_3456));
+
+    // The physical address of a megapage has to be 2 MiB aligned
+    assert_eq!(mmu.translate(&mut mem, 0x40_0000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+}
+
+#[test]
+fn test_mmu_sv39_invalid_addresses() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = SV39 | (ROOT >> 12);
+    map_sv39(&mut mem, 0x5000, 0x6000, PTE_V | PTE_W);
+
+    // Write-only PTEs are reserved
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Store, &context(satp, Privilege::Supervisor)), Err(Exception::StorePageFault));
+    // Bits 63:39 have to copy bit 38
+    assert_eq!(mmu.translate(&mut mem, 0x80_0000_0000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+    // A page table outside of memory is an access fault
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(SV39 | 0x100, Privilege::Supervisor)), Err(Exception::LoadAccessFault));
+}
+
+#[test]
+fn test_mmu_sv48_translation() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = (9 << 60) | (ROOT >> 12);
+
+    // The extra level maps vaddr[47:39]
+    mem.write_double_word((ROOT + 8) as usize, ((0x2000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word(0x2000, ((0x3000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word(0x3000, ((0x4000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_double_word(0x4000, ((0x6000 >> 12) << 10) | PTE_V | PTE_R).unwrap();
+
+    assert_eq!(mmu.translate(&mut mem, 0x80_0000_0010, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x6010));
+}
+
+#[test]
+fn test_mmu_sv32_translation() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = (1 << 31) | (ROOT >> 12);
+    let context = AccessContext { xlen: Xlen::Rv32, ..context(satp, Privilege::Supervisor) };
+
+    // Sv32 uses 4 byte PTEs and 10 bit VPNs
+    mem.write_word((ROOT + 0x301 * 4) as usize, ((0x2000 >> 12) << 10) | PTE_V).unwrap();
+    mem.write_word(0x2000 + 0x5 * 4, ((0x6000 >> 12) << 10) | PTE_V | PTE_R).unwrap();
+
+    assert_eq!(mmu.translate(&mut mem, 0xC040_5ABC, AccessType::Load, &context), Ok(0x6ABC));
+    assert_eq!(mem.read_word(0x2000 + 0x5 * 4).unwrap() & PTE_A, PTE_A);
+}
+
+#[test]
+fn test_mmu_tlb_flush() {
+    let mut mem = Memory::new(0x8000);
+    let mut mmu = Mmu::new();
+    let satp = SV39 | (ROOT >> 12);
+    map_sv39(&mut mem, 0x5000, 0x6000, PTE_V | PTE_R);
+
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x6000));
+
+    // Changing the page table without a fence keeps using the stale translation
+    map_sv39(&mut mem, 0x5000, 0x7000, PTE_V | PTE_R);
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x6000));
+
+    mmu.flush(Some(0x5000));
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(satp, Privilege::Supervisor)), Ok(0x7000));
+
+    map_sv39(&mut mem, 0x5000, 0x6000, 0);
+    mmu.flush(None);
+    assert_eq!(mmu.translate(&mut mem, 0x5000, AccessType::Load, &context(satp, Privilege::Supervisor)), Err(Exception::LoadPageFault));
+}
@@ -3,4 +3,37 @@ mod decoder;
 #[cfg(test)]
 mod components;
 #[cfg(test)]
-mod stages;
\ No newline at end of file
+mod stages;
+#[cfg(test)]
+mod csr;
+#[cfg(test)]
+mod mmu;
+#[cfg(test)]
+mod compressed;
+#[cfg(test)]
+mod fpu;
+
+#[cfg(test)]
+mod isa;
+#[cfg(test)]
+mod vector;
+#[cfg(test)]
+mod halt;
+#[cfg(test)]
+mod linux;
+#[cfg(test)]
+mod newlib;
+#[cfg(test)]
+mod semihosting;
+#[cfg(test)]
+mod htif;
+#[cfg(test)]
+mod bus;
+#[cfg(test)]
+mod uart;
+#[cfg(test)]
+mod clint;
+#[cfg(test)]
+mod plic;
+#[cfg(test)]
+mod virtio;
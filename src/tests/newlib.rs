@@ -0,0 +1,95 @@
+use crate::{components::*, csr::Privilege, newlib::*, stages::Xlen, syscall::*};
+
+use super::linux::{build_elf64, syscall, write_string};
+
+fn newlib_cpu(args: &[&str]) -> CPU {
+    let mut cpu = CPU::new(1 << 20);
+    cpu.syscalls = Syscalls::Newlib(Newlib::new(args.iter().map(|arg| arg.to_string()).collect()));
+    cpu.load_elf(&build_elf64(0x10000, &[0x00000073])).unwrap(); // ecall
+    cpu
+}
+
+#[test]
+fn test_newlib_initial_stack() {
+    let cpu = newlib_cpu(&["prog", "42"]);
+    let sp = cpu.regs[2] as usize;
+    let word = |i: usize| cpu.mem.read_double_word(sp + i * 8).unwrap();
+
+    assert_eq!(sp % 16, 0);
+    assert_eq!(cpu.privilege, Privilege::Machine);
+    assert_eq!(word(0), 2);
+    assert_eq!(guest_string(&cpu.mem, word(1)).unwrap(), "prog");
+    assert_eq!(guest_string(&cpu.mem, word(2)).unwrap(), "42");
+    assert_eq!(word(3), 0);
+
+    // Too little memory for the stack
+    let mut cpu = CPU::new(0x14000);
+    cpu.syscalls = Syscalls::Newlib(Newlib::new(vec![]));
+    assert!(matches!(cpu.load_elf(&build_elf64(0x10000, &[0x00000073])), Err(CPUError::ElfTooLittleMemoryError)));
+}
+
+#[test]
+fn test_newlib_brk() {
+    let mut cpu = newlib_cpu(&["prog"]);
+
+    // sbrk asks for the break first and then moves it
+    let start = syscall(&mut cpu, 214, &[0]).unwrap();
+    assert_eq!(start, 0x12080);
+    assert_eq!(syscall(&mut cpu, 214, &[start + 100]).unwrap(), start + 100);
+
+    // Not into the stack
+    assert_eq!(syscall(&mut cpu, 214, &[(1 << 20) - 16]).unwrap(), start + 100);
+}
+
+#[test]
+fn test_newlib_file_syscalls() {
+    let mut cpu = newlib_cpu(&["prog"]);
+    cpu.xlen = Xlen::Rv32;
+    let path = std::env::temp_dir().join(format!("riscv-newlib-test-{}", std::process::id()));
+    write_string(&mut cpu, 0x20000, path.to_str().unwrap());
+    write_string(&mut cpu, 0x20100, "Hello, world!");
+
+    // open(path, O_WRONLY | O_CREAT | O_TRUNC, 0644) with newlib's flags
+    let fd = syscall(&mut cpu, 1024, &[0x20000, 0x601, 0o644]).unwrap();
+    assert_eq!(fd, 3);
+    assert_eq!(syscall(&mut cpu, 64, &[fd, 0x20100, 13]).unwrap(), 13);
+    assert_eq!(syscall(&mut cpu, 57, &[fd]).unwrap(), 0);
+
+    // Appending with O_WRONLY | O_APPEND
+    let fd = syscall(&mut cpu, 1024, &[0x20000, 0x9, 0]).unwrap();
+    assert_eq!(syscall(&mut cpu, 64, &[fd, 0x20100, 5]).unwrap(), 5);
+    assert_eq!(syscall(&mut cpu, 57, &[fd]).unwrap(), 0);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello, world!Hello");
+
+    // A negative 32 bit offset from the end
+    let fd = syscall(&mut cpu, 1024, &[0x20000, 0, 0]).unwrap();
+    assert_eq!(syscall(&mut cpu, 62, &[fd, 0xFFFF_FFFB, 2]).unwrap(), 13);
+    assert_eq!(syscall(&mut cpu, 63, &[fd, 0x20200, 100]).unwrap(), 5);
+    assert_eq!(&cpu.mem.data[0x20200..0x20205], b"Hello");
+
+    assert_eq!(syscall(&mut cpu, 80, &[fd, 0x20300]).unwrap(), 0);
+    assert_eq!(cpu.mem.read_word(0x20310).unwrap() as u32 & 0o170000, S_IFREG);
+    assert_eq!(cpu.mem.read_double_word(0x20330).unwrap(), 18);
+    assert_eq!(syscall(&mut cpu, 57, &[fd]).unwrap(), 0);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(syscall(&mut cpu, 1024, &[0x20000, 0, 0]).unwrap() as i64, -ENOENT);
+    assert_eq!(syscall(&mut cpu, 64, &[7, 0x20100, 1]).unwrap() as i64, -EBADF);
+
+    // isatty is an fstat looking for a character device
+    assert_eq!(syscall(&mut cpu, 80, &[1, 0x20300]).unwrap(), 0);
+    assert_eq!(cpu.mem.read_word(0x20310).unwrap() as u32 & 0o170000, S_IFCHR);
+}
+
+#[test]
+fn test_newlib_misc_syscalls() {
+    let mut cpu = newlib_cpu(&["prog"]);
+
+    assert_eq!(syscall(&mut cpu, 169, &[0x20000, 0]).unwrap(), 0); // gettimeofday
+    assert!(cpu.mem.read_double_word(0x20000).unwrap() > 1_600_000_000);
+    assert!(cpu.mem.read_double_word(0x20008).unwrap() < 1_000_000);
+
+    // newlib's ENOSYS
+    assert_eq!(syscall(&mut cpu, 9999, &[]).unwrap() as i64, -88);
+    assert!(matches!(syscall(&mut cpu, 93, &[3]), Err(CPUError::Exit { code: 3 })));
+}
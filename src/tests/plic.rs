@@ -0,0 +1,161 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{bus::Bus, components::*, csr::*, plic::*, stages::Xlen};
+
+use super::clint::interrupt_cpu;
+
+fn plic_cpu() -> (CPU, Rc<Cell<bool>>) {
+    let mut cpu = interrupt_cpu(&[]);
+
+    let line = Rc::new(Cell::new(false));
+    let mut plic = Plic::new();
+    let level = line.clone();
+    plic.connect(10, move || level.get());
+    cpu.add_plic(PLIC_BASE, plic);
+    (cpu, line)
+}
+
+#[test]
+fn test_plic_claim_complete() {
+    let mut plic = Plic::new();
+    plic.store(4 * 3, 4, 1).unwrap();
+    plic.store(4 * 40, 4, 0xFF).unwrap();
+    assert_eq!(plic.load(4 * 40, 4).unwrap(), 7);
+    // Source 0 doesn't exist
+    plic.store(0, 4, 5).unwrap();
+    assert_eq!(plic.load(0, 4).unwrap(), 0);
+
+    plic.set_level(3, true);
+    plic.set_level(40, true);
+    assert_eq!(plic.load(0x1000, 4).unwrap(), 1 << 3);
+    assert_eq!(plic.load(0x1004, 4).unwrap(), 1 << 8);
+    // Nothing is enabled yet
+    assert_eq!(plic.interrupts(), 0);
+    assert_eq!(plic.load(0x20_0004, 4).unwrap(), 0);
+
+    plic.store(0x2000, 4, 1 << 3 | 1).unwrap();
+    plic.store(0x2004, 4, 1 << 8).unwrap();
+    assert_eq!(plic.load(0x2000, 4).unwrap(), 1 << 3);
+    assert_eq!(plic.interrupts(), MIP_MEIP);
+
+    // The highest priority goes first, a claimed source waits for its completion
+    assert_eq!(plic.load(0x20_0004, 4).unwrap(), 40);
+    assert_eq!(plic.load(0x20_0004, 4).unwrap(), 3);
+    assert_eq!(plic.interrupts(), 0);
+    assert_eq!(plic.load(0x20_0004, 4).unwrap(), 0);
+    plic.store(0x20_0004, 4, 40).unwrap();
+    assert_eq!(plic.interrupts(), MIP_MEIP);
+
+    // Lowered lines aren't pending anymore
+    plic.set_level(40, false);
+    assert_eq!(plic.interrupts(), 0);
+    plic.store(0x20_0004, 4, 3).unwrap();
+    assert_eq!(plic.interrupts(), MIP_MEIP);
+
+    // Only priorities above the threshold interrupt
+    plic.store(0x20_0000, 4, 1).unwrap();
+    assert_eq!(plic.load(0x20_0000, 4).unwrap(), 1);
+    assert_eq!(plic.interrupts(), 0);
+
+    // The supervisor context has its own enables
+    plic.store(0x2080, 4, 1 << 3).unwrap();
+    assert_eq!(plic.interrupts(), MIP_SEIP);
+    assert_eq!(plic.load(0x20_1004, 4).unwrap(), 3);
+}
+
+#[test]
+fn test_cpu_external_interrupt() {
+    let (mut cpu, line) = plic_cpu();
+    cpu.bus.store(PLIC_BASE + 4 * 10, 4, 1).unwrap();
+    cpu.bus.store(PLIC_BASE + 0x2000, 4, 1 << 10).unwrap();
+    cpu.csr.mie = MIP_MEIP;
+    cpu.csr.mstatus |= MSTATUS_MIE;
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 4);
+
+    // The line is sampled before the next fetch
+    line.set(true);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x800);
+    assert_eq!(cpu.csr.mcause, (1 << 63) | 11);
+    assert_eq!(cpu.csr.mepc, 4);
+
+    // Claiming drops MEIP even though the line is still high
+    assert_eq!(cpu.bus.load(PLIC_BASE + 0x20_0004, 4).unwrap(), 10);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.pending() & MIP_MEIP, 0);
+    cpu.bus.store(PLIC_BASE + 0x20_0004, 4, 10).unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.pending() & MIP_MEIP, MIP_MEIP);
+}
+
+#[test]
+fn test_cpu_supervisor_external_interrupt() {
+    let (mut cpu, line) = plic_cpu();
+    cpu.bus.store(PLIC_BASE + 4 * 10, 4, 1).unwrap();
+    cpu.bus.store(PLIC_BASE + 0x2080, 4, 1 << 10).unwrap();
+    cpu.csr.mideleg = MIP_SEIP;
+    cpu.csr.mie = MIP_SEIP;
+    cpu.csr.mstatus |= MSTATUS_SIE;
+    cpu.privilege = Privilege::Supervisor;
+
+    line.set(true);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x900);
+    assert_eq!(cpu.csr.scause, (1 << 63) | 9);
+
+    // SEIP follows the line when software didn't set it
+    line.set(false);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.pending() & MIP_SEIP, 0);
+}
+
+#[test]
+fn test_cpu_software_seip() {
+    let (mut cpu, line) = plic_cpu();
+    cpu.mem.write_word(0, 0x34416073).unwrap(); // csrsi mip, 2
+    cpu.bus.store(PLIC_BASE + 4 * 10, 4, 1).unwrap();
+    cpu.bus.store(PLIC_BASE + 0x2080, 4, 1 << 10).unwrap();
+
+    // The line shows in mip, but setting another bit doesn't latch it
+    line.set(true);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.read(MIP, Xlen::Rv64).unwrap(), MIP_SEIP | MIP_SSIP);
+    line.set(false);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.read(MIP, Xlen::Rv64).unwrap(), MIP_SSIP);
+
+    // What M-mode writes stays, with the line or without it
+    cpu.csr.write(MIP, MIP_SEIP, Xlen::Rv64).unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.read(MIP, Xlen::Rv64).unwrap(), MIP_SEIP);
+    line.set(true);
+    cpu.cycle().unwrap();
+    line.set(false);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.csr.read(MIP, Xlen::Rv64).unwrap(), MIP_SEIP);
+}
+
+#[test]
+fn test_plic_samples_live_sources() {
+    let samples = Rc::new(Cell::new(0));
+    let mut plic = Plic::new();
+    let counter = samples.clone();
+    plic.connect(5, move || {
+        counter.set(counter.get() + 1);
+        true
+    });
+
+    // Without a priority and an enable the line can't interrupt, so it's left alone
+    plic.update();
+    plic.store(4 * 5, 4, 1).unwrap();
+    plic.update();
+    assert_eq!(samples.get(), 0);
+    assert_eq!(plic.load(0x1000, 4).unwrap(), 0);
+
+    plic.store(0x2080, 4, 1 << 5).unwrap();
+    plic.update();
+    assert_eq!(samples.get(), 1);
+    assert_eq!(plic.interrupts(), MIP_SEIP);
+}
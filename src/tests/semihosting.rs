@@ -0,0 +1,115 @@
+use crate::{components::*, semihosting::*, stages::Xlen};
+
+fn semihosting_cpu(xlen: Xlen) -> CPU {
+    let mut cpu = CPU::with_xlen(0x40000, xlen);
+    cpu.semihosting = Some(Semihosting::new("prog --flag".to_string()));
+    for (i, instruction) in [ENTRY, EBREAK, EXIT].into_iter().enumerate() {
+        cpu.mem.write_word(0x1000 + i * 4, instruction as u64).unwrap();
+    }
+    cpu
+}
+
+/// Runs the semihosting sequence with the operation and its argument, giving back a0.
+fn semihost(cpu: &mut CPU, operation: u64, argument: u64) -> Result<u64, CPUError> {
+    cpu.regs[10] = operation;
+    cpu.regs[11] = argument;
+    cpu.pc.set(0x1000);
+    for _ in 0..3 {
+        cpu.cycle()?;
+    }
+    assert_eq!(cpu.pc.address, 0x100C);
+    Ok(cpu.regs[10])
+}
+
+fn write_block(cpu: &mut CPU, address: usize, words: &[u64]) {
+    for (i, word) in words.iter().enumerate() {
+        cpu.mem.write_double_word(address + i * 8, *word).unwrap();
+    }
+}
+
+#[test]
+fn test_semihosting_files() {
+    let mut cpu = semihosting_cpu(Xlen::Rv64);
+    let path = std::env::temp_dir().join(format!("riscv-semihosting-test-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    cpu.mem.data[0x2000..0x2000 + path.len()].copy_from_slice(path.as_bytes());
+    cpu.mem.data[0x2100..0x2105].copy_from_slice(b"hello");
+
+    // SYS_OPEN with "wb"
+    write_block(&mut cpu, 0x3000, &[0x2000, 5, path.len() as u64]);
+    let handle = semihost(&mut cpu, 0x01, 0x3000).unwrap();
+    assert_eq!(handle, 3);
+
+    // SYS_WRITE gives back the bytes that weren't written
+    write_block(&mut cpu, 0x3000, &[handle, 0x2100, 5]);
+    assert_eq!(semihost(&mut cpu, 0x05, 0x3000).unwrap(), 0);
+    write_block(&mut cpu, 0x3000, &[handle]);
+    assert_eq!(semihost(&mut cpu, 0x02, 0x3000).unwrap(), 0);
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "hello");
+
+    // SYS_READ of more than the file has, with "rb"
+    write_block(&mut cpu, 0x3000, &[0x2000, 1, path.len() as u64]);
+    let handle = semihost(&mut cpu, 0x01, 0x3000).unwrap();
+    write_block(&mut cpu, 0x3000, &[handle, 0x2200, 8]);
+    assert_eq!(semihost(&mut cpu, 0x06, 0x3000).unwrap(), 3);
+    assert_eq!(&cpu.mem.data[0x2200..0x2205], b"hello");
+    write_block(&mut cpu, 0x3000, &[handle]);
+    assert_eq!(semihost(&mut cpu, 0x02, 0x3000).unwrap(), 0);
+    assert_eq!(semihost(&mut cpu, 0x02, 0x3000).unwrap() as i64, -1);
+    std::fs::remove_file(path).unwrap();
+
+    // The console for writing
+    cpu.mem.data[0x2000..0x2003].copy_from_slice(b":tt");
+    write_block(&mut cpu, 0x3000, &[0x2000, 4, 3]);
+    assert_eq!(semihost(&mut cpu, 0x01, 0x3000).unwrap(), 1);
+
+    // A file that isn't there
+    write_block(&mut cpu, 0x3000, &[0x2000, 0, 2]);
+    assert_eq!(semihost(&mut cpu, 0x01, 0x3000).unwrap() as i64, -1);
+}
+
+#[test]
+fn test_semihosting_cmdline_and_clock() {
+    let mut cpu = semihosting_cpu(Xlen::Rv64);
+
+    write_block(&mut cpu, 0x3000, &[0x2000, 64]);
+    assert_eq!(semihost(&mut cpu, 0x15, 0x3000).unwrap(), 0);
+    assert_eq!(&cpu.mem.data[0x2000..0x200C], b"prog --flag\0");
+    assert_eq!(cpu.mem.read_double_word(0x3008).unwrap(), 11);
+
+    // Too small a buffer
+    write_block(&mut cpu, 0x3000, &[0x2000, 4]);
+    assert_eq!(semihost(&mut cpu, 0x15, 0x3000).unwrap() as i64, -1);
+
+    assert!(semihost(&mut cpu, 0x10, 0).unwrap() < 100);
+    assert_eq!(semihost(&mut cpu, 0x99, 0).unwrap() as i64, -1);
+}
+
+#[test]
+fn test_semihosting_exit() {
+    let mut cpu = semihosting_cpu(Xlen::Rv64);
+    write_block(&mut cpu, 0x3000, &[0x20026, 7]);
+    assert!(matches!(semihost(&mut cpu, 0x18, 0x3000), Err(CPUError::Exit { code: 7 })));
+    write_block(&mut cpu, 0x3000, &[0x20023, 0]);
+    assert!(matches!(semihost(&mut cpu, 0x18, 0x3000), Err(CPUError::Exit { code: 1 })));
+
+    // RV32 passes the reason in a1 itself
+    let mut cpu = semihosting_cpu(Xlen::Rv32);
+    assert!(matches!(semihost(&mut cpu, 0x18, 0x20026), Err(CPUError::Exit { code: 0 })));
+    assert!(matches!(semihost(&mut cpu, 0x18, 0x20023), Err(CPUError::Exit { code: 1 })));
+}
+
+#[test]
+fn test_semihosting_plain_ebreak() {
+    // Without the sequence around it an EBREAK still is a breakpoint
+    let mut cpu = semihosting_cpu(Xlen::Rv64);
+    cpu.halt.ebreak = false;
+    cpu.mem.write_word(0x1000, 0x00000013).unwrap(); // nop
+    assert!(matches!(semihost(&mut cpu, 0x10, 0), Err(CPUError::Trap { pc: 0x1004, .. })));
+
+    // And so is the sequence when semihosting is off
+    let mut cpu = semihosting_cpu(Xlen::Rv64);
+    cpu.halt.ebreak = false;
+    cpu.semihosting = None;
+    assert!(matches!(semihost(&mut cpu, 0x10, 0), Err(CPUError::Trap { pc: 0x1004, .. })));
+}
@@ -201,6 +201,69 @@ fn test_execute_sra() {
     assert_eq!(writeback.value as i32, -32);
 }
 
+#[test]
+fn test_execute_sll_upper_shamt() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x00,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 1, 40, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1 << 40);
+
+    let execute_result = execute(&instruction, 1, 64, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+}
+
+#[test]
+fn test_execute_srl_upper_shamt() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x00,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -1, 40, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFF_FFFF);
+}
+
+#[test]
+fn test_execute_sra_upper_shamt() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x20,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, i64::MIN, 40, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_FF80_0000);
+}
+
 #[test]
 fn test_execute_or() {
     let instruction = DecodedInstr::R(RType {
@@ -240,74 +303,1062 @@ fn test_execute_and() {
 }
 
 #[test]
-fn test_execute_jarl() {
-    let instruction = DecodedInstr::I(IType {
-        opcode: 0b1100111,
-        func3: 0,
+fn test_execute_mul() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x0,
         rd: 1,
         rs1: 0,
-        imm: 16,
-        func7: 0,
-        shamt: 0
+        rs2: 0,
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 7, -3, 0).unwrap();
 
     let writeback = execute_result.write_back.unwrap();
     assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 8);
+    assert_eq!(writeback.value, -21i64 as u64);
 
-    let branch = execute_result.branch_addr.unwrap();
-    assert_eq!(branch, 48);
+    let execute_result = execute(&instruction, 0x1_0000_0000, 0x1_0000_0000, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
 }
 
 #[test]
-fn test_execute_lb() {
-    let instruction = DecodedInstr::I(IType {
-        opcode: 0b0000011,
+fn test_execute_mulh() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -1, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
+
+    let execute_result = execute(&instruction, i64::MIN, 2, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+}
+
+#[test]
+fn test_execute_mulhsu() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x2,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -1, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+
+    let execute_result = execute(&instruction, 2, i64::MIN, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+}
+
+#[test]
+fn test_execute_mulhu() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x3,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -1, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX - 1);
+
+    let execute_result = execute(&instruction, 2, i64::MIN, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+}
+
+#[test]
+fn test_execute_div() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x4,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -6i64 as u64);
+
+    let execute_result = execute(&instruction, 5, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+
+    let execute_result = execute(&instruction, i64::MIN, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, i64::MIN as u64);
+}
+
+#[test]
+fn test_execute_divu() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, (-20i64 as u64) / 3);
+
+    let execute_result = execute(&instruction, 5, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+}
+
+#[test]
+fn test_execute_rem() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x6,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -2i64 as u64);
+
+    let execute_result = execute(&instruction, 5, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 5);
+
+    let execute_result = execute(&instruction, i64::MIN, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
+}
+
+#[test]
+fn test_execute_remu() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0110011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x7,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 2);
+
+    let execute_result = execute(&instruction, -5, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -5i64 as u64);
+}
+
+#[test]
+fn test_execute_addw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x00,
         func3: 0x0,
         rd: 1,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        rs2: 0,
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x7FFF_FFFF, 1, 0).unwrap();
 
-    let read_mem = execute_result.read_mem.unwrap();
-    assert_eq!(read_mem.rd, 1);
-    assert_eq!(read_mem.address, 36);
-    assert_eq!(read_mem.size, MemSize::Byte);
-    assert_eq!(read_mem.signed, true);
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, i32::MIN as i64 as u64);
+
+    let execute_result = execute(&instruction, 0x1_0000_0001, 2, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 3);
+}
+
+#[test]
+fn test_execute_subw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x20,
+        func3: 0x0,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 0, 1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+
+    let execute_result = execute(&instruction, 0x1_0000_0005, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 2);
+}
+
+#[test]
+fn test_execute_sllw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x00,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 1, 31, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+
+    let execute_result = execute(&instruction, 1, 33, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 2);
+}
+
+#[test]
+fn test_execute_srlw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x00,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -1, 4, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0x0FFF_FFFF);
+
+    let execute_result = execute(&instruction, 0x8000_0000, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+}
+
+#[test]
+fn test_execute_sraw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x20,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 0x8000_0000, 4, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_F800_0000);
+
+    let execute_result = execute(&instruction, 0x1_7FFF_FFFF, 36, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0x07FF_FFFF);
+}
+
+#[test]
+fn test_execute_mulw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x0,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 0x7FFF_FFFF, 2, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -2i64 as u64);
+
+    let execute_result = execute(&instruction, 0x1_0000_0003, 5, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 15);
+}
+
+#[test]
+fn test_execute_divw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x4,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -6i64 as u64);
+
+    let execute_result = execute(&instruction, 5, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+
+    let execute_result = execute(&instruction, i32::MIN as i64, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, i32::MIN as i64 as u64);
+}
+
+#[test]
+fn test_execute_divuw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 0xFFFF_FFFE, 1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -2i64 as u64);
+
+    let execute_result = execute(&instruction, 5, 0x1_0000_0000, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, u64::MAX);
+}
+
+#[test]
+fn test_execute_remw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x6,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, -20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -2i64 as u64);
+
+    let execute_result = execute(&instruction, 0x1_0000_0005, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 5);
+
+    let execute_result = execute(&instruction, i32::MIN as i64, -1, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
+}
+
+#[test]
+fn test_execute_remuw() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0111011,
+        func: 0,
+        func7: 0x01,
+        func3: 0x7,
+        rd: 1,
+        rs1: 0,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 20, 3, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 2);
+
+    let execute_result = execute(&instruction, 0xFFFF_FFFB, 0, 0).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, -5i64 as u64);
+}
+
+#[test]
+fn test_execute_lr_w() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0101111,
+        func: 0,
+        func7: 0x0A,
+        func3: 0x2,
+        rd: 10,
+        rs1: 6,
+        rs2: 0,
+    });
+
+    let execute_result = execute(&instruction, 0x48, 0, 0).unwrap();
+
+    assert_eq!(execute_result.atomic.unwrap(), AtomicAccess { address: 0x48, size: MemSize::Word, rd: 10, op: AtomicOp::LoadReserved, value: 0 });
+    assert!(execute_result.read_mem.is_none());
+}
+
+#[test]
+fn test_execute_sc_d() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0101111,
+        func: 0,
+        func7: 0x0C,
+        func3: 0x3,
+        rd: 11,
+        rs1: 5,
+        rs2: 12,
+    });
+
+    let execute_result = execute(&instruction, 0x40, 7, 0).unwrap();
+
+    assert_eq!(execute_result.atomic.unwrap(), AtomicAccess { address: 0x40, size: MemSize::Double, rd: 11, op: AtomicOp::StoreConditional, value: 7 });
+}
+
+#[test]
+fn test_execute_amoadd_w() {
+    let instruction = DecodedInstr::R(RType {
+        opcode: 0b0101111,
+        func: 0,
+        func7: 0x01,
+        func3: 0x2,
+        rd: 10,
+        rs1: 7,
+        rs2: 12,
+    });
+
+    let execute_result = execute(&instruction, 0x4A, 7, 0).unwrap();
+
+    assert_eq!(execute_result.atomic.unwrap(), AtomicAccess { address: 0x4A, size: MemSize::Word, rd: 10, op: AtomicOp::Amo(AmoOp::Add), value: 7 });
+
+    // LR has no rs2, and there are no byte sized atomics
+    let instruction = DecodedInstr::R(RType { opcode: 0b0101111, func: 0, func7: 0x08, func3: 0x2, rd: 10, rs1: 7, rs2: 1 });
+    assert!(execute(&instruction, 0, 0, 0).is_err());
+    let instruction = DecodedInstr::R(RType { opcode: 0b0101111, func: 0, func7: 0x00, func3: 0x0, rd: 10, rs1: 7, rs2: 12 });
+    assert!(execute(&instruction, 0, 0, 0).is_err());
+}
+
+#[test]
+fn test_amo_op_apply() {
+    assert_eq!(AmoOp::Swap.apply(1, 2, &MemSize::Double), 2);
+    assert_eq!(AmoOp::Add.apply(0xFFFF_FFFF, 1, &MemSize::Word) as u32, 0);
+    assert_eq!(AmoOp::Xor.apply(0b1100, 0b1010, &MemSize::Double), 0b0110);
+    assert_eq!(AmoOp::And.apply(0b1100, 0b1010, &MemSize::Double), 0b1000);
+    assert_eq!(AmoOp::Or.apply(0b1100, 0b1010, &MemSize::Double), 0b1110);
+
+    // Word sized comparisons only look at the low 32 bits
+    assert_eq!(AmoOp::Min.apply(0xFFFF_FFF0, 7, &MemSize::Word) as u32, 0xFFFF_FFF0);
+    assert_eq!(AmoOp::Max.apply(0xFFFF_FFF0, 7, &MemSize::Word) as u32, 7);
+    assert_eq!(AmoOp::Minu.apply(0xFFFF_FFF0, 7, &MemSize::Word) as u32, 7);
+    assert_eq!(AmoOp::Maxu.apply(0xFFFF_FFF0, 7, &MemSize::Word) as u32, 0xFFFF_FFF0);
+    assert_eq!(AmoOp::Min.apply(0xFFFF_FFF0, 7, &MemSize::Double), 7);
+    assert_eq!(AmoOp::Maxu.apply(-1i64 as u64, 7, &MemSize::Double), -1i64 as u64);
+}
+
+#[test]
+fn test_execute_jarl() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b1100111,
+        func3: 0,
+        rd: 1,
+        rs1: 0,
+        imm: 16,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 8);
+
+    let branch = execute_result.branch_addr.unwrap();
+    assert_eq!(branch, 48);
+}
+
+#[test]
+fn test_execute_lb() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x0,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Byte);
+    assert!(read_mem.signed);
+}
+
+#[test]
+fn test_execute_lh() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Half);
+    assert!(read_mem.signed);
+}
+
+#[test]
+fn test_execute_lw() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x2,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Word);
+    assert!(read_mem.signed);
+}
+
+#[test]
+fn test_execute_ld() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x3,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Double);
+    assert!(read_mem.signed);
+}
+
+#[test]
+fn test_execute_lbu() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x4,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Byte);
+    assert!(!read_mem.signed);
+}
+
+#[test]
+fn test_execute_lhu() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0000011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let read_mem = execute_result.read_mem.unwrap();
+    assert_eq!(read_mem.rd, 1);
+    assert_eq!(read_mem.address, 36);
+    assert_eq!(read_mem.size, MemSize::Half);
+    assert!(!read_mem.signed);
+}
+
+#[test]
+fn test_execute_addi() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x0,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 36);
+}
+
+#[test]
+fn test_execute_slti() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x2,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, -1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
+}
+
+#[test]
+fn test_execute_sltiu() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x3,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0);
+}
+
+#[test]
+fn test_execute_xori() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x4,
+        rd: 1,
+        rs1: 0,
+        imm: 0b1010,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 0b1111, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0b0101);
+}
+
+#[test]
+fn test_execute_ori() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x6,
+        rd: 1,
+        rs1: 0,
+        imm: 0b1010,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 0b0101, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0b1111);
+}
+
+#[test]
+fn test_execute_andi() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x7,
+        rd: 1,
+        rs1: 0,
+        imm: 0b1010,
+        func7: 0,
+        shamt: 0
+    });
+
+    let execute_result = execute(&instruction, 0b1111, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0b1010);
+}
+
+#[test]
+fn test_execute_slli() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        imm: 0,
+        func7: 0,
+        shamt: 2
+    });
+
+    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 128);
+}
+
+#[test]
+fn test_execute_srli() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 0,
+        func7: 0x00,
+        shamt: 2
+    });
+
+    let execute_result = execute(&instruction, 128, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 32);
+}
+
+#[test]
+fn test_execute_srai() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 0,
+        func7: 0x20,
+        shamt: 2
+    });
+
+    let execute_result = execute(&instruction, -128, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value as i32, -32);
+}
+
+#[test]
+fn test_execute_slli_upper_shamt() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        imm: 0x28,
+        func7: 0x01,
+        shamt: 40
+    });
+
+    let execute_result = execute(&instruction, 1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1 << 40);
+}
+
+#[test]
+fn test_execute_srli_upper_shamt() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 0x28,
+        func7: 0x01,
+        shamt: 40
+    });
+
+    let execute_result = execute(&instruction, -1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFF_FFFF);
+}
+
+#[test]
+fn test_execute_srai_upper_shamt() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0010011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 0x428,
+        func7: 0x21,
+        shamt: 40
+    });
+
+    let execute_result = execute(&instruction, i64::MIN, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_FF80_0000);
+}
+
+#[test]
+fn test_execute_addiw() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0011011,
+        func3: 0x0,
+        rd: 1,
+        rs1: 0,
+        imm: 1,
+        func7: 0x00,
+        shamt: 1
+    });
+
+    let execute_result = execute(&instruction, 0x7FFF_FFFF, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+
+    let execute_result = execute(&instruction, 0x1_0000_0001, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 2);
+}
+
+#[test]
+fn test_execute_slliw() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0011011,
+        func3: 0x1,
+        rd: 1,
+        rs1: 0,
+        imm: 31,
+        func7: 0x00,
+        shamt: 31
+    });
+
+    let execute_result = execute(&instruction, 1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+
+    let execute_result = execute(&instruction, 0x1_0000_0003, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+}
+
+#[test]
+fn test_execute_srliw() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0011011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 4,
+        func7: 0x00,
+        shamt: 4
+    });
+
+    let execute_result = execute(&instruction, -1, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0x0FFF_FFFF);
+
+    let execute_result = execute(&instruction, 0x7FFF_FFFF_0000_0010, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
+}
+
+#[test]
+fn test_execute_sraiw() {
+    let instruction = DecodedInstr::I(IType {
+        opcode: 0b0011011,
+        func3: 0x5,
+        rd: 1,
+        rs1: 0,
+        imm: 0x404,
+        func7: 0x20,
+        shamt: 4
+    });
+
+    let execute_result = execute(&instruction, 0x8000_0000, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 0xFFFF_FFFF_F800_0000);
+
+    let execute_result = execute(&instruction, 0x1_0000_0010, 0, 4).unwrap();
+
+    let writeback = execute_result.write_back.unwrap();
+    assert_eq!(writeback.rd, 1);
+    assert_eq!(writeback.value, 1);
 }
 
 #[test]
-fn test_execute_lh() {
+fn test_execute_slliw_reserved_shamt() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0000011,
+        opcode: 0b0011011,
         func3: 0x1,
         rd: 1,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        imm: 0x20,
+        func7: 0x01,
+        shamt: 32
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
-
-    let read_mem = execute_result.read_mem.unwrap();
-    assert_eq!(read_mem.rd, 1);
-    assert_eq!(read_mem.address, 36);
-    assert_eq!(read_mem.size, MemSize::Half);
-    assert_eq!(read_mem.signed, true);
+    assert!(execute(&instruction, 1, 0, 4).is_err());
 }
 
 #[test]
-fn test_execute_lw() {
+fn test_execute_lwu() {
     let instruction = DecodedInstr::I(IType {
         opcode: 0b0000011,
-        func3: 0x2,
+        func3: 0x6,
         rd: 1,
         rs1: 0,
         imm: 4,
@@ -321,285 +1372,262 @@ fn test_execute_lw() {
     assert_eq!(read_mem.rd, 1);
     assert_eq!(read_mem.address, 36);
     assert_eq!(read_mem.size, MemSize::Word);
-    assert_eq!(read_mem.signed, true);
+    assert!(!read_mem.signed);
 }
 
 #[test]
-fn test_execute_ld() {
-    let instruction = DecodedInstr::I(IType {
-        opcode: 0b0000011,
-        func3: 0x3,
-        rd: 1,
-        rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
-    });
+fn test_execute_fence() {
+    // FENCE, including FENCE.TSO, only orders memory, so nothing happens
+    for raw in [0x0FF0000F, 0x8330000F] {
+        let execute_result = execute(&decode_instruction(raw).unwrap(), 0, 0, 4).unwrap();
+        assert!(execute_result.system.is_none());
+        assert!(execute_result.write_back.is_none());
+        assert!(execute_result.read_mem.is_none() && execute_result.write_mem.is_none());
+    }
+}
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+#[test]
+fn test_execute_fencei() {
+    let instruction = decode_instruction(0x0000100F).unwrap(); // fence.i
 
-    let read_mem = execute_result.read_mem.unwrap();
-    assert_eq!(read_mem.rd, 1);
-    assert_eq!(read_mem.address, 36);
-    assert_eq!(read_mem.size, MemSize::Double);
-    assert_eq!(read_mem.signed, true);
+    assert_eq!(execute(&instruction, 0, 0, 4).unwrap().system.unwrap(), SystemOp::FenceI);
+    assert_eq!(execute_rv32(&instruction, 0, 0, 4).unwrap().system.unwrap(), SystemOp::FenceI);
 }
 
 #[test]
-fn test_execute_lbu() {
+fn test_execute_ecall() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0000011,
-        func3: 0x4,
-        rd: 1,
+        opcode: 0b1110011,
+        func3: 0x0,
+        rd: 0,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        imm: 0x0,
+        func7: 0x0,
+        shamt: 0x0
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0, 0, 4).unwrap();
 
-    let read_mem = execute_result.read_mem.unwrap();
-    assert_eq!(read_mem.rd, 1);
-    assert_eq!(read_mem.address, 36);
-    assert_eq!(read_mem.size, MemSize::Byte);
-    assert_eq!(read_mem.signed, false);
+    assert_eq!(execute_result.system.unwrap(), SystemOp::Ecall);
+    assert!(execute_result.write_back.is_none());
+    assert!(execute_result.branch_addr.is_none());
 }
 
 #[test]
-fn test_execute_lhu() {
+fn test_execute_ebreak() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0000011,
-        func3: 0x5,
-        rd: 1,
+        opcode: 0b1110011,
+        func3: 0x0,
+        rd: 0,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        imm: 0x1,
+        func7: 0x0,
+        shamt: 0x1
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0, 0, 4).unwrap();
 
-    let read_mem = execute_result.read_mem.unwrap();
-    assert_eq!(read_mem.rd, 1);
-    assert_eq!(read_mem.address, 36);
-    assert_eq!(read_mem.size, MemSize::Half);
-    assert_eq!(read_mem.signed, false);
+    assert_eq!(execute_result.system.unwrap(), SystemOp::Ebreak);
+    assert!(execute_result.write_back.is_none());
+    assert!(execute_result.branch_addr.is_none());
 }
 
 #[test]
-fn test_execute_addi() {
+fn test_execute_mret() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
+        opcode: 0b1110011,
         func3: 0x0,
-        rd: 1,
+        rd: 0,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        imm: 0x302,
+        func7: 0x18,
+        shamt: 0x2
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 36);
+    assert_eq!(execute_result.system.unwrap(), SystemOp::Mret);
+    assert!(execute_result.write_back.is_none());
+    assert!(execute_result.branch_addr.is_none());
 }
 
 #[test]
-fn test_execute_slti() {
+fn test_execute_wfi() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x2,
-        rd: 1,
+        opcode: 0b1110011,
+        func3: 0x0,
+        rd: 0,
         rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        imm: 0x105,
+        func7: 0x8,
+        shamt: 0x5
     });
 
-    let execute_result = execute(&instruction, -1, 0, 4).unwrap();
-
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 1);
-
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 0);
+    assert_eq!(execute_result.system.unwrap(), SystemOp::Wfi);
+    assert!(execute_result.write_back.is_none());
+    assert!(execute_result.branch_addr.is_none());
 }
 
 #[test]
-fn test_execute_sltiu() {
+fn test_execute_sfence_vma() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x3,
-        rd: 1,
-        rs1: 0,
-        imm: 4,
-        func7: 0,
-        shamt: 0
+        opcode: 0b1110011,
+        func3: 0x0,
+        rd: 0,
+        rs1: 10,
+        imm: 0x120,
+        func7: 0x9,
+        shamt: 0x20
     });
 
-    let execute_result = execute(&instruction, 1, 0, 4).unwrap();
-
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 1);
-
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x4000_5000, 0, 4).unwrap();
+    assert_eq!(execute_result.system.unwrap(), SystemOp::SfenceVma { address: Some(0x4000_5000) });
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 0);
+    let instruction = DecodedInstr::I(IType { rs1: 0, ..match instruction { DecodedInstr::I(i) => i, _ => unreachable!() } });
+    let execute_result = execute(&instruction, 0, 0, 4).unwrap();
+    assert_eq!(execute_result.system.unwrap(), SystemOp::SfenceVma { address: None });
 }
 
 #[test]
-fn test_execute_xori() {
+fn test_execute_csrrw() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x4,
+        opcode: 0b1110011,
+        func3: 0x1,
         rd: 1,
         rs1: 0,
-        imm: 0b1010,
-        func7: 0,
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
         shamt: 0
     });
 
-    let execute_result = execute(&instruction, 0b1111, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 0b0101);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Write);
+    assert_eq!(csr.value, 0x55);
+    assert!(csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
 #[test]
-fn test_execute_ori() {
+fn test_execute_csrrs() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x6,
+        opcode: 0b1110011,
+        func3: 0x2,
         rd: 1,
-        rs1: 0,
-        imm: 0b1010,
-        func7: 0,
+        rs1: 2,
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
         shamt: 0
     });
 
-    let execute_result = execute(&instruction, 0b0101, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 0b1111);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Set);
+    assert_eq!(csr.value, 0x55);
+    assert!(csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
 #[test]
-fn test_execute_andi() {
+fn test_execute_csrrc() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x7,
+        opcode: 0b1110011,
+        func3: 0x3,
         rd: 1,
         rs1: 0,
-        imm: 0b1010,
-        func7: 0,
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
         shamt: 0
     });
 
-    let execute_result = execute(&instruction, 0b1111, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 0b1010);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Clear);
+    assert_eq!(csr.value, 0x55);
+    assert!(!csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
 #[test]
-fn test_execute_slli() {
+fn test_execute_csrrwi() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x1,
+        opcode: 0b1110011,
+        func3: 0x5,
         rd: 1,
         rs1: 0,
-        imm: 0,
-        func7: 0,
-        shamt: 2
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
+        shamt: 0
     });
 
-    let execute_result = execute(&instruction, 32, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 128);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Write);
+    assert_eq!(csr.value, 0);
+    assert!(csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
 #[test]
-fn test_execute_srli() {
+fn test_execute_csrrsi() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x5,
+        opcode: 0b1110011,
+        func3: 0x6,
         rd: 1,
-        rs1: 0,
-        imm: 0,
-        func7: 0x00,
-        shamt: 2
+        rs1: 0x1F,
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
+        shamt: 0
     });
 
-    let execute_result = execute(&instruction, 128, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value, 32);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Set);
+    assert_eq!(csr.value, 0x1F);
+    assert!(csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
 #[test]
-fn test_execute_srai() {
+fn test_execute_csrrci() {
     let instruction = DecodedInstr::I(IType {
-        opcode: 0b0010011,
-        func3: 0x5,
+        opcode: 0b1110011,
+        func3: 0x7,
         rd: 1,
         rs1: 0,
-        imm: 0,
-        func7: 0x20,
-        shamt: 2
+        imm: -0x400, // cycle (0xC00) as a sign extended immediate
+        func7: 0x60,
+        shamt: 0
     });
 
-    let execute_result = execute(&instruction, -128, 0, 4).unwrap();
+    let execute_result = execute(&instruction, 0x55, 0, 4).unwrap();
 
-    let writeback = execute_result.write_back.unwrap();
-    assert_eq!(writeback.rd, 1);
-    assert_eq!(writeback.value as i32, -32);
+    let csr = execute_result.csr.unwrap();
+    assert_eq!(csr.csr, 0xC00);
+    assert_eq!(csr.rd, 1);
+    assert_eq!(csr.op, CsrOp::Clear);
+    assert_eq!(csr.value, 0);
+    assert!(!csr.write);
+    assert!(execute_result.write_back.is_none());
 }
 
-#[test]
-fn test_execute_fence() {}
-
-#[test]
-fn test_execute_fencei() {}
-
-#[test]
-fn test_execute_ecall() {}
-
-#[test]
-fn test_execute_ebreak() {}
-
-#[test]
-fn test_execute_csrrw() {}
-
-#[test]
-fn test_execute_csrrs() {}
-
-#[test]
-fn test_execute_csrrc() {}
-
-#[test]
-fn test_execute_csrrwi() {}
-
-#[test]
-fn test_execute_csrrsi() {}
-
-#[test]
-fn test_execute_csrrci() {}
-
 #[test]
 fn test_execute_sb() {
     let instruction = DecodedInstr::S(SType {
@@ -758,6 +1786,12 @@ fn test_execute_bltu() {
 
     let execute_result = execute(&instruction, 16, 32, 4).unwrap();
     assert_eq!(execute_result.branch_addr.unwrap(), 20);
+
+    let execute_result = execute(&instruction, 0x1_0000_0000, 0x2, 4).unwrap();
+    assert_eq!(execute_result.branch_addr, None);
+
+    let execute_result = execute(&instruction, 0x2, -1, 4).unwrap();
+    assert_eq!(execute_result.branch_addr.unwrap(), 20);
 }
 
 #[test]
@@ -778,6 +1812,12 @@ fn test_execute_bgeu() {
 
     let execute_result = execute(&instruction, 32, 16, 4).unwrap();
     assert_eq!(execute_result.branch_addr.unwrap(), 20);
+
+    let execute_result = execute(&instruction, 0x1_0000_0000, 0x2, 4).unwrap();
+    assert_eq!(execute_result.branch_addr.unwrap(), 20);
+
+    let execute_result = execute(&instruction, 0x2, -1, 4).unwrap();
+    assert_eq!(execute_result.branch_addr, None);
 }
 
 #[test]
@@ -825,4 +1865,182 @@ fn test_execute_jal() {
     assert_eq!(writeback.value, 8);
 
     assert_eq!(execute_result.branch_addr.unwrap(), 36);
-}
\ No newline at end of file
+}
+#[test]
+fn test_execute_rv32_arithmetic() {
+    let add = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x00, func3: 0x0, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&add, 0x7FFF_FFFF, 1, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+
+    let srl = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x00, func3: 0x5, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&srl, -16, 36, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0x0FFF_FFFF);
+
+    let divu = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x01, func3: 0x5, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&divu, -2, 2, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0x7FFF_FFFF);
+
+    let addi = DecodedInstr::I(IType { opcode: 0b0010011, func3: 0x0, rd: 1, rs1: 0, imm: 1, func7: 0, shamt: 1 });
+    let writeback = execute_rv32(&addi, -1, 0, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0);
+}
+
+#[test]
+fn test_execute_rv32_mulh() {
+    let mulh = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x01, func3: 0x1, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&mulh, -1, -1, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0);
+
+    let mulhsu = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x01, func3: 0x2, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&mulhsu, -1, -1, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, u64::MAX);
+
+    let mulhu = DecodedInstr::R(RType { opcode: 0b0110011, func: 0, func7: 0x01, func3: 0x3, rd: 1, rs1: 0, rs2: 0 });
+    let writeback = execute_rv32(&mulhu, -1, -1, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0xFFFF_FFFF_FFFF_FFFE);
+}
+
+#[test]
+fn test_execute_rv32_shift_immediates() {
+    let slli = DecodedInstr::I(IType { opcode: 0b0010011, func3: 0x1, rd: 1, rs1: 0, imm: 31, func7: 0, shamt: 31 });
+    let writeback = execute_rv32(&slli, 1, 0, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0xFFFF_FFFF_8000_0000);
+
+    let srai = DecodedInstr::I(IType { opcode: 0b0010011, func3: 0x5, rd: 1, rs1: 0, imm: 0x404, func7: 0x20, shamt: 4 });
+    let writeback = execute_rv32(&srai, i32::MIN as i64, 0, 0).unwrap().write_back.unwrap();
+    assert_eq!(writeback.value, 0xFFFF_FFFF_F800_0000);
+
+    let slli_reserved = DecodedInstr::I(IType { opcode: 0b0010011, func3: 0x1, rd: 1, rs1: 0, imm: 32, func7: 0x01, shamt: 32 });
+    assert!(execute_rv32(&slli_reserved, 1, 0, 0).is_err());
+}
+
+#[test]
+fn test_execute_rv32_rejects_rv64_instructions() {
+    let addw = DecodedInstr::R(RType { opcode: 0b0111011, func: 0, func7: 0x00, func3: 0x0, rd: 1, rs1: 0, rs2: 0 });
+    assert!(execute_rv32(&addw, 1, 1, 0).is_err());
+
+    let addiw = DecodedInstr::I(IType { opcode: 0b0011011, func3: 0x0, rd: 1, rs1: 0, imm: 1, func7: 0, shamt: 1 });
+    assert!(execute_rv32(&addiw, 1, 0, 0).is_err());
+
+    let ld = DecodedInstr::I(IType { opcode: 0b0000011, func3: 0x3, rd: 1, rs1: 0, imm: 0, func7: 0, shamt: 0 });
+    assert!(execute_rv32(&ld, 0, 0, 0).is_err());
+
+    let sd = DecodedInstr::S(SType { opcode: 0b0100011, func: 0x3, rs1: 0, rs2: 0, imm: 0 });
+    assert!(execute_rv32(&sd, 0, 0, 0).is_err());
+}
+
+#[test]
+fn test_execute_rv32_address_wrapping() {
+    let lw = DecodedInstr::I(IType { opcode: 0b0000011, func3: 0x2, rd: 1, rs1: 0, imm: 8, func7: 0, shamt: 8 });
+    let read_mem = execute_rv32(&lw, -4, 0, 0).unwrap().read_mem.unwrap();
+    assert_eq!(read_mem.address, 4);
+
+    let jal = DecodedInstr::J(JType { opcode: 0b1101111, rd: 1, imm: 8 });
+    let execute_result = execute_rv32(&jal, 0, 0, 0xFFFF_FFFC).unwrap();
+    assert_eq!(execute_result.write_back.unwrap().value, 0);
+    assert_eq!(execute_result.branch_addr.unwrap(), 4);
+}
+
+#[test]
+fn test_execute_fp_operations() {
+    let operands = FpOperands { x_rs1: 3, f_rs1: 0xFFFF_FFFF_4000_0000, f_rs2: 0xFFFF_FFFF_3F80_0000, f_rs3: 0 };
+
+    // fsub.s ft3, ft1, ft2
+    let fsub = decode_instruction(0x0820F1D3).unwrap();
+    let result = execute_fp(&fsub, &operands, 0, Xlen::Rv64).unwrap();
+    assert_eq!(result.fp_write_back.unwrap().value, 0xFFFF_FFFF_3F80_0000);
+
+    // flt.s a0, ft1, ft2
+    let flt = decode_instruction(0xA020A553).unwrap();
+    assert_eq!(execute_fp(&flt, &operands, 0, Xlen::Rv64).unwrap().write_back.unwrap().value, 0);
+
+    // fcvt.s.l ft1, x3
+    let fcvt = decode_instruction(0xD02180D3).unwrap();
+    let result = execute_fp(&fcvt, &operands, 0, Xlen::Rv64).unwrap();
+    assert_eq!(result.fp_write_back.unwrap().value, 0xFFFF_FFFF_4040_0000);
+    assert!(is_floating_point(&fcvt));
+    assert!(!is_floating_point(&decode_instruction(0x00B50533).unwrap()));
+}
+
+#[test]
+fn test_execute_fp_rv32() {
+    let operands = FpOperands { x_rs1: 0xFFFF_FFFF_FFFF_FFFC, ..Default::default() };
+
+    // fcvt.l.d t2, ft2 and fmv.d.x fs0, gp only exist on RV64
+    let fcvt = decode_instruction(0xC22173D3).unwrap();
+    assert!(execute_fp(&fcvt, &operands, 0, Xlen::Rv32).is_err());
+    let fmv = decode_instruction(0xF2018453).unwrap();
+    assert!(execute_fp(&fmv, &operands, 0, Xlen::Rv32).is_err());
+    assert!(execute_fp(&fmv, &operands, 0, Xlen::Rv64).is_ok());
+
+    // fld ft2, 8(ra)
+    let fld = decode_instruction(0x0080B107).unwrap();
+    let read_mem = execute_fp(&fld, &operands, 0, Xlen::Rv32).unwrap().read_mem.unwrap();
+    assert_eq!(read_mem.address, 4);
+    assert!(read_mem.fp);
+}
+
+fn execute_raw(raw: u32, rs1_val: u64, rs2_val: u64, xlen: Xlen) -> u64 {
+    let instruction = decode_instruction(raw).unwrap();
+    let result = match xlen {
+        Xlen::Rv32 => execute_rv32(&instruction, rs1_val as i64, rs2_val as i64, 0),
+        Xlen::Rv64 => execute(&instruction, rs1_val as i64, rs2_val as i64, 0),
+    };
+    result.unwrap().write_back.unwrap().value
+}
+
+#[test]
+fn test_execute_zba() {
+    assert_eq!(execute_raw(0x20C5A533, 5, 100, Xlen::Rv64), 110); // sh1add a0, a1, a2
+    assert_eq!(execute_raw(0x08C5853B, 0xFFFF_FFFF_8000_0000, 1, Xlen::Rv64), 0x8000_0001); // add.uw a0, a1, a2
+    assert_eq!(execute_raw(0x0845951B, 0xFFFF_FFFF_8000_0000, 0, Xlen::Rv64), 0x8_0000_0000); // slli.uw a0, a1, 4
+}
+
+#[test]
+fn test_execute_zbb() {
+    assert_eq!(execute_raw(0x40C5F533, 0b1111, 0b0101, Xlen::Rv64), 0b1010); // andn a0, a1, a2
+    assert_eq!(execute_raw(0x60059513, 1, 0, Xlen::Rv64), 63); // clz a0, a1
+    assert_eq!(execute_raw(0x60059513, 0, 0, Xlen::Rv64), 64);
+    assert_eq!(execute_raw(0x60159513, 0x100, 0, Xlen::Rv64), 8); // ctz a0, a1
+    assert_eq!(execute_raw(0x60259513, 0xF0F0, 0, Xlen::Rv64), 8); // cpop a0, a1
+    assert_eq!(execute_raw(0x6005951B, 0x1_0000_0001, 0, Xlen::Rv64), 31); // clzw a0, a1
+    assert_eq!(execute_raw(0x60459513, 0x80, 0, Xlen::Rv64), 0xFFFF_FFFF_FFFF_FF80); // sext.b a0, a1
+    assert_eq!(execute_raw(0x0805C53B, 0xFFFF_FFFF_FFFF_8001, 0, Xlen::Rv64), 0x8001); // zext.h a0, a1
+    assert_eq!(execute_raw(0x6B85D513, 0x0102_0304_0506_0708, 0, Xlen::Rv64), 0x0807_0605_0403_0201); // rev8 a0, a1
+    assert_eq!(execute_raw(0x2875D513, 0x0100_2000_0000_8000, 0, Xlen::Rv64), 0xFF00_FF00_0000_FF00); // orc.b a0, a1
+    assert_eq!(execute_raw(0x60C59533, 0x8000_0000_0000_0001, 1, Xlen::Rv64), 0x3); // rol a0, a1, a2
+    assert_eq!(execute_raw(0x6245D513, 0xF, 0, Xlen::Rv64), 0xF000_0000); // rori a0, a1, 36
+    assert_eq!(execute_raw(0x6045D51B, 0x1F, 0, Xlen::Rv64), 0xFFFF_FFFF_F000_0001); // roriw a0, a1, 4
+    assert_eq!(execute_raw(0x0AC5E533, -5i64 as u64, 3, Xlen::Rv64), 3); // max a0, a1, a2
+    assert_eq!(execute_raw(0x0AC5D533, -5i64 as u64, 3, Xlen::Rv64), 3); // minu a0, a1, a2
+}
+
+#[test]
+fn test_execute_zbc() {
+    assert_eq!(execute_raw(0x0AC59533, 0b11, 0b11, Xlen::Rv64), 0b101); // clmul a0, a1, a2
+    assert_eq!(execute_raw(0x0AC5B533, 1 << 63, 0b110, Xlen::Rv64), 0b11); // clmulh a0, a1, a2
+    assert_eq!(execute_raw(0x0AC5A533, 1 << 63, 0b110, Xlen::Rv64), 0b110); // clmulr a0, a1, a2
+}
+
+#[test]
+fn test_execute_zbs() {
+    assert_eq!(execute_raw(0x28C59533, 0, 65, Xlen::Rv64), 0b10); // bset a0, a1, a2
+    assert_eq!(execute_raw(0x4A859513, u64::MAX, 0, Xlen::Rv64), !(1 << 40)); // bclri a0, a1, 40
+    assert_eq!(execute_raw(0x4BF5D513, 1 << 63, 0, Xlen::Rv64), 1); // bexti a0, a1, 63
+    assert_eq!(execute_raw(0x68C59533, 0b100, 2, Xlen::Rv64), 0); // binv a0, a1, a2
+}
+
+#[test]
+fn test_execute_bitmanip_rv32() {
+    assert_eq!(execute_raw(0x6985D513, 0x0102_0304, 0, Xlen::Rv32), 0x0403_0201); // rev8 a0, a1
+    assert_eq!(execute_raw(0x0805C533, 0xFFFF_FFFF_FFFF_8001, 0, Xlen::Rv32), 0x8001); // zext.h a0, a1
+    assert_eq!(execute_raw(0x60C5D533, 1, 1, Xlen::Rv32), 0xFFFF_FFFF_8000_0000); // ror a0, a1, a2
+    assert_eq!(execute_raw(0x0AC5B533, 0xFFFF_FFFF_8000_0000, 0b110, Xlen::Rv32), 0b11); // clmulh a0, a1, a2
+    assert_eq!(execute_raw(0x29F59513, 0, 0, Xlen::Rv32), 0xFFFF_FFFF_8000_0000); // bseti a0, a1, 31
+    assert_eq!(execute_raw(0x60259513, 0xFFFF_FFFF_FFFF_FFFF, 0, Xlen::Rv32), 32); // cpop a0, a1
+    assert_eq!(execute_raw(0x28C59533, 0, 33, Xlen::Rv32), 0b10); // bset a0, a1, a2
+
+    // RV64 encodings of rev8 and the .uw instructions don't exist
+    assert!(execute_rv32(&decode_instruction(0x6B85D513).unwrap(), 0, 0, 0).is_err());
+    assert!(execute_rv32(&decode_instruction(0x08C5853B).unwrap(), 0, 0, 0).is_err());
+}
@@ -0,0 +1,128 @@
+use std::{cell::RefCell, io::Write, rc::Rc, sync::mpsc::{self, Sender}};
+
+use crate::{bus::Bus, components::*, uart::*};
+
+/// Collects what the UART transmits.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn uart() -> (Uart, Output, Sender<u8>) {
+    let output = Output::default();
+    let (sender, receiver) = mpsc::channel();
+    (Uart::new(Box::new(output.clone()), Some(receiver)), output, sender)
+}
+
+#[test]
+fn test_uart_transmit() {
+    let (uart, output, _input) = uart();
+    let mut cpu = CPU::new(0x100);
+    cpu.bus.map(UART_BASE, UART_SIZE, uart);
+    for (i, instruction) in [
+        0x100002B7u32, // lui t0, 0x10000
+        0x04800313, // li t1, 'H'
+        0x00628023, // sb t1, 0(t0)
+        0x0052C383, // lbu t2, 5(t0)
+    ].into_iter().enumerate() {
+        cpu.mem.write_word(i * 4, instruction as u64).unwrap();
+    }
+
+    for _ in 0..4 {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(*output.0.borrow(), b"H");
+    assert_eq!(cpu.regs[7], 0x60); // THR and transmitter empty
+}
+
+#[test]
+fn test_uart_receive() {
+    let (mut uart, _output, input) = uart();
+    assert_eq!(uart.load(5, 1).unwrap(), 0x60);
+
+    input.send(b'a').unwrap();
+    input.send(b'b').unwrap();
+    assert_eq!(uart.load(5, 1).unwrap(), 0x61);
+    assert_eq!(uart.load(0, 1).unwrap(), b'a' as u64);
+    assert_eq!(uart.load(0, 1).unwrap(), b'b' as u64);
+    assert_eq!(uart.load(5, 1).unwrap(), 0x60);
+
+    // With the FIFO enabled more than one byte waits in the receiver, until it's reset
+    uart.store(2, 1, 0x01).unwrap();
+    input.send(b'c').unwrap();
+    input.send(b'd').unwrap();
+    assert_eq!(uart.load(5, 1).unwrap(), 0x61);
+    assert_eq!(uart.load(2, 1).unwrap(), 0xC1);
+    uart.store(2, 1, 0x03).unwrap();
+    assert_eq!(uart.load(5, 1).unwrap(), 0x60);
+
+    // Without the received data interrupt IIR leaves the input alone, so a reset can't drop it
+    input.send(b'e').unwrap();
+    assert_eq!(uart.load(2, 1).unwrap(), 0xC1);
+    assert!(!uart.interrupt());
+    uart.store(2, 1, 0x03).unwrap();
+    assert_eq!(uart.load(5, 1).unwrap(), 0x61);
+    assert_eq!(uart.load(0, 1).unwrap(), b'e' as u64);
+
+    // Loopback hands back what's transmitted
+    uart.store(4, 1, 0x10).unwrap();
+    uart.store(0, 1, b'x' as u64).unwrap();
+    assert_eq!(uart.load(0, 1).unwrap(), b'x' as u64);
+}
+
+#[test]
+fn test_uart_interrupts() {
+    let (mut uart, _output, input) = uart();
+    assert!(!uart.interrupt());
+    assert_eq!(uart.load(2, 1).unwrap(), 0x01);
+
+    // Received data, as long as it's there
+    uart.store(1, 1, 0x01).unwrap();
+    assert!(!uart.interrupt());
+    input.send(b'a').unwrap();
+    assert!(uart.interrupt());
+    assert_eq!(uart.load(2, 1).unwrap(), 0x04);
+    uart.load(0, 1).unwrap();
+    assert!(!uart.interrupt());
+
+    // THR empty right after enabling it and after every byte, until IIR is read
+    uart.store(1, 1, 0x03).unwrap();
+    assert!(uart.interrupt());
+    assert_eq!(uart.load(2, 1).unwrap(), 0x02);
+    assert!(!uart.interrupt());
+    uart.store(0, 1, b'!' as u64).unwrap();
+    assert!(uart.interrupt());
+
+    // Received data goes first
+    input.send(b'b').unwrap();
+    assert_eq!(uart.load(2, 1).unwrap(), 0x04);
+}
+
+#[test]
+fn test_uart_divisor_latch() {
+    let (mut uart, output, _input) = uart();
+    uart.store(1, 1, 0x01).unwrap();
+
+    uart.store(3, 1, 0x83).unwrap();
+    uart.store(0, 1, 0x0C).unwrap();
+    uart.store(1, 1, 0x00).unwrap();
+    assert_eq!(uart.load(0, 1).unwrap(), 0x0C);
+    assert_eq!(uart.load(1, 1).unwrap(), 0x00);
+
+    // Back to the data registers, nothing was transmitted and IER kept its value
+    uart.store(3, 1, 0x03).unwrap();
+    assert_eq!(uart.load(1, 1).unwrap(), 0x01);
+    assert!(output.0.borrow().is_empty());
+
+    uart.store(7, 1, 0x5A).unwrap();
+    assert_eq!(uart.load(7, 1).unwrap(), 0x5A);
+}
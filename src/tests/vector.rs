@@ -0,0 +1,160 @@
+use crate::{instruction_formats::VType, stages::{ExecuteResult, MemSize, WriteBack, Xlen}, vector::*};
+
+/// e32, m1
+const E32: u64 = 0x10;
+
+fn execute(raw: u32, x_rs1: u64, x_rs2: u64, registers: &VectorRegisters, vl: u64, vtype: u64) -> Option<ExecuteResult> {
+    let state = VectorState { registers, vl, vtype, vstart: 0, xlen: Xlen::Rv64 };
+    execute_vector(&VType::from(raw), x_rs1, x_rs2, &state)
+}
+
+fn set_elements(registers: &mut VectorRegisters, register: usize, values: &[u32]) {
+    for (index, value) in values.iter().enumerate() {
+        let offset = register * registers.vlenb() + index * 4;
+        registers.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn written_elements(result: ExecuteResult) -> Vec<u32> {
+    match result.vector {
+        Some(VectorEffect::Write { bytes, .. }) => bytes.chunks(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap())).collect(),
+        effect => panic!("Expected a register write, got {effect:?}"),
+    }
+}
+
+#[test]
+fn test_vtype_decode() {
+    assert_eq!(VectorType::decode(E32), Some(VectorType { sew: 32, lmul_log2: 0 }));
+    assert_eq!(VectorType::decode(0xC1), Some(VectorType { sew: 8, lmul_log2: 1 }));
+    assert_eq!(VectorType::decode(0x17).unwrap().vlmax(128), 2); // e32, mf2
+    assert_eq!(VectorType::decode(0x03).unwrap().vlmax(128), 128); // e8, m8
+    assert_eq!(VectorType::decode(0x03).unwrap().group(), 8);
+
+    assert_eq!(VectorType::decode(0x04), None); // Reserved LMUL
+    assert_eq!(VectorType::decode(0x20), None); // e128
+    assert_eq!(VectorType::decode(0x1D), None); // e64, mf8
+    assert_eq!(VectorType::decode(0x100), None);
+    assert_eq!(VectorType::decode(VTYPE_VILL), None);
+}
+
+#[test]
+fn test_vsetvl() {
+    let registers = VectorRegisters::default();
+
+    // vsetvli a0, a1, e32, m1, ta, ma
+    let result = execute(0x0D05F557, 10, 0, &registers, 0, VTYPE_VILL).unwrap();
+    assert_eq!(result.write_back, Some(WriteBack { rd: 10, value: 4 }));
+    assert_eq!(result.vector, Some(VectorEffect::Configure { vl: 4, vtype: 0xD0 }));
+
+    let result = execute(0x0D05F557, 3, 0, &registers, 0, VTYPE_VILL).unwrap();
+    assert_eq!(result.vector, Some(VectorEffect::Configure { vl: 3, vtype: 0xD0 }));
+
+    // vsetivli a0, 4, e8, m2, tu, mu
+    let result = execute(0xC0127557, 0, 0, &registers, 0, VTYPE_VILL).unwrap();
+    assert_eq!(result.vector, Some(VectorEffect::Configure { vl: 4, vtype: 0x01 }));
+
+    // vsetvl a0, a1, a2 with a reserved vtype sets vill
+    let result = execute(0x80C5F557, 10, 0x04, &registers, 4, E32).unwrap();
+    assert_eq!(result.write_back, Some(WriteBack { rd: 10, value: 0 }));
+    assert_eq!(result.vector, Some(VectorEffect::Configure { vl: 0, vtype: VTYPE_VILL }));
+}
+
+#[test]
+fn test_vector_integer() {
+    let mut registers = VectorRegisters::default();
+    set_elements(&mut registers, 2, &[1, 2, 3, 0xFFFF_FFFF]);
+    set_elements(&mut registers, 3, &[10, 20, 30, 2]);
+
+    // vadd.vv v1, v2, v3
+    assert_eq!(written_elements(execute(0x022180D7, 0, 0, &registers, 4, E32).unwrap()), [11, 22, 33, 1]);
+    // vadd.vi v1, v2, -3
+    assert_eq!(written_elements(execute(0x022EB0D7, 0, 0, &registers, 4, E32).unwrap()), [0xFFFF_FFFE, 0xFFFF_FFFF, 0, 0xFFFF_FFFC]);
+    // vrsub.vx v1, v2, a0
+    assert_eq!(written_elements(execute(0x0E2540D7, 5, 0, &registers, 4, E32).unwrap()), [4, 3, 2, 6]);
+    // vmul.vv v1, v2, v3
+    assert_eq!(written_elements(execute(0x9621A0D7, 0, 0, &registers, 4, E32).unwrap()), [10, 40, 90, 0xFFFF_FFFE]);
+    // vsra.vx v1, v2, a0
+    assert_eq!(written_elements(execute(0xA62540D7, 1, 0, &registers, 4, E32).unwrap()), [0, 1, 1, 0xFFFF_FFFF]);
+    // vmaxu.vv v1, v2, v3
+    assert_eq!(written_elements(execute(0x1A2180D7, 0, 0, &registers, 4, E32).unwrap()), [10, 20, 30, 0xFFFF_FFFF]);
+
+    // Only the body is written, the tail stays undisturbed
+    set_elements(&mut registers, 1, &[7, 7, 7, 7]);
+    assert_eq!(written_elements(execute(0x022180D7, 0, 0, &registers, 2, E32).unwrap()), [11, 22, 7, 7]);
+
+    // Reserved without a valid vtype
+    assert!(execute(0x022180D7, 0, 0, &registers, 0, VTYPE_VILL).is_none());
+}
+
+#[test]
+fn test_vector_masks() {
+    let mut registers = VectorRegisters::default();
+    set_elements(&mut registers, 1, &[7, 7, 7, 7]);
+    set_elements(&mut registers, 2, &[5, 2, 5, 3]);
+    set_elements(&mut registers, 3, &[10, 20, 30, 40]);
+
+    // vmseq.vi v0, v2, 5
+    let result = execute(0x6222B057, 0, 0, &registers, 4, E32).unwrap();
+    let Some(VectorEffect::Write { offset: 0, bytes }) = result.vector else { panic!() };
+    assert_eq!(bytes[0], 0b0101);
+    registers.data[0] = 0b0101;
+
+    // vadd.vv v1, v2, v3, v0.t leaves the inactive elements alone
+    assert_eq!(written_elements(execute(0x002180D7, 0, 0, &registers, 4, E32).unwrap()), [15, 7, 35, 7]);
+    // vmerge.vvm v1, v2, v3, v0
+    assert_eq!(written_elements(execute(0x5C2180D7, 0, 0, &registers, 4, E32).unwrap()), [10, 2, 30, 3]);
+    // vcpop.m a0, v0
+    assert_eq!(execute(0x42082557, 0, 0, &registers, 4, E32).unwrap().write_back, Some(WriteBack { rd: 10, value: 2 }));
+    // vfirst.m a0, v0
+    assert_eq!(execute(0x4208A557, 0, 0, &registers, 4, E32).unwrap().write_back, Some(WriteBack { rd: 10, value: 0 }));
+}
+
+#[test]
+fn test_vector_reduction_and_moves() {
+    let mut registers = VectorRegisters::default();
+    set_elements(&mut registers, 2, &[1, 2, 3, 4]);
+    set_elements(&mut registers, 3, &[100, 0, 0, 0]);
+
+    // vredsum.vs v1, v2, v3
+    assert_eq!(written_elements(execute(0x0221A0D7, 0, 0, &registers, 4, E32).unwrap())[0], 110);
+    // vid.v v1
+    assert_eq!(written_elements(execute(0x5208A0D7, 0, 0, &registers, 4, E32).unwrap()), [0, 1, 2, 3]);
+    // vmv.v.i v1, 7
+    assert_eq!(written_elements(execute(0x5E03B0D7, 0, 0, &registers, 4, E32).unwrap()), [7, 7, 7, 7]);
+
+    // vmv.x.s a0, v1 sign extends the element
+    set_elements(&mut registers, 1, &[0x8000_0000]);
+    assert_eq!(execute(0x42102557, 0, 0, &registers, 4, E32).unwrap().write_back, Some(WriteBack { rd: 10, value: 0xFFFF_FFFF_8000_0000 }));
+}
+
+#[test]
+fn test_vector_memory() {
+    let mut registers = VectorRegisters::default();
+    set_elements(&mut registers, 1, &[0xAA, 0xBB]);
+
+    // vle32.v v1, (a0)
+    let Some(VectorEffect::Load(elements)) = execute(0x02056087, 0x1000, 0, &registers, 2, E32).unwrap().vector else { panic!() };
+    assert_eq!(elements, [
+        ElementAccess { index: 0, address: 0x1000, size: MemSize::Word, offset: 16, data: 0 },
+        ElementAccess { index: 1, address: 0x1004, size: MemSize::Word, offset: 20, data: 0 },
+    ]);
+
+    // vse32.v v1, (a1)
+    let Some(VectorEffect::Store(elements)) = execute(0x0205E0A7, 0x2000, 0, &registers, 2, E32).unwrap().vector else { panic!() };
+    assert_eq!(elements.iter().map(|element| (element.address, element.data)).collect::<Vec<_>>(), [(0x2000, 0xAA), (0x2004, 0xBB)]);
+
+    // vlse32.v v1, (a0), a2
+    let Some(VectorEffect::Load(elements)) = execute(0x0AC56087, 0x1000, 0x10, &registers, 2, E32).unwrap().vector else { panic!() };
+    assert_eq!(elements[1].address, 0x1010);
+
+    // vluxei32.v v1, (a0), v2
+    set_elements(&mut registers, 2, &[8, 0]);
+    let Some(VectorEffect::Load(elements)) = execute(0x06256087, 0x1000, 0, &registers, 2, E32).unwrap().vector else { panic!() };
+    assert_eq!(elements[0].address, 0x1008);
+    assert_eq!(elements[1].address, 0x1000);
+
+    // vle64.v v8, (a0) with EMUL = 2 and vle8.v with EMUL = 1/4
+    assert!(execute(0x02057407, 0x1000, 0, &registers, 4, E32).is_some());
+    let Some(VectorEffect::Load(elements)) = execute(0x02050087, 0x1000, 0, &registers, 4, E32).unwrap().vector else { panic!() };
+    assert_eq!(elements[3].size, MemSize::Byte);
+}
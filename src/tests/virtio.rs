@@ -0,0 +1,164 @@
+use std::io::Cursor;
+
+use crate::{bus::Bus, components::*, csr::*, plic::*, virtio::*};
+
+// Where the tests put the queue and the request
+const DESC: u64 = 0x1000;
+const AVAIL: u64 = 0x2000;
+const USED: u64 = 0x3000;
+const HEADER: u64 = 0x4000;
+const DATA: u64 = 0x5000;
+const STATUS: u64 = 0x6000;
+
+fn disk() -> VirtioBlock {
+    let image = (0..4 * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect::<Vec<_>>();
+    VirtioBlock::new(Cursor::new(image)).unwrap()
+}
+
+fn setup_queue(bus: &mut impl Bus, base: u64) {
+    bus.store(base + 0x070, 4, 1 | 2).unwrap(); // acknowledge, driver
+    bus.store(base + 0x024, 4, 1).unwrap();
+    bus.store(base + 0x020, 4, 1).unwrap(); // VIRTIO_F_VERSION_1
+    bus.store(base + 0x070, 4, 1 | 2 | 8).unwrap(); // features ok
+    bus.store(base + 0x038, 4, 8).unwrap();
+    bus.store(base + 0x080, 4, DESC).unwrap();
+    bus.store(base + 0x090, 4, AVAIL).unwrap();
+    bus.store(base + 0x0A0, 4, USED).unwrap();
+    bus.store(base + 0x044, 4, 1).unwrap();
+    bus.store(base + 0x070, 4, 1 | 2 | 8 | 4).unwrap(); // driver ok
+}
+
+/// Puts a header, data and status descriptor chain into the available ring.
+fn submit(mem: &mut Memory, kind: u64, sector: u64, length: u64, writable: bool) {
+    mem.write_word(HEADER as usize, kind).unwrap();
+    mem.write_double_word(HEADER as usize + 8, sector).unwrap();
+    mem.write_byte(STATUS as usize, 0xFF).unwrap();
+
+    let descriptors = [(HEADER, 16, 1), (DATA, length, 1 | if writable { 2 } else { 0 }), (STATUS, 1, 2)];
+    for (i, (address, length, flags)) in descriptors.into_iter().enumerate() {
+        let entry = DESC as usize + 16 * i;
+        mem.write_double_word(entry, address).unwrap();
+        mem.write_word(entry + 8, length).unwrap();
+        mem.write_half_word(entry + 12, flags).unwrap();
+        mem.write_half_word(entry + 14, i as u64 + 1).unwrap();
+    }
+
+    let index = mem.read_half_word(AVAIL as usize + 2, false).unwrap();
+    mem.write_half_word(AVAIL as usize + 4 + 2 * (index as usize % 8), 0).unwrap();
+    mem.write_half_word(AVAIL as usize + 2, index + 1).unwrap();
+}
+
+fn run(block: &mut VirtioBlock, mem: &mut Memory) -> u64 {
+    block.store(0x050, 4, 0).unwrap();
+    block.process(mem);
+    mem.read_byte(STATUS as usize, false).unwrap()
+}
+
+#[test]
+fn test_virtio_registers() {
+    let mut block = disk();
+    assert_eq!(block.load(0x000, 4).unwrap(), 0x7472_6976);
+    assert_eq!(block.load(0x004, 4).unwrap(), 2);
+    assert_eq!(block.load(0x008, 4).unwrap(), 2);
+    assert_eq!(block.load(0x034, 4).unwrap(), 256);
+    block.store(0x014, 4, 1).unwrap();
+    assert_eq!(block.load(0x010, 4).unwrap(), 1);
+
+    // The capacity in sectors
+    assert_eq!(block.load(0x100, 8).unwrap(), 4);
+    assert_eq!(block.load(0x100, 4).unwrap(), 4);
+    assert_eq!(block.load(0x104, 4).unwrap(), 0);
+
+    // There's only queue 0
+    block.store(0x030, 4, 1).unwrap();
+    assert_eq!(block.load(0x034, 4).unwrap(), 0);
+
+    // Unknown features aren't accepted
+    block.store(0x020, 4, 1 << 31).unwrap();
+    block.store(0x070, 4, 1 | 2 | 8).unwrap();
+    assert_eq!(block.load(0x070, 4).unwrap(), 1 | 2);
+    block.store(0x070, 4, 0).unwrap();
+    assert_eq!(block.load(0x070, 4).unwrap(), 0);
+    assert_eq!(block.load(0x034, 4).unwrap(), 256);
+}
+
+#[test]
+fn test_virtio_requests() {
+    let mut mem = Memory::new(0x8000);
+    let mut block = disk();
+    setup_queue(&mut block, 0);
+
+    // Read sector 2
+    submit(&mut mem, 0, 2, 512, true);
+    assert_eq!(run(&mut block, &mut mem), 0);
+    assert!(mem.data[DATA as usize..DATA as usize + 512].iter().all(|byte| *byte == 2));
+    assert_eq!(mem.read_half_word(USED as usize + 2, false).unwrap(), 1);
+    assert_eq!(mem.read_word(USED as usize + 4).unwrap(), 0);
+    assert_eq!(mem.read_word(USED as usize + 8).unwrap(), 513);
+    assert!(block.interrupt());
+    assert_eq!(block.load(0x060, 4).unwrap(), 1);
+    block.store(0x064, 4, 1).unwrap();
+    assert!(!block.interrupt());
+
+    // Write sector 3 and read it back
+    mem.data[DATA as usize..DATA as usize + 512].fill(0xAB);
+    submit(&mut mem, 1, 3, 512, false);
+    assert_eq!(run(&mut block, &mut mem), 0);
+    assert_eq!(mem.read_word(USED as usize + 4 + 8 + 4).unwrap(), 1);
+    mem.data[DATA as usize..DATA as usize + 512].fill(0);
+    submit(&mut mem, 0, 3, 512, true);
+    assert_eq!(run(&mut block, &mut mem), 0);
+    assert!(mem.data[DATA as usize..DATA as usize + 512].iter().all(|byte| *byte == 0xAB));
+
+    // Past the end of the disk
+    submit(&mut mem, 0, 4, 512, true);
+    assert_eq!(run(&mut block, &mut mem), 1);
+    // Reading into a buffer the device may not write
+    submit(&mut mem, 0, 0, 512, false);
+    assert_eq!(run(&mut block, &mut mem), 1);
+
+    submit(&mut mem, 8, 0, 20, true);
+    assert_eq!(run(&mut block, &mut mem), 0);
+    assert_eq!(&mem.data[DATA as usize..DATA as usize + 10], b"riscv-sim\0");
+    submit(&mut mem, 4, 0, 0, true);
+    assert_eq!(run(&mut block, &mut mem), 0);
+    submit(&mut mem, 11, 0, 0, true);
+    assert_eq!(run(&mut block, &mut mem), 2);
+    assert_eq!(mem.read_half_word(USED as usize + 2, false).unwrap(), 8);
+
+    // Nothing happens without a notification
+    submit(&mut mem, 0, 0, 512, true);
+    block.process(&mut mem);
+    assert_eq!(mem.read_byte(STATUS as usize, false).unwrap(), 0xFF);
+}
+
+#[test]
+fn test_cpu_virtio_interrupt() {
+    let mut cpu = CPU::new(0x8000);
+    cpu.mem.write_word(0, 0x100012B7).unwrap(); // lui t0, 0x10001
+    cpu.mem.write_word(4, 0x0402A823).unwrap(); // sw zero, 0x50(t0)
+    cpu.csr.mtvec = 0x800;
+
+    cpu.add_virtio_block(VIRTIO_BASE, disk());
+    let line = cpu.virtio.clone().unwrap();
+    let mut plic = Plic::new();
+    plic.connect(VIRTIO_IRQ, move || line.borrow().interrupt());
+    cpu.add_plic(PLIC_BASE, plic);
+    cpu.bus.store(PLIC_BASE + 4 * VIRTIO_IRQ as u64, 4, 1).unwrap();
+    cpu.bus.store(PLIC_BASE + 0x2000, 4, 1 << VIRTIO_IRQ).unwrap();
+    cpu.csr.mie = MIP_MEIP;
+    cpu.csr.mstatus |= MSTATUS_MIE;
+
+    setup_queue(&mut cpu.bus, VIRTIO_BASE);
+    submit(&mut cpu.mem, 0, 1, 512, true);
+
+    // The request is done once the notifying store retires
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.mem.read_byte(STATUS as usize, false).unwrap(), 0);
+    assert!(cpu.mem.data[DATA as usize..DATA as usize + 512].iter().all(|byte| *byte == 1));
+
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc.address, 0x800);
+    assert_eq!(cpu.csr.mcause, (1 << 63) | 11);
+}
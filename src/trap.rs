@@ -0,0 +1,92 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadAddressMisaligned,
+    LoadAccessFault,
+    StoreAddressMisaligned,
+    StoreAccessFault,
+    EnvironmentCallFromUMode,
+    EnvironmentCallFromSMode,
+    EnvironmentCallFromMMode,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+}
+
+impl Exception {
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::InstructionAddressMisaligned => 0,
+            Exception::InstructionAccessFault => 1,
+            Exception::IllegalInstruction => 2,
+            Exception::Breakpoint => 3,
+            Exception::LoadAddressMisaligned => 4,
+            Exception::LoadAccessFault => 5,
+            Exception::StoreAddressMisaligned => 6,
+            Exception::StoreAccessFault => 7,
+            Exception::EnvironmentCallFromUMode => 8,
+            Exception::EnvironmentCallFromSMode => 9,
+            Exception::EnvironmentCallFromMMode => 11,
+            Exception::InstructionPageFault => 12,
+            Exception::LoadPageFault => 13,
+            Exception::StorePageFault => 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+}
+
+impl Interrupt {
+    pub fn code(&self) -> u64 {
+        match self {
+            Interrupt::SupervisorSoftware => 1,
+            Interrupt::MachineSoftware => 3,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::MachineTimer => 7,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::MachineExternal => 11,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Exception { cause: Exception, tval: u64 },
+    Interrupt(Interrupt),
+}
+
+impl Trap {
+    pub fn exception(cause: Exception, tval: u64) -> Self {
+        Trap::Exception { cause, tval }
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        matches!(self, Trap::Interrupt(_))
+    }
+
+    /// The exception or interrupt code, without the interrupt bit of `mcause`.
+    pub fn code(&self) -> u64 {
+        match self {
+            Trap::Exception { cause, .. } => cause.code(),
+            Trap::Interrupt(interrupt) => interrupt.code(),
+        }
+    }
+
+    pub fn tval(&self) -> u64 {
+        match self {
+            Trap::Exception { tval, .. } => *tval,
+            Trap::Interrupt(_) => 0,
+        }
+    }
+}
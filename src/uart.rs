@@ -0,0 +1,204 @@
+use std::{collections::VecDeque, io::{self, Write}, sync::mpsc::Receiver};
+
+use crate::{bus::Bus, components::MemoryError, syscall::stdin};
+
+/// Where QEMU's virt machine has its UART.
+pub const UART_BASE: u64 = 0x1000_0000;
+/// The eight byte wide registers.
+pub const UART_SIZE: u64 = 0x100;
+/// Its interrupt source on virt's PLIC.
+pub const UART_IRQ: usize = 10;
+
+// Register offsets, the first two are the divisor latch while LCR.DLAB is set
+const RBR_THR_DLL: u64 = 0;
+const IER_DLM: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_THR_EMPTY: u8 = 1 << 1;
+
+const IIR_NO_INTERRUPT: u8 = 0x01;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_RX_RESET: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+/// Carrier detect, ring, data set ready and clear to send.
+const MSR_CONNECTED: u8 = 0xB0;
+
+const FIFO_SIZE: usize = 16;
+
+/// An NS16550A UART with byte wide registers, like the one in QEMU's virt machine.
+///
+/// Transmitting is instant, so the transmitter always reads as empty. Received bytes are taken
+/// from the input whenever the guest looks at the receiver or the interrupt line.
+pub struct Uart {
+    /// Transmitted bytes go here, stdout by default.
+    pub output: Box<dyn Write>,
+    /// Received bytes come from here, or from the shared stdin when it's `None`.
+    pub input: Option<Receiver<u8>>,
+    rx: VecDeque<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    /// A THR empty interrupt is pending until IIR reports it or THR is written.
+    thr_interrupt: bool,
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self::new(Box::new(io::stdout()), None)
+    }
+}
+
+impl Uart {
+    /// A UART that writes to `output` and reads from `input`, or stdin when it's `None`.
+    pub fn new(output: Box<dyn Write>, input: Option<Receiver<u8>>) -> Self {
+        Self {
+            output,
+            input,
+            rx: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            thr_interrupt: false,
+        }
+    }
+
+    /// The level of the interrupt line, for an interrupt controller to pick up.
+    pub fn interrupt(&mut self) -> bool {
+        self.identify() != IIR_NO_INTERRUPT
+    }
+
+    /// Moves waiting input into the receive FIFO.
+    fn receive(&mut self) {
+        let capacity = if self.fcr & FCR_FIFO_ENABLE != 0 { FIFO_SIZE } else { 1 };
+        let fill = |rx: &mut VecDeque<u8>, input: &Receiver<u8>| {
+            while rx.len() < capacity {
+                let Ok(byte) = input.try_recv() else { break };
+                rx.push_back(byte);
+            }
+        };
+
+        match &self.input {
+            Some(input) => fill(&mut self.rx, input),
+            None => fill(&mut self.rx, &stdin()),
+        }
+    }
+
+    /// The highest priority interrupt that's pending, as IIR reports it. Input is only
+    /// looked at when the guest wants to hear about it, so stdin stays untouched otherwise.
+    fn identify(&mut self) -> u8 {
+        if self.ier & IER_RX_AVAILABLE != 0 {
+            self.receive();
+        }
+
+        if self.ier & IER_RX_AVAILABLE != 0 && !self.rx.is_empty() {
+            IIR_RX_AVAILABLE
+        } else if self.ier & IER_THR_EMPTY != 0 && self.thr_interrupt {
+            IIR_THR_EMPTY
+        } else {
+            IIR_NO_INTERRUPT
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        // In loopback mode the byte comes straight back
+        if self.mcr & MCR_LOOPBACK != 0 {
+            if self.rx.len() < FIFO_SIZE {
+                self.rx.push_back(byte);
+            }
+        } else {
+            let _ = self.output.write_all(&[byte]).and_then(|_| self.output.flush());
+        }
+
+        self.thr_interrupt = true;
+    }
+}
+
+impl Bus for Uart {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, MemoryError> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+
+        let value = match offset {
+            RBR_THR_DLL if dlab => self.divisor as u8,
+            RBR_THR_DLL => {
+                self.receive();
+                self.rx.pop_front().unwrap_or(0)
+            },
+            IER_DLM if dlab => (self.divisor >> 8) as u8,
+            IER_DLM => self.ier,
+            IIR_FCR => {
+                let id = self.identify();
+                if id == IIR_THR_EMPTY {
+                    self.thr_interrupt = false;
+                }
+                let fifo = if self.fcr & FCR_FIFO_ENABLE != 0 { IIR_FIFO_ENABLED } else { 0 };
+                id | fifo
+            },
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                self.receive();
+                let ready = if self.rx.is_empty() { 0 } else { LSR_DATA_READY };
+                ready | LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY
+            },
+            MSR => MSR_CONNECTED,
+            SCR => self.scr,
+            _ => 0,
+        };
+
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), MemoryError> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = value as u8;
+
+        match offset {
+            RBR_THR_DLL if dlab => self.divisor = (self.divisor & 0xFF00) | value as u16,
+            RBR_THR_DLL => self.transmit(value),
+            IER_DLM if dlab => self.divisor = (self.divisor & 0x00FF) | (value as u16) << 8,
+            IER_DLM => {
+                // Enabling the THR empty interrupt raises it right away, the holding register
+                // always is empty
+                if value & IER_THR_EMPTY != 0 && self.ier & IER_THR_EMPTY == 0 {
+                    self.thr_interrupt = true;
+                }
+                self.ier = value & 0x0F;
+            },
+            IIR_FCR => {
+                if value & FCR_RX_RESET != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = value;
+            },
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1F,
+            SCR => self.scr = value,
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
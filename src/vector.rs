@@ -0,0 +1,519 @@
+use crate::{instruction_formats::VType, stages::{ExecuteResult, MemSize, WriteBack, Xlen}};
+
+/// The widest supported element.
+const ELEN: u64 = 64;
+
+/// vill in the normalized `vtype` kept by the CSR file, it moves to bit 31 when read on RV32.
+pub const VTYPE_VILL: u64 = 1 << 63;
+
+/// The 32 vector registers, `vlen` bits each, stored back to back so a register group is a
+/// contiguous slice.
+#[derive(Debug, Clone)]
+pub struct VectorRegisters {
+    pub vlen: usize,
+    pub data: Vec<u8>,
+}
+
+impl Default for VectorRegisters {
+    fn default() -> Self {
+        Self::new(128)
+    }
+}
+
+impl VectorRegisters {
+    /// Panics unless `vlen` is a power of two between 64 and 65536 bits.
+    pub fn new(vlen: usize) -> Self {
+        assert!(vlen.is_power_of_two() && (64..=65536).contains(&vlen), "Unsupported VLEN: {vlen}");
+        Self { vlen, data: vec![0; vlen / 8 * 32] }
+    }
+
+    pub fn vlenb(&self) -> usize {
+        self.vlen / 8
+    }
+
+    fn element(&self, register: u8, index: u64, bytes: usize) -> u64 {
+        read_element(&self.data, register as usize * self.vlenb() + index as usize * bytes, bytes)
+    }
+
+    fn mask_bit(&self, register: u8, index: u64) -> bool {
+        self.data[register as usize * self.vlenb() + index as usize / 8] >> (index % 8) & 1 == 1
+    }
+}
+
+fn read_element(data: &[u8], offset: usize, bytes: usize) -> u64 {
+    data[offset..offset + bytes].iter().rev().fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn write_element(data: &mut [u8], offset: usize, bytes: usize, value: u64) {
+    data[offset..offset + bytes].copy_from_slice(&value.to_le_bytes()[..bytes]);
+}
+
+fn sign_extend(value: u64, bits: u64) -> i64 {
+    ((value << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/// A valid `vtype`: the element width and the register group multiplier LMUL as a power of two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorType {
+    pub sew: u64,
+    pub lmul_log2: i8,
+}
+
+impl VectorType {
+    /// Decodes a normalized `vtype`, `None` when vill is set or the setting is reserved.
+    pub fn decode(vtype: u64) -> Option<Self> {
+        if vtype & !0xFF != 0 {
+            return None;
+        }
+
+        let vsew = (vtype >> 3) & 0b111;
+        let lmul_log2 = match vtype & 0b111 {
+            4 => return None,
+            vlmul @ 0..=3 => vlmul as i8,
+            vlmul => vlmul as i8 - 8,
+        };
+        if vsew > 3 {
+            return None;
+        }
+        let sew = 8 << vsew;
+
+        // Fractional groups still have to hold at least one ELEN wide element
+        if lmul_log2 < 0 && sew > ELEN >> -lmul_log2 {
+            return None;
+        }
+
+        Some(Self { sew, lmul_log2 })
+    }
+
+    pub fn vlmax(&self, vlen: usize) -> u64 {
+        scale(vlen as u64, self.lmul_log2) / self.sew
+    }
+
+    /// The number of registers in a group, fractional LMUL still occupies one.
+    pub fn group(&self) -> u8 {
+        1 << self.lmul_log2.max(0)
+    }
+
+    fn bytes(&self) -> usize {
+        self.sew as usize / 8
+    }
+}
+
+fn scale(value: u64, log2: i8) -> u64 {
+    if log2 >= 0 { value << log2 } else { value >> -log2 }
+}
+
+/// A single element of a vector load or store. `offset` is the element's position in the
+/// register file, `index` its position in the vector for restarting through `vstart`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementAccess {
+    pub index: u64,
+    pub address: u64,
+    pub size: MemSize,
+    pub offset: usize,
+    pub data: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VectorEffect {
+    /// The vl and normalized vtype chosen by a vsetvl instruction.
+    Configure { vl: u64, vtype: u64 },
+    /// Replaces the register file bytes starting at `offset`.
+    Write { offset: usize, bytes: Vec<u8> },
+    Load(Vec<ElementAccess>),
+    Store(Vec<ElementAccess>),
+}
+
+/// Everything a vector instruction reads besides its scalar operands.
+pub struct VectorState<'a> {
+    pub registers: &'a VectorRegisters,
+    pub vl: u64,
+    pub vtype: u64,
+    pub vstart: u64,
+    pub xlen: Xlen,
+}
+
+impl VectorState<'_> {
+    fn active(&self, vm: bool, index: u64) -> bool {
+        vm || self.registers.mask_bit(0, index)
+    }
+
+    fn body(&self) -> std::ops::Range<u64> {
+        self.vstart..self.vl
+    }
+}
+
+fn aligned(register: u8, group: u8) -> bool {
+    register.is_multiple_of(group)
+}
+
+/// Executes a decoded vector instruction, `None` for reserved or unsupported encodings.
+pub fn execute_vector(v: &VType, x_rs1: u64, x_rs2: u64, state: &VectorState) -> Option<ExecuteResult> {
+    match (v.opcode, v.func3) {
+        (0b1010111, 0b111) => execute_config(v, x_rs1, x_rs2, state),
+        (0b1010111, 0b000 | 0b011 | 0b100) => execute_integer(v, x_rs1, state),
+        (0b1010111, 0b010 | 0b110) => execute_mask_and_multiply(v, x_rs1, state),
+        (0b0000111, _) => execute_memory(v, x_rs1, x_rs2, state, false),
+        (0b0100111, _) => execute_memory(v, x_rs1, x_rs2, state, true),
+        _ => None,
+    }
+}
+
+/// VSETVLI, VSETIVLI and VSETVL
+fn execute_config(v: &VType, x_rs1: u64, x_rs2: u64, state: &VectorState) -> Option<ExecuteResult> {
+    let (avl, vtype) = match v.func6 >> 4 {
+        0b00 | 0b01 => (None, v.zimm as u64), // VSETVLI
+        0b11 => (Some(v.rs1 as u64), v.zimm as u64 & 0x3FF), // VSETIVLI
+        0b10 if v.func6 == 0b100000 && !v.vm => { // VSETVL
+            // Without its vill bit anything above the low byte is reserved and rejected below
+            let vtype = match state.xlen {
+                Xlen::Rv32 => x_rs2 & 0x7FFF_FFFF,
+                Xlen::Rv64 => x_rs2 & !VTYPE_VILL,
+            };
+            (None, vtype)
+        },
+        _ => return None,
+    };
+
+    // rs1 = x0 asks for VLMAX, or keeps vl when rd is x0 as well
+    let avl = avl.unwrap_or(match (v.rs1, v.vd) {
+        (0, 0) => state.vl,
+        (0, _) => u64::MAX,
+        _ => x_rs1,
+    });
+
+    let (vl, vtype) = match VectorType::decode(vtype) {
+        Some(decoded) => (avl.min(decoded.vlmax(state.registers.vlen)), vtype),
+        None => (0, VTYPE_VILL),
+    };
+
+    Some(ExecuteResult::default()
+        .with_write_back(WriteBack { rd: v.vd, value: vl })
+        .with_vector(VectorEffect::Configure { vl, vtype })
+    )
+}
+
+/// Writes every body element of the vd group the closure returns a value for.
+fn write_elements(v: &VType, state: &VectorState, vtype: VectorType, element: impl Fn(u64) -> Option<u64>) -> Option<ExecuteResult> {
+    // A masked instruction can't overwrite its own mask
+    if !aligned(v.vd, vtype.group()) || (!v.vm && v.vd == 0) {
+        return None;
+    }
+
+    let vlenb = state.registers.vlenb();
+    let offset = v.vd as usize * vlenb;
+    let mut bytes = state.registers.data[offset..offset + vtype.group() as usize * vlenb].to_vec();
+
+    for index in state.body() {
+        if let Some(value) = element(index) {
+            write_element(&mut bytes, index as usize * vtype.bytes(), vtype.bytes(), value);
+        }
+    }
+
+    Some(ExecuteResult::default()
+        .with_vector(VectorEffect::Write { offset, bytes })
+    )
+}
+
+/// Writes mask bits to vd for every body element the closure returns a value for.
+fn write_mask(v: &VType, state: &VectorState, bit: impl Fn(u64) -> Option<bool>) -> Option<ExecuteResult> {
+    let vlenb = state.registers.vlenb();
+    let offset = v.vd as usize * vlenb;
+    let mut bytes = state.registers.data[offset..offset + vlenb].to_vec();
+
+    for index in state.body() {
+        if let Some(set) = bit(index) {
+            let byte = &mut bytes[index as usize / 8];
+            *byte = (*byte & !(1 << (index % 8))) | ((set as u8) << (index % 8));
+        }
+    }
+
+    Some(ExecuteResult::default()
+        .with_vector(VectorEffect::Write { offset, bytes })
+    )
+}
+
+/// The OPIVV, OPIVX and OPIVI integer instructions.
+fn execute_integer(v: &VType, x_rs1: u64, state: &VectorState) -> Option<ExecuteResult> {
+    let vtype = VectorType::decode(state.vtype)?;
+    let sew = vtype.sew;
+    let bytes = vtype.bytes();
+    let truncate = |value: u64| if sew == 64 { value } else { value & ((1 << sew) - 1) };
+    let signed = |value: u64| sign_extend(value, sew);
+
+    let (vv, vi) = (v.func3 == 0b000, v.func3 == 0b011);
+    let scalar = match v.func3 {
+        0b100 => x_rs1,
+        // Shifts take the immediate unsigned, everything else sign extends it
+        0b011 if matches!(v.func6, 0b100101 | 0b101000 | 0b101001) => v.rs1 as u64,
+        0b011 => sign_extend(v.rs1 as u64, 5) as u64,
+        _ => 0,
+    };
+
+    if !aligned(v.rs2, vtype.group()) || (vv && !aligned(v.rs1, vtype.group())) {
+        return None;
+    }
+
+    let a = |index: u64| state.registers.element(v.rs2, index, bytes);
+    let b = |index: u64| if vv { state.registers.element(v.rs1, index, bytes) } else { truncate(scalar) };
+    let shift = |index: u64| (b(index) & (sew - 1)) as u32;
+
+    let arithmetic: fn(u64, u64, u64) -> u64 = match v.func6 {
+        0b000000 => |a, b, _| a.wrapping_add(b), // VADD
+        0b000010 if !vi => |a, b, _| a.wrapping_sub(b), // VSUB
+        0b000011 if !vv => |a, b, _| b.wrapping_sub(a), // VRSUB Reverse subtract
+        0b000100 if !vi => |a, b, _| a.min(b), // VMINU
+        0b000101 if !vi => |a, b, sew| if sign_extend(a, sew) < sign_extend(b, sew) { a } else { b }, // VMIN
+        0b000110 if !vi => |a, b, _| a.max(b), // VMAXU
+        0b000111 if !vi => |a, b, sew| if sign_extend(a, sew) > sign_extend(b, sew) { a } else { b }, // VMAX
+        0b001001 => |a, b, _| a & b, // VAND
+        0b001010 => |a, b, _| a | b, // VOR
+        0b001011 => |a, b, _| a ^ b, // VXOR
+        0b010111 if v.vm => { // VMV.V Move, vs2 has to be v0
+            if v.rs2 != 0 {
+                return None;
+            }
+            return write_elements(v, state, vtype, |index| Some(b(index)));
+        },
+        0b010111 => { // VMERGE Merge under mask, every body element is written
+            return write_elements(v, state, vtype, |index| Some(if state.registers.mask_bit(0, index) { b(index) } else { a(index) }));
+        },
+        0b011000..=0b011111 => {
+            let compare: fn(u64, u64, u64) -> bool = match v.func6 {
+                0b011000 => |a, b, _| a == b, // VMSEQ Set if equal
+                0b011001 => |a, b, _| a != b, // VMSNE Set if not equal
+                0b011010 if !vi => |a, b, _| a < b, // VMSLTU Set if less than unsigned
+                0b011011 if !vi => |a, b, sew| sign_extend(a, sew) < sign_extend(b, sew), // VMSLT Set if less than
+                0b011100 => |a, b, _| a <= b, // VMSLEU Set if less than or equal unsigned
+                0b011101 => |a, b, sew| sign_extend(a, sew) <= sign_extend(b, sew), // VMSLE Set if less than or equal
+                0b011110 if !vv => |a, b, _| a > b, // VMSGTU Set if greater than unsigned
+                0b011111 if !vv => |a, b, sew| sign_extend(a, sew) > sign_extend(b, sew), // VMSGT Set if greater than
+                _ => return None,
+            };
+            return write_mask(v, state, |index| state.active(v.vm, index).then(|| compare(a(index), b(index), sew)));
+        },
+        0b100101 => { // VSLL Shift left logical
+            return write_elements(v, state, vtype, |index| state.active(v.vm, index).then(|| truncate(a(index) << shift(index))));
+        },
+        0b101000 => { // VSRL Shift right logical
+            return write_elements(v, state, vtype, |index| state.active(v.vm, index).then(|| a(index) >> shift(index)));
+        },
+        0b101001 => { // VSRA Shift right arithmetic
+            return write_elements(v, state, vtype, |index| state.active(v.vm, index).then(|| truncate((signed(a(index)) >> shift(index)) as u64)));
+        },
+        _ => return None,
+    };
+
+    write_elements(v, state, vtype, |index| state.active(v.vm, index).then(|| truncate(arithmetic(a(index), b(index), sew))))
+}
+
+/// The OPMVV and OPMVX instructions: multiply and divide, reductions, mask operations and moves
+/// between the vector and scalar registers.
+fn execute_mask_and_multiply(v: &VType, x_rs1: u64, state: &VectorState) -> Option<ExecuteResult> {
+    let vtype = VectorType::decode(state.vtype)?;
+    let sew = vtype.sew;
+    let bytes = vtype.bytes();
+    let truncate = |value: u64| if sew == 64 { value } else { value & ((1 << sew) - 1) };
+    let vv = v.func3 == 0b010;
+
+    let a = |index: u64| state.registers.element(v.rs2, index, bytes);
+    let b = |index: u64| if vv { state.registers.element(v.rs1, index, bytes) } else { truncate(x_rs1) };
+    let to_xlen = |value: u64| match state.xlen {
+        Xlen::Rv32 => value as i32 as u64,
+        Xlen::Rv64 => value,
+    };
+
+    match (v.func6, vv) {
+        (0b000000..=0b000111, true) => { // VRED* Reductions into element 0 of vd
+            if !aligned(v.rs2, vtype.group()) || state.vstart != 0 {
+                return None;
+            }
+            if state.vl == 0 {
+                return Some(ExecuteResult::default());
+            }
+
+            let signed = |value: u64| sign_extend(value, sew);
+            let reduce: fn(u64, u64, &dyn Fn(u64) -> i64) -> u64 = match v.func6 {
+                0b000000 => |acc, x, _| acc.wrapping_add(x), // VREDSUM
+                0b000001 => |acc, x, _| acc & x, // VREDAND
+                0b000010 => |acc, x, _| acc | x, // VREDOR
+                0b000011 => |acc, x, _| acc ^ x, // VREDXOR
+                0b000100 => |acc, x, _| acc.min(x), // VREDMINU
+                0b000101 => |acc, x, signed| if signed(x) < signed(acc) { x } else { acc }, // VREDMIN
+                0b000110 => |acc, x, _| acc.max(x), // VREDMAXU
+                _ => |acc, x, signed| if signed(x) > signed(acc) { x } else { acc }, // VREDMAX
+            };
+
+            let result = (0..state.vl)
+                .filter(|index| state.active(v.vm, *index))
+                .fold(state.registers.element(v.rs1, 0, bytes), |acc, index| truncate(reduce(acc, a(index), &signed)));
+
+            let vlenb = state.registers.vlenb();
+            let offset = v.vd as usize * vlenb;
+            let mut register = state.registers.data[offset..offset + vlenb].to_vec();
+            write_element(&mut register, 0, bytes, result);
+
+            Some(ExecuteResult::default()
+                .with_vector(VectorEffect::Write { offset, bytes: register })
+            )
+        },
+        (0b010000, true) => match v.rs1 {
+            0b00000 if v.vm => { // VMV.X.S Move element 0 to a scalar register
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: v.vd, value: to_xlen(sign_extend(a(0), sew) as u64) })
+                )
+            },
+            0b10000 => { // VCPOP.M Count the set mask bits
+                let count = (state.vstart..state.vl)
+                    .filter(|index| state.active(v.vm, *index) && state.registers.mask_bit(v.rs2, *index))
+                    .count();
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: v.vd, value: count as u64 })
+                )
+            },
+            0b10001 => { // VFIRST.M Index of the first set mask bit, or -1
+                let first = (state.vstart..state.vl)
+                    .find(|index| state.active(v.vm, *index) && state.registers.mask_bit(v.rs2, *index));
+                Some(ExecuteResult::default()
+                    .with_write_back(WriteBack { rd: v.vd, value: first.unwrap_or(u64::MAX) })
+                )
+            },
+            _ => None
+        },
+        (0b010000, false) if v.rs2 == 0 && v.vm => { // VMV.S.X Move a scalar to element 0
+            let vlenb = state.registers.vlenb();
+            let offset = v.vd as usize * vlenb;
+            let mut register = state.registers.data[offset..offset + vlenb].to_vec();
+            if state.vstart < state.vl {
+                write_element(&mut register, 0, bytes, truncate(x_rs1));
+            }
+
+            Some(ExecuteResult::default()
+                .with_vector(VectorEffect::Write { offset, bytes: register })
+            )
+        },
+        (0b010100, true) if v.rs1 == 0b10001 && v.rs2 == 0 => { // VID.V Element indices
+            write_elements(v, state, vtype, |index| state.active(v.vm, index).then_some(truncate(index)))
+        },
+        (0b011000..=0b011111, true) if v.vm => { // Mask logical operations
+            let logical: fn(bool, bool) -> bool = match v.func6 {
+                0b011000 => |a, b| a & !b, // VMANDN
+                0b011001 => |a, b| a & b, // VMAND
+                0b011010 => |a, b| a | b, // VMOR
+                0b011011 => |a, b| a ^ b, // VMXOR
+                0b011100 => |a, b| a | !b, // VMORN
+                0b011101 => |a, b| !(a & b), // VMNAND
+                0b011110 => |a, b| !(a | b), // VMNOR
+                _ => |a, b| !(a ^ b), // VMXNOR
+            };
+            write_mask(v, state, |index| Some(logical(state.registers.mask_bit(v.rs2, index), state.registers.mask_bit(v.rs1, index))))
+        },
+        (0b100000..=0b100111, _) => {
+            if !aligned(v.rs2, vtype.group()) || (vv && !aligned(v.rs1, vtype.group())) {
+                return None;
+            }
+
+            let signed = |value: u64| sign_extend(value, sew) as i128;
+            let multiply_divide = |a: u64, b: u64| -> u64 {
+                match v.func6 {
+                    0b100000 => a.checked_div(b).unwrap_or(u64::MAX), // VDIVU
+                    0b100001 => match b { // VDIV, overflow wraps like the scalar DIV
+                        0 => u64::MAX,
+                        _ => (signed(a) / signed(b)) as u64,
+                    },
+                    0b100010 => a.checked_rem(b).unwrap_or(a), // VREMU
+                    0b100011 => match b { // VREM
+                        0 => a,
+                        _ => (signed(a) % signed(b)) as u64,
+                    },
+                    0b100100 => ((a as u128 * b as u128) >> sew) as u64, // VMULHU Multiply high unsigned
+                    0b100101 => a.wrapping_mul(b), // VMUL
+                    0b100110 => ((signed(a) * b as i128) >> sew) as u64, // VMULHSU Multiply high signed-unsigned
+                    _ => ((signed(a) * signed(b)) >> sew) as u64, // VMULH Multiply high
+                }
+            };
+
+            write_elements(v, state, vtype, |index| state.active(v.vm, index).then(|| truncate(multiply_divide(a(index), b(index)))))
+        },
+        _ => None
+    }
+}
+
+/// Maps an element index to its address.
+type ElementAddress<'a> = Box<dyn Fn(u64) -> u64 + 'a>;
+
+/// Unit-stride, strided and indexed loads and stores, plus the mask loads and stores.
+fn execute_memory(v: &VType, x_rs1: u64, x_rs2: u64, state: &VectorState, store: bool) -> Option<ExecuteResult> {
+    let vtype = VectorType::decode(state.vtype)?;
+    let (nf, mew, mop) = (v.func6 >> 3, (v.func6 >> 2) & 1, v.func6 & 0b11);
+    // Segments aren't supported
+    if nf != 0 || mew != 0 {
+        return None;
+    }
+
+    let eew: u64 = match v.func3 {
+        0b000 => 8,
+        0b101 => 16,
+        0b110 => 32,
+        0b111 => 64,
+        _ => return None,
+    };
+    // EMUL = EEW / SEW * LMUL
+    let emul_log2 = vtype.lmul_log2 + eew.trailing_zeros() as i8 - vtype.sew.trailing_zeros() as i8;
+    if !(-3..=3).contains(&emul_log2) {
+        return None;
+    }
+    let emul_group = 1 << emul_log2.max(0);
+
+    let size = |bytes: u64| match bytes {
+        1 => MemSize::Byte,
+        2 => MemSize::Half,
+        4 => MemSize::Word,
+        _ => MemSize::Double,
+    };
+    let address = |address: u64| match state.xlen {
+        Xlen::Rv32 => address & 0xFFFF_FFFF,
+        Xlen::Rv64 => address,
+    };
+
+    // The element width in memory, the destination group and the address of every element
+    let (bytes, group, evl, masked, element_address): (u64, u8, u64, bool, ElementAddress) = match mop {
+        0b00 => match v.rs2 {
+            0b00000 => (eew / 8, emul_group, state.vl, !v.vm, Box::new(move |index| x_rs1.wrapping_add(index * eew / 8))), // VLE, VSE Unit-stride
+            0b01011 if eew == 8 && v.vm => (1, 1, state.vl.div_ceil(8), false, Box::new(move |index| x_rs1.wrapping_add(index))), // VLM, VSM Mask
+            _ => return None,
+        },
+        0b10 => (eew / 8, emul_group, state.vl, !v.vm, Box::new(move |index| x_rs1.wrapping_add(index.wrapping_mul(x_rs2)))), // VLSE, VSSE Strided
+        _ => { // VLUXEI, VLOXEI, VSUXEI, VSOXEI Indexed, the data uses SEW and the offsets EEW
+            if !aligned(v.rs2, emul_group) {
+                return None;
+            }
+            let offsets = state.registers;
+            let index_bytes = eew as usize / 8;
+            (vtype.sew / 8, vtype.group(), state.vl, !v.vm, Box::new(move |index| x_rs1.wrapping_add(offsets.element(v.rs2, index, index_bytes))))
+        },
+    };
+
+    if !aligned(v.vd, group) || (masked && !store && v.vd == 0) {
+        return None;
+    }
+
+    let vlenb = state.registers.vlenb();
+    let elements = (state.vstart..evl)
+        .filter(|index| !masked || state.registers.mask_bit(0, *index))
+        .map(|index| {
+            let offset = v.vd as usize * vlenb + (index * bytes) as usize;
+            ElementAccess {
+                index,
+                address: address(element_address(index)),
+                size: size(bytes),
+                offset,
+                data: if store { read_element(&state.registers.data, offset, bytes as usize) } else { 0 },
+            }
+        })
+        .collect();
+
+    Some(ExecuteResult::default()
+        .with_vector(if store { VectorEffect::Store(elements) } else { VectorEffect::Load(elements) })
+    )
+}
@@ -0,0 +1,349 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{bus::Bus, components::{Memory, MemoryError}, syscall::{guest_slice, guest_slice_mut, read_guest, write_guest, EFAULT}};
+
+/// Where QEMU's virt machine has its first virtio-mmio transport.
+pub const VIRTIO_BASE: u64 = 0x1000_1000;
+pub const VIRTIO_SIZE: u64 = 0x1000;
+/// Its interrupt source on virt's PLIC.
+pub const VIRTIO_IRQ: usize = 1;
+
+pub const SECTOR_SIZE: u64 = 512;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const VERSION: u32 = 2;
+const DEVICE_BLOCK: u32 = 2;
+const VENDOR: u32 = 0x554D_4551; // "QEMU"
+
+// Register offsets
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION_REGISTER: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00C;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0A0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0A4;
+const CONFIG_GENERATION: u64 = 0x0FC;
+const CONFIG: u64 = 0x100;
+
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const FEATURES: u64 = VIRTIO_F_VERSION_1 | VIRTIO_BLK_F_FLUSH;
+
+const STATUS_FEATURES_OK: u32 = 0x08;
+const STATUS_NEEDS_RESET: u32 = 0x40;
+const INTERRUPT_USED_BUFFER: u32 = 1 << 0;
+
+/// The largest queue the driver may set up, there's only queue 0.
+const QUEUE_SIZE: u32 = 256;
+
+const DESC_F_NEXT: u64 = 1;
+const DESC_F_WRITE: u64 = 2;
+
+// Request types and the status byte the device answers with
+const BLK_T_IN: u64 = 0;
+const BLK_T_OUT: u64 = 1;
+const BLK_T_FLUSH: u64 = 4;
+const BLK_T_GET_ID: u64 = 8;
+const BLK_S_OK: u8 = 0;
+const BLK_S_IOERR: u8 = 1;
+const BLK_S_UNSUPP: u8 = 2;
+
+const DEVICE_ID_STRING: &[u8] = b"riscv-sim";
+
+/// What a block device reads and writes, a host file or anything else that can seek.
+pub trait Disk: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Disk for T {}
+
+/// The addresses the driver gave for the split virtqueue.
+#[derive(Debug, Default)]
+struct Queue {
+    num: u32,
+    ready: bool,
+    /// Descriptor table, available (driver) ring and used (device) ring.
+    desc: u64,
+    driver: u64,
+    device: u64,
+    /// The next entry of the available ring to serve.
+    last_avail: u16,
+}
+
+/// A descriptor of a request chain.
+struct Descriptor {
+    address: u64,
+    length: u64,
+    writable: bool,
+}
+
+/// A virtio block device behind a version 2 virtio-mmio transport.
+///
+/// Requests are served from main memory when the guest notifies the queue, so they're done by
+/// the time the notifying store retires. The interrupt line stays up until the guest
+/// acknowledges it.
+pub struct VirtioBlock {
+    disk: Box<dyn Disk>,
+    /// Size in sectors.
+    capacity: u64,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queue: Queue,
+    interrupt_status: u32,
+    status: u32,
+    notified: bool,
+}
+
+impl VirtioBlock {
+    /// A device for `disk`, rounded down to whole sectors.
+    pub fn new(mut disk: impl Disk + 'static) -> io::Result<Self> {
+        let capacity = disk.seek(SeekFrom::End(0))? / SECTOR_SIZE;
+
+        Ok(Self {
+            disk: Box::new(disk),
+            capacity,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queue: Queue::default(),
+            interrupt_status: 0,
+            status: 0,
+            notified: false,
+        })
+    }
+
+    /// The level of the interrupt line, for an interrupt controller to pick up.
+    pub fn interrupt(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    /// Serves the requests the driver made available since the last notification.
+    pub fn process(&mut self, mem: &mut Memory) {
+        if !std::mem::take(&mut self.notified) || !self.queue.ready || self.queue.num == 0 {
+            return;
+        }
+
+        // Rings outside of memory are a driver bug the device can't recover from
+        if self.serve(mem).is_err() {
+            self.status |= STATUS_NEEDS_RESET;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.queue_sel = 0;
+        self.queue = Queue::default();
+        self.interrupt_status = 0;
+        self.status = 0;
+        self.notified = false;
+    }
+
+    fn serve(&mut self, mem: &mut Memory) -> Result<(), i64> {
+        let num = self.queue.num as u64;
+        let available = read_guest(mem, self.queue.driver + 2, 2)? as u16;
+
+        while self.queue.last_avail != available {
+            let slot = self.queue.last_avail as u64 % num;
+            let head = read_guest(mem, self.queue.driver + 4 + 2 * slot, 2)?;
+            let written = self.request(mem, head)?;
+
+            let used = read_guest(mem, self.queue.device + 2, 2)?;
+            let element = self.queue.device + 4 + 8 * (used % num);
+            write_guest(mem, element, 4, head)?;
+            write_guest(mem, element + 4, 4, written)?;
+            write_guest(mem, self.queue.device + 2, 2, (used + 1) & 0xFFFF)?;
+
+            self.queue.last_avail = self.queue.last_avail.wrapping_add(1);
+            self.interrupt_status |= INTERRUPT_USED_BUFFER;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the request whose chain starts at descriptor `head`, giving back how many bytes
+    /// it wrote to guest memory.
+    fn request(&mut self, mem: &mut Memory, head: u64) -> Result<u64, i64> {
+        let num = self.queue.num as u64;
+        let mut chain = Vec::new();
+        let mut index = head;
+
+        loop {
+            // A chain can't be longer than the queue, unless it loops
+            if index >= num || chain.len() as u64 >= num {
+                return Err(EFAULT);
+            }
+
+            let descriptor = self.queue.desc + 16 * index;
+            let flags = read_guest(mem, descriptor + 12, 2)?;
+            chain.push(Descriptor {
+                address: read_guest(mem, descriptor, 8)?,
+                length: read_guest(mem, descriptor + 8, 4)?,
+                writable: flags & DESC_F_WRITE != 0,
+            });
+
+            if flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            index = read_guest(mem, descriptor + 14, 2)?;
+        }
+
+        // The 16 byte header comes first and the status byte last
+        let (Some(header), Some(status)) = (chain.first(), chain.last()) else {
+            return Err(EFAULT);
+        };
+        if chain.len() < 2 || header.writable || header.length < 16 || !status.writable || status.length == 0 {
+            return Err(EFAULT);
+        }
+
+        let kind = read_guest(mem, header.address, 4)?;
+        let sector = read_guest(mem, header.address + 8, 8)?;
+        let status = status.address + status.length - 1;
+        let data = &chain[1..chain.len() - 1];
+
+        let (result, written) = match kind {
+            BLK_T_IN => self.transfer(mem, data, sector, false)?,
+            BLK_T_OUT => self.transfer(mem, data, sector, true)?,
+            BLK_T_FLUSH => (if self.disk.flush().is_ok() { BLK_S_OK } else { BLK_S_IOERR }, 0),
+            BLK_T_GET_ID => match data.first().filter(|buffer| buffer.writable) {
+                Some(buffer) => {
+                    // Up to 20 bytes, NUL terminated only if it's shorter
+                    let length = buffer.length.min(20);
+                    let id = guest_slice_mut(mem, buffer.address, length)?;
+                    id.fill(0);
+                    let end = DEVICE_ID_STRING.len().min(id.len());
+                    id[..end].copy_from_slice(&DEVICE_ID_STRING[..end]);
+                    (BLK_S_OK, length)
+                },
+                None => (BLK_S_IOERR, 0),
+            },
+            _ => (BLK_S_UNSUPP, 0),
+        };
+
+        write_guest(mem, status, 1, result as u64)?;
+        Ok(written + 1)
+    }
+
+    /// Reads sectors into the data buffers or writes them from there.
+    fn transfer(&mut self, mem: &mut Memory, data: &[Descriptor], sector: u64, write: bool) -> Result<(u8, u64), i64> {
+        let length = data.iter().map(|buffer| buffer.length).sum::<u64>();
+        let fits = sector.checked_mul(SECTOR_SIZE)
+            .and_then(|start| start.checked_add(length))
+            .is_some_and(|end| end <= self.capacity * SECTOR_SIZE);
+
+        // Reads need buffers the device can write and writes ones it can't
+        if !fits || data.iter().any(|buffer| buffer.writable == write) {
+            return Ok((BLK_S_IOERR, 0));
+        }
+        if self.disk.seek(SeekFrom::Start(sector * SECTOR_SIZE)).is_err() {
+            return Ok((BLK_S_IOERR, 0));
+        }
+
+        for buffer in data {
+            let done = if write {
+                self.disk.write_all(guest_slice(mem, buffer.address, buffer.length)?)
+            } else {
+                self.disk.read_exact(guest_slice_mut(mem, buffer.address, buffer.length)?)
+            };
+            if done.is_err() {
+                return Ok((BLK_S_IOERR, 0));
+            }
+        }
+
+        Ok((BLK_S_OK, if write { 0 } else { length }))
+    }
+}
+
+fn set_low(register: &mut u64, value: u32) {
+    *register = (*register & !0xFFFF_FFFF) | value as u64;
+}
+
+fn set_high(register: &mut u64, value: u32) {
+    *register = (*register & 0xFFFF_FFFF) | (value as u64) << 32;
+}
+
+impl Bus for VirtioBlock {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, MemoryError> {
+        // The configuration space only holds the capacity, in sectors
+        if offset >= CONFIG {
+            let config = self.capacity.to_le_bytes();
+            let start = (offset - CONFIG) as usize;
+            let bytes = config.get(start..(start + size as usize).min(config.len())).unwrap_or(&[]);
+            return Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64));
+        }
+
+        let queue = self.queue_sel == 0;
+        let value = match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION_REGISTER => VERSION,
+            DEVICE_ID => DEVICE_BLOCK,
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => FEATURES as u32,
+                1 => (FEATURES >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX if queue => QUEUE_SIZE,
+            QUEUE_READY if queue => self.queue.ready as u32,
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            // The configuration never changes
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        };
+
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), MemoryError> {
+        let value = value as u32;
+        let queue = self.queue_sel == 0;
+
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = value,
+            DRIVER_FEATURES => match self.driver_features_sel {
+                0 => set_low(&mut self.driver_features, value),
+                1 => set_high(&mut self.driver_features, value),
+                _ => {},
+            },
+            DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+            QUEUE_SEL => self.queue_sel = value,
+            QUEUE_NUM if queue => self.queue.num = value.min(QUEUE_SIZE),
+            QUEUE_READY if queue => self.queue.ready = value & 1 == 1,
+            QUEUE_NOTIFY if value == 0 => self.notified = true,
+            INTERRUPT_ACK => self.interrupt_status &= !value,
+            // Writing zero resets the device
+            STATUS if value == 0 => self.reset(),
+            // Features the device doesn't offer can't be accepted
+            STATUS if self.driver_features & !FEATURES != 0 => self.status = value & !STATUS_FEATURES_OK,
+            STATUS => self.status = value,
+            QUEUE_DESC_LOW if queue => set_low(&mut self.queue.desc, value),
+            QUEUE_DESC_HIGH if queue => set_high(&mut self.queue.desc, value),
+            QUEUE_DRIVER_LOW if queue => set_low(&mut self.queue.driver, value),
+            QUEUE_DRIVER_HIGH if queue => set_high(&mut self.queue.driver, value),
+            QUEUE_DEVICE_LOW if queue => set_low(&mut self.queue.device, value),
+            QUEUE_DEVICE_HIGH if queue => set_high(&mut self.queue.device, value),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}